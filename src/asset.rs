@@ -0,0 +1,451 @@
+//! Import of glTF 2.0 assets, as separate glTF files or binary GLB files
+//!
+//! Nodes become groups placed by their transformations, meshes become `Mesh` things shared by
+//! all nodes using them, and metallic-roughness materials become `Principled` ones.  Base color
+//! textures become image textures of their materials, decoded from sRGB and wrapped as their
+//! samplers say.
+
+use crate::error::{Error, Result};
+use crate::graph::*;
+use crate::instance::*;
+use crate::mesh::*;
+use crate::principled::Principled;
+use crate::scene::*;
+use crate::texture::{Texture, Textures, Wrap};
+use crate::things::*;
+use gltf::camera::Projection;
+use gltf::image::Format;
+use gltf::texture::WrappingMode;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+/// The distance of the eye behind the screen for orthographic cameras, far enough for rays to
+/// be nearly parallel
+static ORTHOGRAPHIC_DISTANCE: f32 = 1e4;
+
+/// The contents of a glTF file, ready to be rendered
+///
+/// The things of the file are placed below `root`, which can be passed to `render` like any
+/// other group.  The first camera found in the default scene is used as `camera`.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Asset {
+    root: Group,
+    camera: Option<Camera>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Asset {
+    /// Loads a glTF or GLB file, together with the buffers and images it refers to
+    #[staticmethod]
+    #[name = "load"]
+    fn py_load(filename: &str) -> PyResult<Self> {
+        Ok(Asset::load(filename)?)
+    }
+
+    #[getter]
+    fn get_root(&self) -> PyResult<Group> {
+        Ok(self.root())
+    }
+
+    #[getter]
+    fn get_camera(&self) -> PyResult<Option<Camera>> {
+        Ok(self.camera())
+    }
+}
+
+/// Returns the transformation of a node relative to its parent, or None if it is singular
+fn transform(node: &gltf::Node) -> Option<Transform> {
+    // glTF matrices are stored column by column
+    let m = node.transform().matrix();
+    let mut rows = [[0.0; 4]; 3];
+    for (i, row) in rows.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = m[j][i];
+        }
+    }
+    Transform::from_matrix(rows)
+}
+
+/// Returns a camera placed in the world by a transformation
+///
+/// glTF cameras look along their negative z axis, with y pointing up.
+fn camera(camera: &gltf::Camera, placement: &Transform) -> Camera {
+    let eye = placement.point(ORIGIN);
+    let forward = placement.vector(-UNIT_Z).normalized();
+    let right = placement.vector(UNIT_X).normalized();
+    let down = placement.vector(-UNIT_Y).normalized();
+    let (width, height, distance) = match camera.projection() {
+        Projection::Perspective(p) => {
+            let height = 2.0 * (0.5 * p.yfov()).tan();
+            (p.aspect_ratio().unwrap_or(1.0) * height, height, 1.0)
+        }
+        Projection::Orthographic(o) => (2.0 * o.xmag(), 2.0 * o.ymag(), ORTHOGRAPHIC_DISTANCE),
+    };
+    let normal = Ray::new(eye + distance * forward, forward);
+    Camera::oriented(normal, width * right, height * down, distance)
+}
+
+/// Returns the linear value of an sRGB encoded one
+fn linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Returns the wrap mode of a glTF sampler
+fn wrap(mode: WrappingMode) -> Wrap {
+    match mode {
+        WrappingMode::ClampToEdge => Wrap::Clamp,
+        WrappingMode::MirroredRepeat => Wrap::Mirror,
+        WrappingMode::Repeat => Wrap::Repeat,
+    }
+}
+
+/// Returns an sRGB encoded image as a texture wrapped by a sampler, or None for formats other
+/// than 8 or 16 bits per channel
+fn texture(image: &gltf::image::Data, sampler: &gltf::texture::Sampler) -> Result<Option<Texture>> {
+    let (channels, depth) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        _ => return Ok(None),
+    };
+    let channel = |pixel: &[u8], c: usize| {
+        let offset = c.min(channels - 1) * depth;
+        let value = if depth == 1 {
+            pixel[offset] as f32 / 255.0
+        } else {
+            u16::from_le_bytes([pixel[offset], pixel[offset + 1]]) as f32 / 65535.0
+        };
+        linear(value)
+    };
+    // Images with one or two channels are gray, possibly with alpha
+    let blue = if channels < 3 { 0 } else { 2 };
+    let green = if channels < 3 { 0 } else { 1 };
+    let pixels = image
+        .pixels
+        .chunks_exact(channels * depth)
+        .map(|p| Color::new(channel(p, 0), channel(p, green), channel(p, blue)))
+        .collect();
+    let (width, height) = (image.width as usize, image.height as usize);
+    let wrap = (wrap(sampler.wrap_s()), wrap(sampler.wrap_t()));
+    Ok(Some(Texture::pixels(width, height, pixels, wrap)?))
+}
+
+/// Converts a metallic-roughness material, with its base color texture if any
+///
+/// The factors of glTF are linear, as are the colors of textures once decoded.
+fn material(material: &gltf::Material, images: &[gltf::image::Data]) -> Result<Material> {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let [er, eg, eb] = material.emissive_factor();
+    let principled = Principled {
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        emission: Color::new(er, eg, eb),
+        ..Principled::new(Color::new(r, g, b))
+    };
+    let mut result = Material::principled(principled);
+    if let Some(info) = pbr.base_color_texture() {
+        let texture = info.texture();
+        let image = &images[texture.source().index()];
+        if let Some(color) = self::texture(image, &texture.sampler())? {
+            result = result.textured(Textures {
+                color: Some(color),
+                ..Textures::default()
+            });
+        }
+    }
+    Ok(result)
+}
+
+/// Converts the triangles of a glTF mesh into meshes, one for each primitive
+fn meshes(
+    mesh: &gltf::Mesh,
+    buffers: &[gltf::buffer::Data],
+    images: &[gltf::image::Data],
+) -> Result<Vec<SharedThing>> {
+    let mut things: Vec<SharedThing> = Vec::new();
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            continue;
+        }
+        let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+        let vertices: Vec<Point> = reader
+            .read_positions()
+            .ok_or_else(|| Error::Value(format!("mesh {} without positions", mesh.index())))?
+            .map(|p| Point::new(p[0], p[1], p[2]))
+            .collect();
+        let indices: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..vertices.len()).collect(),
+        };
+        let faces = indices.chunks_exact(3).map(|f| f.to_vec()).collect();
+        let normals = reader
+            .read_normals()
+            .map(|n| n.map(|n| Point::new(n[0], n[1], n[2])).collect());
+        let gltf_material = primitive.material();
+        // The coordinates the base color texture is looked up at, if any
+        let set = gltf_material
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .map_or(0, |info| info.tex_coord());
+        // glTF puts the origin of surface coordinates at the top left of images
+        let uvs: Option<Vec<(f32, f32)>> = reader
+            .read_tex_coords(set)
+            .map(|t| t.into_f32().map(|t| (t[0], 1.0 - t[1])).collect());
+
+        let mut material = material(&gltf_material, images)?;
+        let mut colors: Option<Vec<Color>> = reader.read_colors(0).map(|c| {
+            c.into_rgb_f32()
+                .map(|c| Color::new(c[0], c[1], c[2]))
+                .collect()
+        });
+        // Vertex colors replace the color of the material, so apply its factor to them
+        if let Some(colors) = colors.as_mut() {
+            for c in colors.iter_mut() {
+                *c = *c * material.color;
+            }
+            material.color = Color::white();
+        }
+        let mesh = Mesh::validated(vertices, faces, material, uvs, normals, colors)?;
+        things.push(std::sync::Arc::new(mesh));
+    }
+    Ok(things)
+}
+
+/// Walks the node hierarchy, creating groups and finding the camera
+struct Importer<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    /// The converted meshes by their index in the file, shared by all nodes using them
+    meshes: HashMap<usize, Vec<SharedThing>>,
+    camera: Option<Camera>,
+}
+
+impl<'a> Importer<'a> {
+    fn node(&mut self, node: &gltf::Node, parent: &Transform) -> Result<Option<Group>> {
+        let local = match transform(node) {
+            Some(local) => local,
+            None => return Ok(None), // scaled to nothing
+        };
+        let placement = local.then(*parent);
+        let name = node
+            .name()
+            .map_or_else(|| format!("node {}", node.index()), String::from);
+        let mut group = Group::new(name, Some(local), None);
+        if let Some(mesh) = node.mesh() {
+            if !self.meshes.contains_key(&mesh.index()) {
+                let things = meshes(&mesh, self.buffers, self.images)?;
+                self.meshes.insert(mesh.index(), things);
+            }
+            for thing in &self.meshes[&mesh.index()] {
+                group.add_thing(thing.clone());
+            }
+        }
+        if let (Some(c), None) = (node.camera(), &self.camera) {
+            self.camera = Some(camera(&c, &placement));
+        }
+        for child in node.children() {
+            if let Some(child) = self.node(&child, &placement)? {
+                group.add_group(child)?;
+            }
+        }
+        Ok(Some(group))
+    }
+}
+
+impl Asset {
+    /// Loads a glTF or GLB file, together with the buffers and images it refers to
+    pub fn load(filename: &str) -> Result<Self> {
+        let (document, buffers, images) =
+            gltf::import(filename).map_err(|e| Error::Io(format!("{}: {}", filename, e)))?;
+        Asset::from_document(&document, &buffers, &images)
+    }
+
+    /// Returns the group holding everything in the file
+    pub fn root(&self) -> Group {
+        self.root.clone()
+    }
+
+    /// Returns the first camera of the file, if any
+    pub fn camera(&self) -> Option<Camera> {
+        self.camera.clone()
+    }
+
+    /// Converts the default scene of a document, or its first scene if there is no default
+    pub fn from_document(
+        document: &gltf::Document,
+        buffers: &[gltf::buffer::Data],
+        images: &[gltf::image::Data],
+    ) -> Result<Self> {
+        let mut importer = Importer {
+            buffers,
+            images,
+            meshes: HashMap::new(),
+            camera: None,
+        };
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| Error::Value("no scene in file".into()))?;
+        let name = scene.name().unwrap_or("scene").to_string();
+        let mut root = Group::new(name, None, None);
+        for node in scene.nodes() {
+            if let Some(group) = importer.node(&node, &Transform::identity())? {
+                root.add_group(group)?;
+            }
+        }
+        Ok(Asset {
+            root,
+            camera: importer.camera,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::Shading;
+    use rand::SeedableRng;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    /// Returns a GLB file of a JSON document and a binary buffer
+    fn glb(json: &str, mut bin: Vec<u8>) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        let mut glb = b"glTF".to_vec();
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+        glb
+    }
+
+    /// Returns the little-endian bytes of floats
+    fn bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    /// Returns a GLB file with a red triangle moved up by one, used twice, and a camera
+    fn triangles() -> Vec<u8> {
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "scene": 0,
+            "scenes": [{"nodes": [0, 1]}],
+            "nodes": [
+                {"name": "triangles", "translation": [0, 1, 0], "children": [2, 3]},
+                {"camera": 0, "translation": [0, 0, 5]},
+                {"mesh": 0},
+                {"mesh": 0, "translation": [5, 0, 0]}
+            ],
+            "cameras": [{"type": "perspective", "perspective": {"yfov": 1.0, "znear": 0.1}}],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}],
+            "materials": [{"pbrMetallicRoughness": {
+                "baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0.0
+            }}],
+            "accessors": [{
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            }],
+            "bufferViews": [{"buffer": 0, "byteLength": 36}],
+            "buffers": [{"byteLength": 36}]
+        }"#;
+        glb(json, bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]))
+    }
+
+    #[test]
+    fn gltf_imports() {
+        let (document, buffers, images) = gltf::import_slice(triangles()).unwrap();
+        let asset = Asset::from_document(&document, &buffers, &images).unwrap();
+        let things = asset.root.flatten();
+        assert_eq!(things.len(), 2);
+        assert!(asset.root.find("triangles").is_some());
+
+        let hits: Vec<Hit> = things
+            .iter()
+            .filter_map(|t| t.hit_by(&Ray::new(Point::new(0.25, 1.25, 5.0), -UNIT_Z)))
+            .collect();
+        assert_eq!(hits.len(), 1);
+        assert!((hits[0].distance - 5.0).abs() < 1e-5);
+        assert_eq!(hits[0].material.color, Color::red());
+        assert!(hits[0].material.bsdf.is_some());
+
+        let camera = asset.camera.unwrap();
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let center = camera.view(0.5, 0.5, &mut rng);
+        assert!((center.direction - -UNIT_Z).norm() < 1e-6);
+        // The top of the image looks up
+        assert!(camera.view(0.5, 0.0, &mut rng).direction.y > 0.0);
+    }
+
+    #[test]
+    fn base_color_textures_apply() {
+        // red on the left and mid gray on the right, clamped across u
+        let mut png = Vec::new();
+        let mut image = image::RgbImage::new(2, 1);
+        image.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+        image.put_pixel(1, 0, image::Rgb([128, 128, 128]));
+        image
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let mut bin = bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        bin.extend(bytes(&[0.0, 0.0, 1.0, 0.0, 0.0, 1.0]));
+        bin.extend(&png);
+        let json = format!(
+            r#"{{
+            "asset": {{"version": "2.0"}},
+            "scenes": [{{"nodes": [0]}}],
+            "nodes": [{{"mesh": 0}}],
+            "meshes": [{{"primitives": [{{
+                "attributes": {{"POSITION": 0, "TEXCOORD_0": 1}}, "material": 0
+            }}]}}],
+            "materials": [{{"pbrMetallicRoughness": {{"baseColorTexture": {{"index": 0}}}}}}],
+            "textures": [{{"source": 0, "sampler": 0}}],
+            "samplers": [{{"wrapS": 33071, "wrapT": 10497}}],
+            "images": [{{"bufferView": 2, "mimeType": "image/png"}}],
+            "accessors": [
+                {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [0, 0, 0], "max": [1, 1, 0]}},
+                {{"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2"}}
+            ],
+            "bufferViews": [
+                {{"buffer": 0, "byteLength": 36}},
+                {{"buffer": 0, "byteOffset": 36, "byteLength": 24}},
+                {{"buffer": 0, "byteOffset": 60, "byteLength": {}}}
+            ],
+            "buffers": [{{"byteLength": {}}}]
+        }}"#,
+            png.len(),
+            60 + png.len()
+        );
+        let (document, buffers, images) = gltf::import_slice(glb(&json, bin)).unwrap();
+        let asset = Asset::from_document(&document, &buffers, &images).unwrap();
+        let things = asset.root.flatten();
+        let color = |x: f32| {
+            let ray = Ray::new(Point::new(x, 0.1, 1.0), -UNIT_Z);
+            let hit = things[0].hit_by(&ray).unwrap();
+            Shading::at(&ray, &hit).color
+        };
+        assert_eq!(color(0.1), Color::red());
+        // decoded from sRGB, and not blended with the red pixel across the edge
+        let gray = color(0.8);
+        assert!((gray.r - 0.2158).abs() < 1e-3, "{:?}", gray);
+        assert_eq!((gray.r, gray.g), (gray.g, gray.b));
+    }
+}
@@ -0,0 +1,334 @@
+//! How surfaces scatter light, as seen by the integrator
+//!
+//! A `Bsdf` samples directions light arrives from and gives its value and sampling density for
+//! any pair of directions, so that new kinds of materials need no changes to `Scene`.  Plain
+//! materials scatter light by the weighted parts of `Material`; materials with a BSDF of their
+//! own defer to it.
+
+use crate::microfacet::{self, Frame, Microfacet};
+use crate::texture::Texture;
+use crate::things::*;
+use rand::prelude::*;
+use std::f32::consts::PI;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Where a ray hit a surface, with what is needed to shade it
+#[derive(Clone, Copy, Debug)]
+pub struct Shading {
+    /// The point of impact
+    pub point: Point,
+    /// The outward surface normal
+    pub normal: Point,
+    /// The color of the material there, which vertex colors vary
+    pub color: Color,
+    /// Coordinates on the surface
+    pub uv: (f32, f32),
+    /// The direction of a fiber, for surfaces scattering light like hair
+    pub tangent: Option<Point>,
+    /// The roughness there, replacing that of the material where it is textured
+    pub roughness: Option<f32>,
+    /// The tint of the light emitted there
+    pub emission: Color,
+    /// The share of light stopped by the surface rather than passing straight through it
+    pub opacity: f32,
+}
+
+impl Shading {
+    /// Returns what a ray sees where it hits a surface, with the textures of its material
+    /// looked up there
+    pub fn at(ray: &Ray, hit: &Hit) -> Self {
+        let point = ray.at(hit.distance);
+        let (u, v) = hit.uv;
+        let textures = &hit.material.textures;
+        let color = |texture: &Option<Texture>| {
+            texture
+                .as_ref()
+                .map_or(Color::white(), |texture| texture.color_at(point, u, v))
+        };
+        let value = |texture: &Option<Texture>| {
+            texture
+                .as_ref()
+                .map(|texture| texture.value_at(point, u, v))
+        };
+        Shading {
+            point,
+            normal: hit.normal,
+            color: hit.material.color * color(&textures.color),
+            uv: hit.uv,
+            tangent: hit.tangent,
+            roughness: value(&textures.roughness),
+            emission: color(&textures.emission),
+            opacity: value(&textures.opacity).unwrap_or(1.0),
+        }
+    }
+}
+
+/// A direction sampled by a BSDF
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// The direction light arrives from, pointing away from the surface
+    pub direction: Point,
+    /// The value times the cosine to the normal over the sampling density
+    pub weight: Color,
+    /// Whether the direction is the only one possible, as off a mirror, where `eval` and `pdf`
+    /// are zero
+    pub specular: bool,
+}
+
+/// A bidirectional scattering distribution function
+///
+/// All directions point away from the surface: `wo` to where light leaves to, along the ray
+/// back to the camera, and `wi` to where it arrives from.
+pub trait Bsdf: Debug {
+    /// Samples a direction light leaving towards `wo` arrives from, None if it is absorbed
+    fn sample(&self, at: &Shading, wo: Point, rng: &mut dyn RngCore) -> Option<Sample>;
+
+    /// Returns the share of light arriving from `wi` that leaves towards `wo`, per solid angle
+    fn eval(&self, at: &Shading, wo: Point, wi: Point) -> Color;
+
+    /// Returns the density `sample` picks `wi` with, per solid angle
+    fn pdf(&self, at: &Shading, wo: Point, wi: Point) -> f32;
+
+    /// Returns the light emitted towards `wo`, before the tint of `Shading::emission`
+    fn emission(&self, _at: &Shading, _wo: Point) -> Color {
+        Color::black()
+    }
+}
+
+pub type SharedBsdf = Arc<dyn Bsdf + Send + Sync>;
+
+/// The refraction of plain materials
+static GLASS: Microfacet = Microfacet::Dielectric {
+    ior: 1.5,
+    roughness: 0.0,
+};
+
+impl Material {
+    /// Returns the BSDF the material scatters light by
+    pub fn bsdf(&self) -> &dyn Bsdf {
+        match &self.bsdf {
+            Some(bsdf) => bsdf.as_ref(),
+            None => self,
+        }
+    }
+
+    /// The hardness of the material where it is hit
+    fn hardness(&self, at: &Shading) -> f32 {
+        at.roughness.unwrap_or(self.hardness)
+    }
+
+    /// The total weight of the parts of the material, and those of the specular and diffuse
+    /// parts, picked in proportion to their weights
+    fn parts(&self) -> (f32, f32, f32) {
+        let total = self.specularity + self.diffusion + self.refraction;
+        (total, self.specularity / total, self.diffusion / total)
+    }
+}
+
+/// Plain materials scatter light by the sum of their parts: a specular reflection with the
+/// hardness as roughness, a Lambertian one and refraction into glass
+impl Bsdf for Material {
+    fn sample(&self, at: &Shading, wo: Point, rng: &mut dyn RngCore) -> Option<Sample> {
+        let (total, specular, diffuse) = self.parts();
+        if total <= 0.0 {
+            return None;
+        }
+        let pick = rng.gen::<f32>();
+        let direction = if pick < specular {
+            let hardness = self.hardness(at);
+            let (direction, weight) = microfacet::glossy(at.normal, -wo, hardness, rng)?;
+            if microfacet::smooth(hardness) {
+                return Some(Sample {
+                    direction,
+                    weight: total * weight * Color::white(),
+                    specular: true,
+                });
+            }
+            direction
+        } else if pick < specular + diffuse {
+            Frame::facing(at.normal, -wo).world(cosine_weighted(rng))
+        } else {
+            let (direction, weight) = GLASS.scatter(at.normal, -wo, rng)?;
+            return Some(Sample {
+                direction,
+                weight: total * weight,
+                specular: true,
+            });
+        };
+        // weighted by all parts the direction could have been sampled from
+        let pdf = self.pdf(at, wo, direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(Sample {
+            direction,
+            weight: (facing(at.normal, wo) * direction) / pdf * self.eval(at, wo, direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, at: &Shading, wo: Point, wi: Point) -> Color {
+        let normal = facing(at.normal, wo);
+        let cos = normal * wi;
+        if cos <= 0.0 {
+            return Color::black();
+        }
+        let hardness = self.hardness(at);
+        let glossy = self.specularity * microfacet::glossy_value(normal, wo, wi, hardness);
+        glossy * Color::white() + self.diffusion / PI * at.color
+    }
+
+    fn pdf(&self, at: &Shading, wo: Point, wi: Point) -> f32 {
+        let normal = facing(at.normal, wo);
+        let (total, specular, diffuse) = self.parts();
+        let cos = normal * wi;
+        if total <= 0.0 || cos <= 0.0 {
+            return 0.0;
+        }
+        let hardness = self.hardness(at);
+        specular * microfacet::glossy_pdf(normal, wo, wi, hardness) + diffuse * cos / PI
+    }
+
+    fn emission(&self, at: &Shading, _wo: Point) -> Color {
+        self.emittance * at.color
+    }
+}
+
+/// Samples a local direction about the z axis with a density of the cosine to it over pi
+pub(crate) fn cosine_weighted(rng: &mut dyn RngCore) -> Point {
+    let r = rng.gen::<f32>().sqrt();
+    let phi = 2.0 * PI * rng.gen::<f32>();
+    Point::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt())
+}
+
+/// Returns the normal flipped to the side of a direction
+fn facing(normal: Point, wo: Point) -> Point {
+    if normal * wo < 0.0 {
+        -normal
+    } else {
+        normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hair::Hair;
+    use crate::texture::Textures;
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    fn shading(normal: Point, color: Color) -> Shading {
+        Shading {
+            point: ORIGIN,
+            normal,
+            color,
+            uv: (0.0, 0.0),
+            tangent: None,
+            roughness: None,
+            emission: Color::white(),
+            opacity: 1.0,
+        }
+    }
+
+    #[test]
+    fn bsdfs_agree_with_their_samples() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let wo = Point::new(0.4, -0.2, 1.0).normalized();
+        let bsdfs: Vec<SharedBsdf> = vec![
+            Arc::new(Material::new(0.5, 0.3, 0.5, 0.0, 0.0, Color::red())),
+            Arc::new(Hair::new(&Material::new(
+                0.3,
+                0.4,
+                0.3,
+                0.3,
+                0.0,
+                Color::red(),
+            ))),
+            Arc::new(Microfacet::metal("copper", 0.4).unwrap()),
+            Arc::new(Microfacet::Dielectric {
+                ior: 1.3,
+                roughness: 0.3,
+            }),
+        ];
+        for bsdf in bsdfs {
+            let at = shading(UNIT_Z, Color::red());
+            for _ in 0..200 {
+                let sample = match bsdf.sample(&at, wo, &mut rng) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                assert!(!sample.specular);
+                let pdf = bsdf.pdf(&at, wo, sample.direction);
+                let cos = (sample.direction * UNIT_Z).abs();
+                let expected = cos / pdf * bsdf.eval(&at, wo, sample.direction);
+                let sum = |c: Color| c.r.abs() + c.g.abs() + c.b.abs();
+                let error = sum(expected - sample.weight);
+                assert!(error < 1e-3 * (1.0 + sum(sample.weight)), "{:?}", bsdf);
+            }
+        }
+    }
+
+    #[test]
+    fn plain_materials_are_reciprocal() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(2);
+        let material = Material::new(0.5, 0.4, 0.5, 0.0, 0.0, Color::white());
+        let at = shading(UNIT_Z, Color::white());
+        let n = 20000;
+        let mut density = 0.0;
+        for _ in 0..n {
+            let (wo, wi) = (UNIT_Z.randomize(&mut rng), UNIT_Z.randomize(&mut rng));
+            let (there, back) = (material.eval(&at, wo, wi), material.eval(&at, wi, wo));
+            assert!((there.g - back.g).abs() < 1e-4 * (1.0 + there.g));
+            density += material.pdf(&at, wo, wi);
+        }
+        // the density over the hemisphere integrates to one
+        let total = 2.0 * PI * density / n as f32;
+        assert!((total - 1.0).abs() < 0.05, "{}", total);
+    }
+
+    #[test]
+    fn plain_materials_scatter() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let wo = Point::new(1.0, 0.0, 1.0).normalized();
+        let mirror = Material::new(1.0, 0.0, 0.0, 0.0, 0.0, Color::red());
+        let at = shading(UNIT_Z, Color::red());
+        let sample = mirror.sample(&at, wo, &mut rng).unwrap();
+        assert!(sample.specular);
+        assert!((sample.direction - Point::new(-1.0, 0.0, 1.0).normalized()).norm() < 1e-5);
+        assert_eq!(sample.weight, Color::white());
+
+        // diffuse light leaves on the side it arrives from, tinted by the color at the hit
+        let matte = Material::new(0.0, 0.0, 0.5, 0.0, 0.0, Color::white());
+        let at = shading(-UNIT_Z, Color::green());
+        for _ in 0..10 {
+            let sample = matte.sample(&at, wo, &mut rng).unwrap();
+            assert!(sample.direction.z > 0.0);
+            assert!((sample.weight.g - 0.5).abs() < 1e-5);
+            assert_eq!((sample.weight.r, sample.weight.b), (0.0, 0.0));
+        }
+
+        let light = Material::light(Color::white());
+        assert!(light.sample(&at, wo, &mut rng).is_none());
+        assert_eq!(light.emission(&at, wo), Color::green());
+        assert_eq!(light.pdf(&at, wo, UNIT_Z), 0.0);
+    }
+
+    #[test]
+    fn textures_vary_shading() {
+        let material = Material::new(0.5, 0.0, 0.5, 0.0, 1.0, Color::red()).textured(Textures {
+            color: Some(Texture::constant(0.5)),
+            roughness: Some(Texture::constant(0.3)),
+            opacity: Some(Texture::constant(0.25)),
+            ..Textures::default()
+        });
+        let ray = Ray::new(ORIGIN, UNIT_Z);
+        let at = Shading::at(&ray, &Hit::new(2.0, -UNIT_Z, material).with_uv(0.2, 0.4));
+        assert_eq!(at.point, 2.0 * UNIT_Z);
+        assert_eq!(at.color, Color::new(0.5, 0.0, 0.0));
+        assert_eq!(at.roughness, Some(0.3));
+        assert_eq!(at.emission, Color::white());
+        assert_eq!(at.opacity, 0.25);
+    }
+}
@@ -0,0 +1,357 @@
+use crate::things::*;
+
+/// The maximum number of things kept in a single leaf of the hierarchy
+static LEAF_SIZE: usize = 4;
+
+/// An axis aligned box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Bounds {
+    pub fn new(min: Point, max: Point) -> Bounds {
+        Bounds { min, max }
+    }
+
+    /// Returns bounds that contain nothing, to be grown with `union` or `grow`
+    pub fn empty() -> Bounds {
+        Bounds::new(
+            Point::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            Point::new(-f32::INFINITY, -f32::INFINITY, -f32::INFINITY),
+        )
+    }
+
+    /// Returns bounds that contain everything, for things without a finite extent
+    pub fn infinite() -> Bounds {
+        Bounds::new(
+            Point::new(-f32::INFINITY, -f32::INFINITY, -f32::INFINITY),
+            Point::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        )
+    }
+
+    /// Returns the smallest bounds containing all points
+    pub fn around(points: &[Point]) -> Bounds {
+        points.iter().fold(Bounds::empty(), |b, p| b.grow(*p))
+    }
+
+    /// Returns bounds extended to contain a point
+    pub fn grow(self, p: Point) -> Bounds {
+        Bounds::new(
+            Point::new(
+                self.min.x.min(p.x),
+                self.min.y.min(p.y),
+                self.min.z.min(p.z),
+            ),
+            Point::new(
+                self.max.x.max(p.x),
+                self.max.y.max(p.y),
+                self.max.z.max(p.z),
+            ),
+        )
+    }
+
+    /// Returns bounds containing both bounds
+    pub fn union(self, other: Bounds) -> Bounds {
+        self.grow(other.min).grow(other.max)
+    }
+
+    /// Returns the bounds contained in both bounds, empty if they do not overlap
+    pub fn intersection(self, other: Bounds) -> Bounds {
+        let min = Point::new(
+            self.min.x.max(other.min.x),
+            self.min.y.max(other.min.y),
+            self.min.z.max(other.min.z),
+        );
+        let max = Point::new(
+            self.max.x.min(other.max.x),
+            self.max.y.min(other.max.y),
+            self.max.z.min(other.max.z),
+        );
+        if (0..3).any(|i| min[i] > max[i]) {
+            Bounds::empty()
+        } else {
+            Bounds::new(min, max)
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        (0..3).all(|i| self.min[i].is_finite() && self.max[i].is_finite())
+    }
+
+    pub fn center(&self) -> Point {
+        0.5 * (self.min + self.max)
+    }
+
+    /// Returns the eight corners of the box
+    pub fn corners(&self) -> [Point; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point::new(a.x, a.y, a.z),
+            Point::new(b.x, a.y, a.z),
+            Point::new(a.x, b.y, a.z),
+            Point::new(b.x, b.y, a.z),
+            Point::new(a.x, a.y, b.z),
+            Point::new(b.x, a.y, b.z),
+            Point::new(a.x, b.y, b.z),
+            Point::new(b.x, b.y, b.z),
+        ]
+    }
+
+    /// Returns the axis along which the bounds are the widest
+    pub fn widest_axis(&self) -> usize {
+        let size = self.max - self.min;
+        if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Returns the distances at which a ray enters and leaves the box
+    ///
+    /// Uses the slab method, only considering distances between zero and `limit`.
+    pub fn entry(&self, ray: &Ray, limit: f32) -> Option<(f32, f32)> {
+        let mut near = 0.0f32;
+        let mut far = limit;
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.base[axis]) * inverse;
+            let mut t1 = (self.max[axis] - ray.base[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN, from a ray in the plane of a face, is ignored by min and max
+            near = near.max(t0);
+            far = far.min(t1);
+            if near > far {
+                return None;
+            }
+        }
+        Some((near, far))
+    }
+}
+
+/// Something taking up space, to be found through a `Hierarchy`
+pub trait Bounded {
+    fn bounds(&self) -> Bounds;
+}
+
+impl Bounded for Bounds {
+    fn bounds(&self) -> Bounds {
+        *self
+    }
+}
+
+/// A node of the hierarchy
+///
+/// Leaves refer to `count` consecutive indices starting at `start`.  Branches have a `count` of
+/// zero, their first child follows directly, and the second child is found at `start`.
+#[derive(Clone, Debug)]
+struct Node {
+    bounds: Bounds,
+    start: usize,
+    count: usize,
+}
+
+/// A bounding volume hierarchy over items kept elsewhere, referring to them by their index
+///
+/// Primitives made of many small parts, such as blobs or points, keep them in a plain array
+/// and find those a ray may hit through a hierarchy, which is far smaller than one over
+/// separate things.
+#[derive(Clone, Debug)]
+pub struct Hierarchy {
+    nodes: Vec<Node>,
+    /// The indices of the items, in the order leaves refer to them
+    indices: Vec<usize>,
+}
+
+impl Hierarchy {
+    /// Builds the hierarchy over items with finite bounds, with up to `leaf_size` in a leaf
+    pub fn new<T: Bounded>(items: &[T], leaf_size: usize) -> Hierarchy {
+        let bounds: Vec<Bounds> = items.iter().map(|item| item.bounds()).collect();
+        let mut indices: Vec<usize> = (0..items.len()).collect();
+        let mut nodes = Vec::new();
+        if !indices.is_empty() {
+            Hierarchy::build(&bounds, &mut indices, 0, leaf_size.max(1), &mut nodes);
+        }
+        Hierarchy { nodes, indices }
+    }
+
+    /// Recursively splits the items at the median along the widest axis of their centers
+    fn build(
+        bounds: &[Bounds],
+        indices: &mut [usize],
+        offset: usize,
+        leaf_size: usize,
+        nodes: &mut Vec<Node>,
+    ) {
+        let total = indices
+            .iter()
+            .fold(Bounds::empty(), |b, &i| b.union(bounds[i]));
+        let index = nodes.len();
+        nodes.push(Node {
+            bounds: total,
+            start: offset,
+            count: indices.len(),
+        });
+        if indices.len() <= leaf_size {
+            return;
+        }
+        let axis = indices
+            .iter()
+            .fold(Bounds::empty(), |b, &i| b.grow(bounds[i].center()))
+            .widest_axis();
+        let half = indices.len() / 2;
+        indices.select_nth_unstable_by(half, |&a, &b| {
+            bounds[a].center()[axis]
+                .partial_cmp(&bounds[b].center()[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let (left, right) = indices.split_at_mut(half);
+        Hierarchy::build(bounds, left, offset, leaf_size, nodes);
+        nodes[index].start = nodes.len();
+        nodes[index].count = 0;
+        Hierarchy::build(bounds, right, offset + half, leaf_size, nodes);
+    }
+
+    /// Returns the bounds of all items, empty if there are none
+    pub fn bounds(&self) -> Bounds {
+        self.nodes.first().map_or(Bounds::empty(), |n| n.bounds)
+    }
+
+    /// Calls `visit` with the index of every item in leaves the ray enters before `limit`
+    ///
+    /// `visit` returns the distance of a hit on the item, if any, below which the remaining
+    /// leaves must be entered to be visited, so that finding the closest hit skips items
+    /// behind it.
+    pub fn visit(&self, ray: &Ray, mut limit: f32, mut visit: impl FnMut(usize) -> Option<f32>) {
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds.entry(ray, limit).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(index + 1);
+                continue;
+            }
+            for &item in &self.indices[node.start..node.start + node.count] {
+                if let Some(distance) = visit(item) {
+                    limit = limit.min(distance);
+                }
+            }
+        }
+    }
+}
+
+/// A bounding volume hierarchy to speed up finding the things hit by a ray
+///
+/// Things without finite bounds are kept at the end of the list and tested against every ray.
+pub struct Bvh {
+    things: Vec<SharedThing>,
+    hierarchy: Hierarchy,
+    bounded: usize,
+}
+
+impl Bvh {
+    pub fn new(things: Vec<SharedThing>) -> Bvh {
+        let mut entries: Vec<(Bounds, SharedThing)> =
+            things.into_iter().map(|t| (t.bounds(), t)).collect();
+        entries.sort_by_key(|(b, _)| !b.is_finite());
+        let bounded = entries.iter().filter(|(b, _)| b.is_finite()).count();
+        let bounds: Vec<Bounds> = entries[..bounded].iter().map(|(b, _)| *b).collect();
+        Bvh {
+            things: entries.into_iter().map(|(_, t)| t).collect(),
+            hierarchy: Hierarchy::new(&bounds, LEAF_SIZE),
+            bounded,
+        }
+    }
+}
+
+impl Thing for Bvh {
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest = ray
+            .intersect(&self.things[self.bounded..], None)
+            .map(|(hit, _)| hit);
+        let limit = closest.as_ref().map_or(f32::INFINITY, |h| h.distance);
+        self.hierarchy.visit(ray, limit, |i| {
+            let hit = self.things[i].hit_by(ray)?;
+            if matches!(closest, Some(ref c) if c.distance < hit.distance) {
+                return None;
+            }
+            let distance = hit.distance;
+            closest = Some(hit);
+            Some(distance)
+        });
+        closest
+    }
+
+    fn bounds(&self) -> Bounds {
+        if self.bounded < self.things.len() {
+            Bounds::infinite()
+        } else {
+            self.hierarchy.bounds()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn bounds_entry() {
+        let b = Bounds::new(Point::new(1.0, -1.0, -1.0), Point::new(2.0, 1.0, 1.0));
+        assert_eq!(
+            b.entry(&Ray::new(ORIGIN, UNIT_X), f32::INFINITY),
+            Some((1.0, 2.0))
+        );
+        assert_eq!(b.entry(&Ray::new(ORIGIN, UNIT_X), 0.5), None);
+        assert_eq!(b.entry(&Ray::new(ORIGIN, -UNIT_X), f32::INFINITY), None);
+        assert_eq!(b.entry(&Ray::new(ORIGIN, UNIT_Y), f32::INFINITY), None);
+
+        let flat = Bounds::new(Point::new(1.0, -1.0, -1.0), Point::new(1.0, 1.0, 1.0));
+        assert_eq!(
+            flat.entry(&Ray::new(ORIGIN, UNIT_X), f32::INFINITY),
+            Some((1.0, 1.0))
+        );
+        assert!(Bounds::infinite()
+            .entry(&Ray::new(ORIGIN, UNIT_Z), f32::INFINITY)
+            .is_some());
+    }
+
+    #[test]
+    fn bvh_finds_closest() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let mut things: Vec<SharedThing> = (0..20)
+            .map(|i| {
+                let center = Point::new(i as f32, (i % 3) as f32, 5.0 - i as f32);
+                Arc::new(Sphere::new(center, 0.4, m.clone())) as SharedThing
+            })
+            .collect();
+        let front = Rhomboid::new(
+            Point::new(-10.0, -10.0, 8.0),
+            20.0 * UNIT_X,
+            20.0 * UNIT_Y,
+            m,
+        );
+        things.push(Arc::new(front));
+        let bvh = Bvh::new(things.clone());
+
+        for i in 0..20 {
+            let r = Ray::new(Point::new(i as f32, 0.0, -20.0), UNIT_Z);
+            let expected = r.intersect(&things, None).map(|(h, _)| h.distance);
+            assert_eq!(bvh.hit_by(&r).map(|h| h.distance), expected);
+        }
+    }
+}
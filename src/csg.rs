@@ -0,0 +1,269 @@
+use crate::bvh::*;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+/// The most surfaces considered along a ray for each side of an operation
+static MAX_CROSSINGS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl Operation {
+    /// Returns if a point is inside the combination, given if it is inside either solid
+    fn inside(self, a: bool, b: bool) -> bool {
+        match self {
+            Operation::Union => a || b,
+            Operation::Intersection => a && b,
+            Operation::Difference => a && !b,
+        }
+    }
+}
+
+/// A place where a ray enters or leaves a solid
+struct Crossing {
+    hit: Hit,
+    entering: bool,
+}
+
+/// Returns if a ray starts inside a closed thing, and all places where it crosses its surface
+///
+/// The surface is found by repeatedly casting the ray onwards from the last hit.  As the ray
+/// ends up outside a closed thing, it starts inside if it crosses the surface an odd number of
+/// times, and crossings alternate between entering and leaving from there.  Normals do not
+/// tell, as triangles turn them towards the ray, so they are made to point out of the thing.
+/// Only when there are too many crossings to count is the first normal trusted instead.
+fn crossings(thing: &dyn Thing, ray: &Ray) -> (bool, Vec<Crossing>) {
+    let mut hits: Vec<Hit> = Vec::new();
+    let mut current = *ray;
+    let mut offset = 0.0;
+    while hits.len() < MAX_CROSSINGS {
+        let hit = match thing.hit_by(&current) {
+            Some(hit) => hit,
+            None => break,
+        };
+        hits.push(Hit {
+            distance: offset + hit.distance,
+            ..hit
+        });
+        current = current.spawn(current.at(hit.distance), ray.direction);
+        offset += hit.distance + BIAS;
+    }
+    let inside = if hits.len() < MAX_CROSSINGS {
+        hits.len() % 2 == 1
+    } else {
+        hits[0].normal * ray.direction > 0.0
+    };
+    let result = hits
+        .into_iter()
+        .enumerate()
+        .map(|(k, mut hit)| {
+            let entering = (k % 2 == 0) != inside;
+            if (hit.normal * ray.direction < 0.0) != entering {
+                hit.normal = -hit.normal;
+            }
+            Crossing { hit, entering }
+        })
+        .collect();
+    (inside, result)
+}
+
+/// A solid combined from two closed things by constructive solid geometry
+///
+/// Surfaces keep the materials of the things they belong to, so glass parts can be shaped by
+/// cutting or intersecting them with other solids.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Csg {
+    operation: Operation,
+    a: SharedThing,
+    b: SharedThing,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Csg {
+    /// Returns the solid inside either of two things
+    #[staticmethod]
+    #[name = "union"]
+    fn py_union(a: &PyAny, b: &PyAny) -> PyResult<Self> {
+        Ok(Csg::union(to_thing(a)?, to_thing(b)?))
+    }
+
+    /// Returns the solid inside both of two things
+    #[staticmethod]
+    #[name = "intersection"]
+    fn py_intersection(a: &PyAny, b: &PyAny) -> PyResult<Self> {
+        Ok(Csg::intersection(to_thing(a)?, to_thing(b)?))
+    }
+
+    /// Returns the solid inside the first thing but outside of the second
+    #[staticmethod]
+    #[name = "difference"]
+    fn py_difference(a: &PyAny, b: &PyAny) -> PyResult<Self> {
+        Ok(Csg::difference(to_thing(a)?, to_thing(b)?))
+    }
+}
+
+impl Csg {
+    /// Returns the solid inside either of two things
+    pub fn union(a: SharedThing, b: SharedThing) -> Self {
+        Csg::combine(Operation::Union, a, b)
+    }
+
+    /// Returns the solid inside both of two things
+    pub fn intersection(a: SharedThing, b: SharedThing) -> Self {
+        Csg::combine(Operation::Intersection, a, b)
+    }
+
+    /// Returns the solid inside the first thing but outside of the second
+    pub fn difference(a: SharedThing, b: SharedThing) -> Self {
+        Csg::combine(Operation::Difference, a, b)
+    }
+
+    fn combine(operation: Operation, a: SharedThing, b: SharedThing) -> Self {
+        Csg { operation, a, b }
+    }
+}
+
+impl Thing for Csg {
+    /// Walks the crossings of both things along the ray, returning the first one that changes
+    /// whether the ray is inside the combined solid
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let (mut in_a, a) = crossings(&*self.a, ray);
+        let (mut in_b, b) = crossings(&*self.b, ray);
+        let inside = self.operation.inside(in_a, in_b);
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            let from_a = j == b.len() || (i < a.len() && a[i].hit.distance <= b[j].hit.distance);
+            let crossing = if from_a {
+                i += 1;
+                in_a = a[i - 1].entering;
+                &a[i - 1]
+            } else {
+                j += 1;
+                in_b = b[j - 1].entering;
+                &b[j - 1]
+            };
+            if self.operation.inside(in_a, in_b) != inside {
+                let mut hit = crossing.hit.clone();
+                // The surface of a removed solid faces into the remaining one
+                if self.operation == Operation::Difference && !from_a {
+                    hit.normal = -hit.normal;
+                }
+                return Some(hit);
+            }
+        }
+        None
+    }
+
+    fn bounds(&self) -> Bounds {
+        let (a, b) = (self.a.bounds(), self.b.bounds());
+        match self.operation {
+            Operation::Union => a.union(b),
+            Operation::Intersection => a.intersection(b),
+            Operation::Difference => a,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Mesh;
+    use std::sync::Arc;
+
+    #[test]
+    fn csg_operations() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let n = Material::light(Color::white());
+        let left: SharedThing = Arc::new(Sphere::new(-0.5 * UNIT_X, 1.0, m.clone()));
+        let right: SharedThing = Arc::new(Sphere::new(0.5 * UNIT_X, 1.0, n.clone()));
+        let ray = Ray::new(Point::new(-5.0, 0.0, 0.0), UNIT_X);
+        let back = Ray::new(Point::new(5.0, 0.0, 0.0), -UNIT_X);
+        let inside = Ray::new(ORIGIN, UNIT_X);
+
+        let union = Csg::combine(Operation::Union, left.clone(), right.clone());
+        let hit = union.hit_by(&ray).unwrap();
+        assert!((hit.distance - 3.5).abs() < 1e-4);
+        assert_eq!(hit.material, m);
+        let hit = union.hit_by(&inside).unwrap();
+        assert!((hit.distance - 1.5).abs() < 1e-4);
+        assert_eq!(hit.material, n);
+        assert!(hit.normal.x > 0.99);
+
+        let lens = Csg::combine(Operation::Intersection, left.clone(), right.clone());
+        let hit = lens.hit_by(&ray).unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-4);
+        assert_eq!(hit.material, n);
+        assert!(hit.normal.x < -0.99);
+        let hit = lens.hit_by(&inside).unwrap();
+        assert!((hit.distance - 0.5).abs() < 1e-4);
+        assert_eq!(hit.material, m);
+        let above = Ray::new(Point::new(-1.2, 0.0, -5.0), UNIT_Z);
+        assert!(lens.hit_by(&above).is_none());
+        let bounds = lens.bounds();
+        assert!((bounds.min.x + 0.5).abs() < 1e-6 && (bounds.max.x - 0.5).abs() < 1e-6);
+
+        let cut = Csg::combine(Operation::Difference, left, right);
+        let hit = cut.hit_by(&ray).unwrap();
+        assert!((hit.distance - 3.5).abs() < 1e-4);
+        let hit = cut.hit_by(&back).unwrap();
+        assert!((hit.distance - 5.5).abs() < 1e-4);
+        assert_eq!(hit.material, n);
+        assert!(hit.normal.x > 0.99);
+        assert!(cut.hit_by(&inside).is_none());
+    }
+
+    #[test]
+    fn meshes_can_be_subtracted() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let corners = (0..8)
+            .map(|i| {
+                let c = |bit: usize| if i & bit == 0 { -0.5 } else { 0.5 };
+                Point::new(c(1), c(2), c(4))
+            })
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        let cube: SharedThing =
+            Arc::new(Mesh::new(corners, faces, m.clone(), None, None, None, None).unwrap());
+        let ball: SharedThing = Arc::new(Sphere::new(ORIGIN, 1.0, m));
+        // Off the diagonals of the faces, where the ball reaches out to x = ±surface, and with
+        // normals of the cube smoothed over its corners
+        let start = |x: f32| Ray::new(Point::new(x, 0.1, 0.2), UNIT_X);
+        let surface = 0.95f32.sqrt();
+
+        // The ray enters the ball, then leaves the remaining solid into the hole cut by the cube
+        let hollow = Csg::difference(ball.clone(), cube.clone());
+        let hit = hollow.hit_by(&start(-5.0)).unwrap();
+        assert!((hit.distance - (5.0 - surface)).abs() < 1e-4);
+        assert!(hit.normal.x < -0.9);
+        let hit = hollow.hit_by(&start(-0.75)).unwrap();
+        assert!((hit.distance - 0.25).abs() < 1e-4);
+        assert!(hit.normal.x > 0.9);
+
+        // From inside the hole, the ray first enters the ball again through the cube
+        let hit = hollow.hit_by(&start(0.0)).unwrap();
+        assert!((hit.distance - 0.5).abs() < 1e-4);
+        assert!(hit.normal.x < -0.9);
+
+        let core = Csg::intersection(ball, cube);
+        let hit = core.hit_by(&start(-5.0)).unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-4);
+        assert!(hit.normal.x < -0.9);
+        let hit = core.hit_by(&start(0.0)).unwrap();
+        assert!((hit.distance - 0.5).abs() < 1e-4);
+        assert!(hit.normal.x > 0.9);
+    }
+}
@@ -0,0 +1,425 @@
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::hair::Hair;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::buffer::PyBuffer;
+#[cfg(feature = "python")]
+use pyo3::exceptions;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::sync::Arc;
+
+/// The maximum number of pieces kept in a single leaf of the hierarchy
+static LEAF_SIZE: usize = 4;
+
+/// A control point of a curve, with the radius of the curve around it
+type Control = (Point, f32);
+
+/// A straight piece of a curve, from tessellating its segments, between the point it starts at
+/// and the next one
+#[derive(Clone, Copy, Debug)]
+struct Piece {
+    start: usize,
+    /// The fraction along the whole curve at both ends
+    span: (f32, f32),
+}
+
+impl Piece {
+    /// Returns the capsule around the piece, with the mean radius at its ends
+    fn segment(&self, points: &[Control]) -> Segment {
+        let ((a, ra), (b, rb)) = (points[self.start], points[self.start + 1]);
+        Segment {
+            a,
+            b,
+            radius: 0.5 * (ra + rb),
+        }
+    }
+}
+
+/// A capsule around the line between two points
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    a: Point,
+    b: Point,
+    radius: f32,
+}
+
+impl Segment {
+    /// Returns the entry into a capsule around the piece, by Inigo Quilez
+    fn tube(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let ba = self.b - self.a;
+        let oa = ray.base - self.a;
+        let baba = ba * ba;
+        let bard = ba * ray.direction;
+        let baoa = ba * oa;
+        let rdoa = ray.direction * oa;
+        let oaoa = oa * oa;
+        let r2 = self.radius * self.radius;
+        let a = baba - bard * bard;
+        let b = baba * rdoa - baoa * bard;
+        let c = baba * oaoa - baoa * baoa - r2 * baba;
+        let h = b * b - a * c;
+        if h < 0.0 {
+            return None;
+        }
+        // Where along the axis the ray enters the infinite cylinder, if not parallel to it
+        let y = if a > 1e-12 {
+            let t = (-b - h.sqrt()) / a;
+            let y = baoa + t * bard;
+            if t > 0.0 && y > 0.0 && y < baba {
+                return Some((t, y / baba));
+            }
+            y
+        } else if bard > 0.0 {
+            -1.0
+        } else {
+            baba + 1.0
+        };
+        // The rounded end caps
+        let (oc, s) = if y <= 0.0 {
+            (oa, 0.0)
+        } else {
+            (ray.base - self.b, 1.0)
+        };
+        let b = ray.direction * oc;
+        let h = b * b - (oc * oc - r2);
+        if h < 0.0 {
+            return None;
+        }
+        let t = -b - h.sqrt();
+        if t > 0.0 {
+            Some((t, s))
+        } else {
+            None
+        }
+    }
+
+    /// Returns where the ray passes closest to the piece, if it is within the radius
+    fn flat(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let ba = self.b - self.a;
+        let oa = ray.base - self.a;
+        let baba = ba * ba;
+        let bard = ba * ray.direction;
+        let denominator = baba - bard * bard;
+        if denominator < 1e-12 {
+            return None;
+        }
+        let s = ((ba * oa) - bard * (ray.direction * oa)) / denominator;
+        let s = s.clamp(0.0, 1.0);
+        let closest = self.a + s * ba;
+        let t = (closest - ray.base) * ray.direction;
+        if t <= 0.0 || (ray.at(t) - closest).norm() > self.radius {
+            return None;
+        }
+        Some((t, s))
+    }
+}
+
+impl Bounded for Segment {
+    fn bounds(&self) -> Bounds {
+        let r = Point::new(self.radius, self.radius, self.radius);
+        Bounds::around(&[self.a - r, self.a + r, self.b - r, self.b + r])
+    }
+}
+
+/// Returns the point and radius of a cubic Bézier segment at a fraction `t` along it
+fn bezier(c: &[Control], t: f32) -> Control {
+    let s = 1.0 - t;
+    let weights = [s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t];
+    c.iter()
+        .zip(weights.iter())
+        .fold((ORIGIN, 0.0), |(p, r), ((q, w), k)| {
+            (p + *k * *q, r + k * w)
+        })
+}
+
+/// Converts the segments of a uniform cubic B-spline into Bézier segments
+fn bspline_to_bezier(controls: &[Control]) -> Vec<Control> {
+    let mut result = Vec::new();
+    for window in controls.windows(4) {
+        let combine = |weights: [f32; 4]| {
+            window
+                .iter()
+                .zip(weights.iter())
+                .fold((ORIGIN, 0.0), |(p, r), ((q, w), k)| {
+                    (p + (k / 6.0) * *q, r + k / 6.0 * w)
+                })
+        };
+        if result.is_empty() {
+            result.push(combine([1.0, 4.0, 1.0, 0.0]));
+        }
+        result.push(combine([0.0, 4.0, 2.0, 0.0]));
+        result.push(combine([0.0, 2.0, 4.0, 0.0]));
+        result.push(combine([0.0, 1.0, 4.0, 1.0]));
+    }
+    result
+}
+
+/// Strands of hair, fur or grass, as curves with a varying radius
+///
+/// Curves are made of cubic segments, each split into straight pieces for tracing.  They are
+/// either round tubes or flat ribbons always facing the ray, which are cheaper for very thin
+/// fibers.  Hits carry the direction of the curve, and plain materials scatter light off them
+/// like hair, see `hair::Hair`.  Only rays entering a tube hit it, as the light passing through
+/// a fiber is left to its material.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Curves {
+    strands: Arc<Strands>,
+}
+
+/// The pieces of all curves, sharing the points they run between and a material
+struct Strands {
+    /// The points along the curves with the radii there, those of a curve consecutive
+    points: Vec<Control>,
+    pieces: Vec<Piece>,
+    hierarchy: Hierarchy,
+    ribbon: bool,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Curves {
+    /// Creates curves from arrays of control points
+    ///
+    /// # Arguments
+    ///
+    /// * `points` - control points of shape `(strands, count, 3)`, or `(count, 3)` for a single
+    ///   strand
+    /// * `radii` - a radius for all curves, or one per control point
+    /// * `material` - what the curves are made of
+    /// * `bspline` - if the control points form B-splines instead of Bézier curves, where each
+    ///   segment shares its last control point with the next one
+    /// * `ribbon` - if curves are flat ribbons instead of round tubes
+    /// * `pieces` - how many straight pieces each segment is split into, 8 by default
+    #[new]
+    fn py_new(
+        points: &PyAny,
+        radii: &PyAny,
+        material: Material,
+        bspline: Option<bool>,
+        ribbon: Option<bool>,
+        pieces: Option<usize>,
+    ) -> PyResult<Self> {
+        let py = points.py();
+        let buffer = PyBuffer::get(py, points)?;
+        let shape = buffer.shape().to_vec();
+        let (strands, count) = match shape.as_slice() {
+            [count, 3] => (1, *count),
+            [strands, count, 3] => (*strands, *count),
+            _ => {
+                return Err(exceptions::ValueError::py_err(
+                    "control points need a shape of (strands, count, 3) or (count, 3)",
+                ))
+            }
+        };
+        let coordinates = to_floats(py, &buffer)?;
+        let radii: Vec<f32> = match radii.extract::<f32>() {
+            Ok(r) => vec![r; strands * count],
+            Err(_) => {
+                let values = to_floats(py, &PyBuffer::get(py, radii)?)?;
+                values.into_iter().map(|r| r as f32).collect()
+            }
+        };
+        if radii.len() != strands * count {
+            return Err(exceptions::ValueError::py_err(
+                "need a radius for each control point",
+            ));
+        }
+        let controls: Vec<Vec<Control>> = (0..strands)
+            .map(|s| {
+                (0..count)
+                    .map(|i| {
+                        let k = s * count + i;
+                        let c = &coordinates[3 * k..3 * k + 3];
+                        let p = Point::new(c[0] as f32, c[1] as f32, c[2] as f32);
+                        (p, radii[k])
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Curves::from_controls(
+            controls,
+            bspline.unwrap_or(false),
+            ribbon.unwrap_or(false),
+            pieces.unwrap_or(8),
+            material,
+        )?)
+    }
+}
+
+impl Curves {
+    /// Creates curves from control points with radii, see the Python constructor
+    pub fn from_controls(
+        strands: Vec<Vec<Control>>,
+        bspline: bool,
+        ribbon: bool,
+        pieces: usize,
+        material: Material,
+    ) -> Result<Self> {
+        let per_segment = pieces.max(1);
+        let material = match material.bsdf {
+            Some(_) => material,
+            None => Material {
+                bsdf: Some(Arc::new(Hair::new(&material))),
+                ..material
+            },
+        };
+        let mut points = Vec::new();
+        let mut pieces = Vec::new();
+        for controls in strands {
+            let controls = if bspline {
+                if controls.len() < 4 {
+                    return Err(Error::Value(
+                        "a B-spline needs at least 4 control points".into(),
+                    ));
+                }
+                bspline_to_bezier(&controls)
+            } else {
+                controls
+            };
+            if controls.len() < 4 || (controls.len() - 1) % 3 != 0 {
+                return Err(Error::Value(
+                    "Bézier curves need 3 control points per segment and one more".into(),
+                ));
+            }
+            let segments = (controls.len() - 1) / 3;
+            let steps = segments * per_segment;
+            let start = points.len();
+            points.extend((0..=steps).map(|k| {
+                let segment = (k / per_segment).min(segments - 1);
+                let t = (k - segment * per_segment) as f32 / per_segment as f32;
+                bezier(&controls[3 * segment..3 * segment + 4], t)
+            }));
+            pieces.extend((0..steps).map(|k| Piece {
+                start: start + k,
+                span: (k as f32 / steps as f32, (k + 1) as f32 / steps as f32),
+            }));
+        }
+        let segments: Vec<Segment> = pieces.iter().map(|p| p.segment(&points)).collect();
+        Ok(Curves {
+            strands: Arc::new(Strands {
+                hierarchy: Hierarchy::new(&segments, LEAF_SIZE),
+                points,
+                pieces,
+                ribbon,
+                material,
+            }),
+        })
+    }
+}
+
+impl Thing for Curves {
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let strands = &self.strands;
+        let mut closest: Option<(f32, f32, usize)> = None;
+        strands.hierarchy.visit(ray, f32::INFINITY, |i| {
+            let segment = strands.pieces[i].segment(&strands.points);
+            let (t, s) = if strands.ribbon {
+                segment.flat(ray)
+            } else {
+                segment.tube(ray)
+            }?;
+            if matches!(closest, Some(c) if c.0 <= t) {
+                return None;
+            }
+            closest = Some((t, s, i));
+            Some(t)
+        });
+        let (t, s, i) = closest?;
+        let piece = &strands.pieces[i];
+        let segment = piece.segment(&strands.points);
+        let tangent = (segment.b - segment.a).normalized();
+        let axis = segment.a + s * (segment.b - segment.a);
+        let normal = if strands.ribbon {
+            let facing = ray.direction - (ray.direction * tangent) * tangent;
+            -facing.normalized()
+        } else {
+            (ray.at(t) - axis).normalized()
+        };
+        let offset = ray.at(t) - axis;
+        let side = offset * tangent.cross(normal);
+        let u = piece.span.0 + s * (piece.span.1 - piece.span.0);
+        let v = 0.5 + 0.5 * side / segment.radius;
+        let hit = Hit::new(t, normal, strands.material.clone()).with_uv(u, v.clamp(0.0, 1.0));
+        Some(hit.with_tangent(tangent))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.strands.hierarchy.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).norm() < 1e-4
+    }
+
+    #[test]
+    fn splines_convert() {
+        let controls: Vec<Control> = (0..5)
+            .map(|i| (Point::new(i as f32, (i * i) as f32, 0.0), 1.0))
+            .collect();
+        let bezier_controls = bspline_to_bezier(&controls);
+        assert_eq!(bezier_controls.len(), 7);
+        // The B-spline starts at (p0 + 4 p1 + p2) / 6
+        assert!(close(
+            bezier_controls[0].0,
+            Point::new(1.0, 1.0 + 1.0 / 3.0, 0.0)
+        ));
+        let (p, r) = bezier(&bezier_controls[0..4], 1.0);
+        assert!(close(p, bezier_controls[3].0));
+        assert!((r - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ray_hits_curves() {
+        let m = Material::new(0.5, 0.1, 0.5, 0.0, 0.0, Color::white());
+        let straight: Vec<Control> = (0..4)
+            .map(|i| (Point::new(0.0, i as f32, 0.0), 0.1))
+            .collect();
+        let tube =
+            Curves::from_controls(vec![straight.clone()], false, false, 4, m.clone()).unwrap();
+        let hit = tube
+            .hit_by(&Ray::new(Point::new(-5.0, 1.5, 0.0), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - 4.9).abs() < 1e-4);
+        assert!(close(hit.normal, -UNIT_X));
+        assert!(close(hit.tangent.unwrap(), UNIT_Y));
+        assert!((hit.uv.0 - 0.5).abs() < 1e-4);
+        assert!(tube
+            .hit_by(&Ray::new(Point::new(-5.0, 1.5, 0.2), UNIT_X))
+            .is_none());
+        // Rounded ends
+        let hit = tube
+            .hit_by(&Ray::new(Point::new(0.0, -5.0, 0.0), UNIT_Y))
+            .unwrap();
+        assert!((hit.distance - 4.9).abs() < 1e-4);
+
+        let ribbon = Curves::from_controls(vec![straight], false, true, 4, m.clone()).unwrap();
+        let hit = ribbon
+            .hit_by(&Ray::new(Point::new(-5.0, 1.5, 0.05), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-4);
+        assert!(close(hit.normal, -UNIT_X));
+
+        let bent = vec![
+            (ORIGIN, 0.1),
+            (UNIT_Y, 0.1),
+            (UNIT_Y + UNIT_X, 0.1),
+            (2.0 * UNIT_X, 0.1),
+        ];
+        let (top, _) = bezier(&bent, 0.5);
+        let curve = Curves::from_controls(vec![bent], false, false, 16, m.clone()).unwrap();
+        let hit = curve
+            .hit_by(&Ray::new(Point::new(top.x, 5.0, 0.0), -UNIT_Y))
+            .unwrap();
+        assert!((hit.distance - (5.0 - top.y - 0.1)).abs() < 1e-2);
+        assert!(Curves::from_controls(vec![vec![(ORIGIN, 0.1); 3]], false, false, 4, m).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+#[cfg(feature = "python")]
+use pyo3::exceptions;
+#[cfg(feature = "python")]
+use pyo3::PyErr;
+use std::fmt;
+
+/// Why a scene or one of its parts could not be built
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// Arguments out of range or inconsistent with each other
+    Value(String),
+    /// A file that could not be read or written
+    Io(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Value(message) | Error::Io(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(feature = "python")]
+impl From<Error> for PyErr {
+    fn from(error: Error) -> PyErr {
+        match error {
+            Error::Value(message) => exceptions::ValueError::py_err(message),
+            Error::Io(message) => exceptions::IOError::py_err(message),
+        }
+    }
+}
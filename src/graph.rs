@@ -0,0 +1,262 @@
+use crate::error::{Error, Result};
+use crate::instance::*;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::sync::{Arc, RwLock};
+
+/// The contents of a group, shared by all handles to it
+struct Node {
+    name: String,
+    transform: Transform,
+    material: Option<Material>,
+    groups: Vec<Group>,
+    things: Vec<SharedThing>,
+}
+
+/// A named node of a scene graph
+///
+/// Groups hold things and other groups, placing them with a transformation relative to the
+/// group containing them.  A material set on a group replaces the materials of everything below
+/// it, unless a group further down sets its own.
+///
+/// Copies of a group refer to the same node, so that changes made to a group found by name are
+/// seen by all groups containing it.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Group {
+    node: Arc<RwLock<Node>>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Group {
+    #[new]
+    fn py_new(name: String, transform: Option<Transform>, material: Option<Material>) -> Self {
+        Group::new(name, transform, material)
+    }
+
+    #[getter]
+    fn get_name(&self) -> PyResult<String> {
+        Ok(self.name())
+    }
+
+    #[setter(name)]
+    fn py_set_name(&mut self, name: String) -> PyResult<()> {
+        self.set_name(name);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_transform(&self) -> PyResult<Transform> {
+        Ok(self.transform())
+    }
+
+    #[setter(transform)]
+    fn py_set_transform(&mut self, transform: Transform) -> PyResult<()> {
+        self.set_transform(transform);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_material(&self) -> PyResult<Option<Material>> {
+        Ok(self.material())
+    }
+
+    #[setter(material)]
+    fn py_set_material(&mut self, material: Option<Material>) -> PyResult<()> {
+        self.set_material(material);
+        Ok(())
+    }
+
+    /// Adds a thing or another group as a child
+    fn add(&mut self, child: &PyAny) -> PyResult<()> {
+        if let Ok(group) = child.extract::<Group>() {
+            Ok(self.add_group(group)?)
+        } else {
+            self.add_thing(to_thing(child)?);
+            Ok(())
+        }
+    }
+
+    /// Returns the first group with the given name, searching depth first
+    #[name = "find"]
+    fn py_find(&self, name: &str) -> Option<Group> {
+        self.find(name)
+    }
+
+    /// Removes all groups with the given name below this one, returns if any were found
+    #[name = "remove"]
+    fn py_remove(&mut self, name: &str) -> bool {
+        self.remove(name)
+    }
+}
+
+impl Group {
+    pub fn new(name: String, transform: Option<Transform>, material: Option<Material>) -> Self {
+        Group {
+            node: Arc::new(RwLock::new(Node {
+                name,
+                transform: transform.unwrap_or_else(Transform::identity),
+                material,
+                groups: Vec::new(),
+                things: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.node.read().unwrap().name.clone()
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.node.write().unwrap().name = name;
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.node.read().unwrap().transform
+    }
+
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.node.write().unwrap().transform = transform;
+    }
+
+    /// Returns the material replacing those of everything below the group, if any
+    pub fn material(&self) -> Option<Material> {
+        self.node.read().unwrap().material.clone()
+    }
+
+    pub fn set_material(&mut self, material: Option<Material>) {
+        self.node.write().unwrap().material = material;
+    }
+
+    /// Returns the first group with the given name, searching depth first
+    pub fn find(&self, name: &str) -> Option<Group> {
+        let node = self.node.read().unwrap();
+        if node.name == name {
+            return Some(self.clone());
+        }
+        node.groups.iter().find_map(|g| g.find(name))
+    }
+
+    /// Removes all groups with the given name below this one, returns if any were found
+    pub fn remove(&mut self, name: &str) -> bool {
+        let mut node = self.node.write().unwrap();
+        let count = node.groups.len();
+        node.groups.retain(|g| g.node.read().unwrap().name != name);
+        let removed = node.groups.len() < count;
+        node.groups
+            .iter_mut()
+            .fold(removed, |found, g| g.remove(name) || found)
+    }
+
+    /// Adds a group as a child, refusing to create cycles
+    pub fn add_group(&mut self, group: Group) -> Result<()> {
+        if group.contains(self) {
+            return Err(Error::Value(
+                "cannot add a group to itself or its children".into(),
+            ));
+        }
+        self.node.write().unwrap().groups.push(group);
+        Ok(())
+    }
+
+    pub fn add_thing(&mut self, thing: SharedThing) {
+        self.node.write().unwrap().things.push(thing);
+    }
+
+    /// Returns if `other` is this group or one of its descendants
+    fn contains(&self, other: &Group) -> bool {
+        Arc::ptr_eq(&self.node, &other.node)
+            || self
+                .node
+                .read()
+                .unwrap()
+                .groups
+                .iter()
+                .any(|g| g.contains(other))
+    }
+
+    /// Returns all things below this group, placed in world coordinates
+    pub fn flatten(&self) -> Vec<SharedThing> {
+        let mut things = Vec::new();
+        self.collect(Transform::identity(), None, &mut things);
+        things
+    }
+
+    fn collect(
+        &self,
+        parent: Transform,
+        material: Option<Material>,
+        things: &mut Vec<SharedThing>,
+    ) {
+        let node = self.node.read().unwrap();
+        let transform = node.transform.then(parent);
+        let material = node.material.clone().or(material);
+        for thing in &node.things {
+            if transform == Transform::identity() && material.is_none() {
+                things.push(thing.clone());
+            } else {
+                things.push(Arc::new(Instance::place(
+                    thing.clone(),
+                    transform,
+                    material.clone(),
+                )));
+            }
+        }
+        for group in &node.groups {
+            group.collect(transform, material.clone(), things);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::*;
+
+    #[test]
+    fn groups_flatten() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let n = Material::light(Color::white());
+        let sphere: SharedThing = Arc::new(Sphere::new(ORIGIN, 1.0, m.clone()));
+
+        let mut root = Group::new("root".to_string(), None, None);
+        let mut car = Group::new(
+            "car".to_string(),
+            Some(Transform::translation(5.0 * UNIT_X)),
+            None,
+        );
+        let mut wheel = Group::new(
+            "wheel".to_string(),
+            Some(Transform::translation(-2.0 * UNIT_Z)),
+            Some(n.clone()),
+        );
+        wheel.add_thing(sphere.clone());
+        car.add_thing(sphere.clone());
+        car.add_group(wheel).unwrap();
+        root.add_thing(sphere);
+        root.add_group(car.clone()).unwrap();
+        assert!(car.add_group(root.clone()).is_err());
+
+        let things = root.flatten();
+        assert_eq!(things.len(), 3);
+        let ray = Ray::new(Point::new(5.0, 0.0, -5.0), UNIT_Z);
+        let world = Bvh::new(things);
+        assert_eq!(world.hit_by(&ray).unwrap().material, n);
+
+        let mut found = root.find("wheel").unwrap();
+        found.set_transform(Transform::translation(UNIT_Y));
+        let world = Bvh::new(root.flatten());
+        let ray = Ray::new(Point::new(5.0, 1.5, -5.0), UNIT_Z);
+        assert_eq!(world.hit_by(&ray).unwrap().material, n);
+
+        found.set_material(None);
+        let world = Bvh::new(root.flatten());
+        assert_eq!(world.hit_by(&ray).unwrap().material, m);
+
+        assert!(root.remove("wheel"));
+        assert!(root.find("wheel").is_none());
+        assert_eq!(root.flatten().len(), 2);
+    }
+}
@@ -0,0 +1,213 @@
+//! Light scattered by fibers such as hair, fur or grass
+
+use crate::bsdf::*;
+use crate::microfacet::{glossy, glossy_pdf, glossy_value, smooth};
+use crate::things::*;
+use rand::prelude::*;
+
+/// How much rougher light reflected inside a fiber leaves it than light reflected off it
+static INNER_ROUGHENING: f32 = 2.0;
+
+/// Fibers scattering light into cones around their direction
+///
+/// A simplified version of the three lobes of Marschner et al., each a GGX reflection off the
+/// surface of the fiber with the hardness as roughness: light reflected off it (R, weighted by
+/// the specularity of the material), light passing through it (TT, weighted by the refraction
+/// and tinted once by the color), which is mirrored to the far side, and light reflected inside
+/// it (TRT, weighted by the diffusion, tinted twice and rougher).  As the normals of fibers are
+/// across their direction, reflections keep the angle to the fiber, spreading light into a cone
+/// around it over the width of the fiber.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hair {
+    pub specularity: f32,
+    pub hardness: f32,
+    pub diffusion: f32,
+    pub refraction: f32,
+    pub emittance: f32,
+}
+
+/// One of the lobes of a fiber
+struct Lobe {
+    weight: f32,
+    tint: Color,
+    roughness: f32,
+    /// Whether light passes through the fiber, leaving on the far side
+    through: bool,
+}
+
+impl Lobe {
+    /// Returns the direction `wi` as reflected by the lobe, on the side of the surface of `wo`
+    fn reflected(&self, normal: Point, wi: Point) -> Point {
+        if self.through {
+            wi - 2.0 * (wi * normal) * normal
+        } else {
+            wi
+        }
+    }
+}
+
+impl Hair {
+    /// Returns fibers scattering light by the parts of a plain material
+    pub fn new(material: &Material) -> Self {
+        Hair {
+            specularity: material.specularity,
+            hardness: material.hardness,
+            diffusion: material.diffusion,
+            refraction: material.refraction,
+            emittance: material.emittance,
+        }
+    }
+
+    /// Returns the R, TT and TRT lobes where a fiber is hit, with their total weight
+    fn lobes(&self, at: &Shading) -> ([Lobe; 3], f32) {
+        let roughness = at.roughness.unwrap_or(self.hardness);
+        let lobes = [
+            Lobe {
+                weight: self.specularity,
+                tint: Color::white(),
+                roughness,
+                through: false,
+            },
+            Lobe {
+                weight: self.refraction,
+                tint: at.color,
+                roughness,
+                through: true,
+            },
+            Lobe {
+                weight: self.diffusion,
+                tint: at.color * at.color,
+                roughness: (INNER_ROUGHENING * roughness).min(1.0),
+                through: false,
+            },
+        ];
+        (lobes, self.specularity + self.refraction + self.diffusion)
+    }
+}
+
+/// Lobes of smooth fibers are sampled as specular directions, the others by their densities
+/// summed over all lobes
+impl Bsdf for Hair {
+    fn sample(&self, at: &Shading, wo: Point, rng: &mut dyn RngCore) -> Option<Sample> {
+        let (lobes, total) = self.lobes(at);
+        if total <= 0.0 {
+            return None;
+        }
+        let mut pick = rng.gen::<f32>() * total;
+        let mut chosen = &lobes[2];
+        for lobe in lobes.iter().filter(|lobe| lobe.weight > 0.0) {
+            chosen = lobe;
+            if pick < lobe.weight {
+                break;
+            }
+            pick -= lobe.weight;
+        }
+        let (direction, weight) = glossy(at.normal, -wo, chosen.roughness, rng)?;
+        let direction = chosen.reflected(at.normal, direction);
+        if smooth(chosen.roughness) {
+            return Some(Sample {
+                direction,
+                weight: total * weight * chosen.tint,
+                specular: true,
+            });
+        }
+        let pdf = self.pdf(at, wo, direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(Sample {
+            direction,
+            weight: (at.normal * direction).abs() / pdf * self.eval(at, wo, direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, at: &Shading, wo: Point, wi: Point) -> Color {
+        let (lobes, _) = self.lobes(at);
+        lobes.iter().fold(Color::black(), |sum, lobe| {
+            let wi = lobe.reflected(at.normal, wi);
+            let value = glossy_value(at.normal, wo, wi, lobe.roughness);
+            sum + lobe.weight * value * lobe.tint
+        })
+    }
+
+    fn pdf(&self, at: &Shading, wo: Point, wi: Point) -> f32 {
+        let (lobes, total) = self.lobes(at);
+        if total <= 0.0 {
+            return 0.0;
+        }
+        lobes
+            .iter()
+            .map(|lobe| {
+                let wi = lobe.reflected(at.normal, wi);
+                lobe.weight / total * glossy_pdf(at.normal, wo, wi, lobe.roughness)
+            })
+            .sum()
+    }
+
+    fn emission(&self, at: &Shading, _wo: Point) -> Color {
+        self.emittance * at.color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    fn shading(color: Color) -> Shading {
+        Shading {
+            point: ORIGIN,
+            normal: -UNIT_Z,
+            color,
+            uv: (0.0, 0.0),
+            tangent: Some(UNIT_Y),
+            roughness: None,
+            emission: Color::white(),
+            opacity: 1.0,
+        }
+    }
+
+    #[test]
+    fn fibers_scatter_into_cones() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let wo = Point::new(-1.0, 0.0, 1.0).normalized();
+        let shiny = Hair::new(&Material::new(1.0, 0.0, 0.0, 0.0, 0.0, Color::red()));
+        let sample = shiny.sample(&shading(Color::red()), wo, &mut rng).unwrap();
+        // Light reflected off a fiber keeps its direction along the fiber
+        assert!(sample.specular);
+        assert!((sample.direction - Point::new(1.0, 0.0, 1.0).normalized()).norm() < 1e-5);
+        assert_eq!(sample.weight, Color::white());
+
+        // Light passing through a smooth fiber goes straight on, tinted by its color
+        let clear = Hair::new(&Material::new(0.0, 0.0, 0.0, 0.5, 0.0, Color::white()));
+        let grey = Color::new(0.5, 0.5, 0.5);
+        let sample = clear.sample(&shading(grey), wo, &mut rng).unwrap();
+        assert!((sample.direction + wo).norm() < 1e-5);
+        assert_eq!(sample.weight, Color::new(0.25, 0.25, 0.25));
+
+        // Rough fibers scatter light to both sides, rougher inside
+        let rough = Hair::new(&Material::new(0.2, 0.3, 0.4, 0.4, 0.0, Color::white()));
+        let at = shading(grey);
+        let (mut front, mut back) = (0, 0);
+        for _ in 0..200 {
+            if let Some(sample) = rough.sample(&at, wo, &mut rng) {
+                assert!(!sample.specular);
+                if sample.direction.z > 0.0 {
+                    front += 1;
+                } else {
+                    back += 1;
+                }
+            }
+        }
+        assert!(front > 50 && back > 50, "{} {}", front, back);
+        let sharp = rough.eval(&at, wo, Point::new(1.0, 0.0, 1.0).normalized());
+        let wide = rough.eval(&at, wo, Point::new(1.0, 0.0, 3.0).normalized());
+        assert!(sharp.r > wide.r);
+
+        let dull = Hair::new(&Material::new(0.0, 0.0, 0.0, 0.0, 1.0, Color::white()));
+        assert!(dull.sample(&at, wo, &mut rng).is_none());
+        assert_eq!(dull.emission(&at, wo), grey);
+    }
+}
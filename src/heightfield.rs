@@ -0,0 +1,376 @@
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::terrain::*;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::buffer::PyBuffer;
+#[cfg(feature = "python")]
+use pyo3::exceptions;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::sync::Arc;
+
+/// Elevations sampled on a regular grid, with a normal for each sample
+struct Grid {
+    /// The number of samples along x and y
+    size: (usize, usize),
+    /// Elevations, consecutive along y
+    heights: Vec<f32>,
+    normals: Vec<Point>,
+    /// The lowest and highest elevation of each cell
+    ranges: Vec<(f32, f32)>,
+}
+
+impl Grid {
+    fn height(&self, i: usize, j: usize) -> f32 {
+        self.heights[i * self.size.1 + j]
+    }
+}
+
+/// A terrain given by elevations on a regular grid
+///
+/// Each cell of the grid is split into two triangles, with normals interpolated between the
+/// samples to give a smooth surface.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Heightfield {
+    grid: Arc<Grid>,
+    /// The position of the first sample at an elevation of zero
+    corner: Point,
+    /// The distance between samples along x and y
+    spacing: (f32, f32),
+    material: Material,
+    /// The box around all samples, computed once as rays need it to enter the grid
+    bounds: Bounds,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Heightfield {
+    /// Creates a terrain from generated elevations or a two dimensional array of floating point
+    /// elevations
+    ///
+    /// # Arguments
+    ///
+    /// * `elevation` - an `Elevation` or heights along z, indexed by x first
+    /// * `corner` - where the first sample is placed, elevations are added to its z
+    /// * `width` - the extent of the terrain along x
+    /// * `depth` - the extent of the terrain along y
+    /// * `material` - what the terrain is made of
+    #[new]
+    fn py_new(
+        elevation: &PyAny,
+        corner: Point,
+        width: f32,
+        depth: f32,
+        material: Material,
+    ) -> PyResult<Self> {
+        if let Ok(e) = elevation.extract::<Elevation>() {
+            return Ok(Heightfield::from_elevation(
+                e, corner, width, depth, material,
+            )?);
+        }
+        let py = elevation.py();
+        let buffer = PyBuffer::get(py, elevation)?;
+        if buffer.dimensions() != 2 {
+            return Err(exceptions::ValueError::py_err(
+                "elevation must be a two dimensional array",
+            ));
+        }
+        let size = (buffer.shape()[0], buffer.shape()[1]);
+        let heights = to_floats(py, &buffer)?
+            .into_iter()
+            .map(|h| h as f32)
+            .collect();
+        Ok(Heightfield::from_heights(
+            heights, size, corner, width, depth, material,
+        )?)
+    }
+}
+
+impl Heightfield {
+    /// Creates a terrain from generated elevations, see the Python constructor
+    pub fn from_elevation(
+        elevation: Elevation,
+        corner: Point,
+        width: f32,
+        depth: f32,
+        material: Material,
+    ) -> Result<Self> {
+        elevation.validate()?;
+        Heightfield::from_heights(
+            elevation.heights,
+            elevation.size,
+            corner,
+            width,
+            depth,
+            material,
+        )
+    }
+
+    /// Creates a terrain from elevations consecutive along y, see the Python constructor
+    pub fn from_heights(
+        heights: Vec<f32>,
+        size: (usize, usize),
+        corner: Point,
+        width: f32,
+        depth: f32,
+        material: Material,
+    ) -> Result<Self> {
+        if size.0 < 2 || size.1 < 2 || heights.len() != size.0 * size.1 {
+            return Err(Error::Value(
+                "elevation needs at least two samples along each side".into(),
+            ));
+        }
+        if !(width > 0.0 && width.is_finite() && depth > 0.0 && depth.is_finite()) {
+            return Err(Error::Value(format!(
+                "terrain needs a positive width and depth, not {} and {}",
+                width, depth
+            )));
+        }
+        let spacing = (width / (size.0 - 1) as f32, depth / (size.1 - 1) as f32);
+        let mut grid = Grid {
+            size,
+            heights,
+            normals: Vec::new(),
+            ranges: Vec::new(),
+        };
+        for i in 0..size.0 {
+            for j in 0..size.1 {
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(size.0 - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(size.1 - 1));
+                let dx = (grid.height(i1, j) - grid.height(i0, j)) / ((i1 - i0) as f32 * spacing.0);
+                let dy = (grid.height(i, j1) - grid.height(i, j0)) / ((j1 - j0) as f32 * spacing.1);
+                grid.normals.push(Point::new(-dx, -dy, 1.0).normalized());
+            }
+        }
+        for i in 0..size.0 - 1 {
+            for j in 0..size.1 - 1 {
+                let corners = [
+                    grid.height(i, j),
+                    grid.height(i + 1, j),
+                    grid.height(i, j + 1),
+                    grid.height(i + 1, j + 1),
+                ];
+                grid.ranges.push((
+                    corners.iter().cloned().fold(f32::INFINITY, f32::min),
+                    corners.iter().cloned().fold(-f32::INFINITY, f32::max),
+                ));
+            }
+        }
+        let (low, high) = grid
+            .ranges
+            .iter()
+            .fold((f32::INFINITY, -f32::INFINITY), |(l, h), r| {
+                (l.min(r.0), h.max(r.1))
+            });
+        let far = corner
+            + Point::new(
+                (size.0 - 1) as f32 * spacing.0,
+                (size.1 - 1) as f32 * spacing.1,
+                0.0,
+            );
+        let bounds = Bounds::new(
+            Point::new(corner.x, corner.y, corner.z + low),
+            Point::new(far.x, far.y, corner.z + high),
+        );
+        Ok(Heightfield {
+            grid: Arc::new(grid),
+            corner,
+            spacing,
+            material,
+            bounds,
+        })
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point {
+        self.corner
+            + Point::new(
+                i as f32 * self.spacing.0,
+                j as f32 * self.spacing.1,
+                self.grid.height(i, j),
+            )
+    }
+
+    /// Returns where a ray hits the two triangles of a cell
+    fn hit_cell(&self, ray: &Ray, i: usize, j: usize) -> Option<Hit> {
+        let corners = [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)];
+        let triangles = [[0, 1, 2], [1, 2, 3]];
+        let mut closest: Option<(f32, f32, f32, [usize; 3])> = None;
+        for &t in triangles.iter() {
+            let [a, b, c] = t.map(|k| self.vertex(corners[k].0, corners[k].1));
+            if let Some((d, u, v)) = triangle_distance(a, b, c, ray) {
+                if !matches!(closest, Some(h) if h.0 <= d) {
+                    closest = Some((d, u, v, t));
+                }
+            }
+        }
+        let (d, u, v, [a, b, c]) = closest?;
+        let normals = &self.grid.normals;
+        let index = |k: usize| corners[k].0 * self.grid.size.1 + corners[k].1;
+        let n = (1.0 - u - v) * normals[index(a)] + u * normals[index(b)] + v * normals[index(c)];
+        let n = n.normalized();
+        let n = if n * ray.direction < 0.0 { n } else { -n };
+        let p = ray.at(d) - self.corner;
+        let (w, h) = (
+            self.spacing.0 * (self.grid.size.0 - 1) as f32,
+            self.spacing.1 * (self.grid.size.1 - 1) as f32,
+        );
+        Some(Hit::new(d, n, self.material.clone()).with_uv(p.x / w, p.y / h))
+    }
+}
+
+impl Thing for Heightfield {
+    /// Walks the cells below the ray in order, only testing cells whose elevations overlap with
+    /// the heights of the ray above them
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let (start, end) = self.bounds.entry(ray, f32::INFINITY)?;
+        let (nx, ny) = (self.grid.size.0 - 1, self.grid.size.1 - 1);
+        let local = ray.at(start) - self.corner;
+        let cell =
+            |x: f32, spacing: f32, n: usize| ((x / spacing).floor().max(0.0) as usize).min(n - 1);
+        let (mut i, mut j) = (
+            cell(local.x, self.spacing.0, nx),
+            cell(local.y, self.spacing.1, ny),
+        );
+
+        // Distances to the next cell boundary along each axis, and between boundaries
+        let setup = |index: usize, position: f32, direction: f32, spacing: f32| {
+            if direction > 0.0 {
+                (
+                    start + ((index + 1) as f32 * spacing - position) / direction,
+                    spacing / direction,
+                )
+            } else if direction < 0.0 {
+                (
+                    start + (index as f32 * spacing - position) / direction,
+                    -spacing / direction,
+                )
+            } else {
+                (f32::INFINITY, f32::INFINITY)
+            }
+        };
+        let (mut next_x, delta_x) = setup(i, local.x, ray.direction.x, self.spacing.0);
+        let (mut next_y, delta_y) = setup(j, local.y, ray.direction.y, self.spacing.1);
+
+        let mut enter = start;
+        loop {
+            let exit = next_x.min(next_y).min(end);
+            let (low, high) = self.grid.ranges[i * ny + j];
+            let (z0, z1) = (
+                ray.at(enter).z - self.corner.z,
+                ray.at(exit).z - self.corner.z,
+            );
+            if z0.min(z1) <= high + 1e-4 && z0.max(z1) >= low - 1e-4 {
+                if let Some(hit) = self.hit_cell(ray, i, j) {
+                    return Some(hit);
+                }
+            }
+            if exit >= end {
+                return None;
+            }
+            enter = exit;
+            if next_x < next_y {
+                if ray.direction.x > 0.0 && i + 1 < nx {
+                    i += 1;
+                } else if ray.direction.x < 0.0 && i > 0 {
+                    i -= 1;
+                } else {
+                    return None;
+                }
+                next_x += delta_x;
+            } else {
+                if ray.direction.y > 0.0 && j + 1 < ny {
+                    j += 1;
+                } else if ray.direction.y < 0.0 && j > 0 {
+                    j -= 1;
+                } else {
+                    return None;
+                }
+                next_y += delta_y;
+            }
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_hits_heightfield() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let corner = Point::new(-1.0, -1.0, 0.0);
+        let slope = Heightfield::from_heights(
+            vec![0.0, 0.0, 1.0, 1.0],
+            (2, 2),
+            corner,
+            2.0,
+            2.0,
+            m.clone(),
+        )
+        .unwrap();
+        let hit = slope
+            .hit_by(&Ray::new(Point::new(0.0, 0.0, 5.0), -UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-5);
+        assert!((hit.normal - Point::new(-0.5, 0.0, 1.0).normalized()).norm() < 1e-5);
+        assert!((hit.uv.0 - 0.5).abs() < 1e-5);
+        assert!(
+            Heightfield::from_heights(vec![0.0; 3], (3, 1), corner, 1.0, 1.0, m.clone()).is_err()
+        );
+        for (width, depth) in [
+            (0.0, 1.0),
+            (1.0, -1.0),
+            (f32::INFINITY, 1.0),
+            (1.0, f32::NAN),
+        ] {
+            let flat = vec![0.0; 4];
+            let field = Heightfield::from_heights(flat, (2, 2), corner, width, depth, m.clone());
+            assert!(field.is_err());
+        }
+
+        // Compare against the triangles of the same grid for rays in many directions
+        let size = (9, 7);
+        let heights: Vec<f32> = (0..size.0 * size.1)
+            .map(|k| ((k * 7919) % 13) as f32 * 0.1)
+            .collect();
+        let field = Heightfield::from_heights(heights, size, corner, 2.0, 1.5, m.clone()).unwrap();
+        let mut triangles: Vec<SharedThing> = Vec::new();
+        for i in 0..size.0 - 1 {
+            for j in 0..size.1 - 1 {
+                let v = |x, y| field.vertex(x, y);
+                triangles.push(Arc::new(Triangle::new(
+                    v(i, j),
+                    v(i + 1, j),
+                    v(i, j + 1),
+                    m.clone(),
+                )));
+                triangles.push(Arc::new(Triangle::new(
+                    v(i + 1, j),
+                    v(i, j + 1),
+                    v(i + 1, j + 1),
+                    m.clone(),
+                )));
+            }
+        }
+        for k in 0..50 {
+            let angle = k as f32 * 0.37;
+            let base = Point::new(3.0 * angle.cos(), 3.0 * angle.sin(), 2.0);
+            let target = Point::new(0.1 * (k % 7) as f32 - 0.3, 0.05 * (k % 5) as f32, 0.3);
+            let ray = Ray::new(base, target - base);
+            let expected = ray.intersect(&triangles, None).map(|(h, _)| h.distance);
+            let actual = field.hit_by(&ray).map(|h| h.distance);
+            match (expected, actual) {
+                (Some(e), Some(a)) => assert!((e - a).abs() < 1e-4, "{} != {}", e, a),
+                (e, a) => assert_eq!(e, a),
+            }
+        }
+        let below = Ray::new(Point::new(0.0, -0.5, -1.0), UNIT_X);
+        assert!(field.hit_by(&below).is_none());
+    }
+}
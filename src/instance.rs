@@ -0,0 +1,405 @@
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use pyo3::types::PyList;
+use std::sync::Arc;
+
+/// The upper three rows of a 4x4 matrix, the last row being implicitly `[0, 0, 0, 1]`
+type Affine = [[f32; 4]; 3];
+
+static IDENTITY: Affine = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+];
+
+/// Returns the product `a * b`, i.e., applying `b` first and `a` second
+fn multiply(a: &Affine, b: &Affine) -> Affine {
+    let mut m = [[0.0; 4]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+        row[3] += a[i][3];
+    }
+    m
+}
+
+/// Returns the inverse of an affine matrix, if it is not singular
+fn invert(m: &Affine) -> Option<Affine> {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det: f32 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let mut inv = [[0.0; 4]; 3];
+    for (i, row) in inv.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().take(3).enumerate() {
+            *value = cofactor(j, i) / det;
+        }
+    }
+    for row in inv.iter_mut() {
+        row[3] = -(0..3).map(|k| row[k] * m[k][3]).sum::<f32>();
+    }
+    Some(inv)
+}
+
+/// An affine transformation of space
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    matrix: Affine,
+    inverse: Affine,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Transform {
+    /// Creates a transformation from the rows of a 4x4 or 3x4 matrix
+    #[new]
+    fn py_new(rows: Vec<Vec<f32>>) -> PyResult<Self> {
+        Ok(Transform::new(rows)?)
+    }
+
+    #[staticmethod]
+    #[name = "identity"]
+    fn py_identity() -> Self {
+        Transform::identity()
+    }
+
+    #[staticmethod]
+    #[name = "translation"]
+    fn py_translation(offset: Point) -> Self {
+        Transform::translation(offset)
+    }
+
+    #[staticmethod]
+    #[name = "scaling"]
+    fn py_scaling(x: f32, y: f32, z: f32) -> PyResult<Self> {
+        Ok(Transform::scaling(x, y, z)?)
+    }
+
+    /// Creates a rotation by `angle` radians around `axis`, counter-clockwise looking down the axis
+    #[staticmethod]
+    #[name = "rotation"]
+    fn py_rotation(axis: Point, angle: f32) -> Self {
+        Transform::rotation(axis, angle)
+    }
+
+    /// Returns the transformation applying this one first, followed by `other`
+    #[name = "then"]
+    fn py_then(&self, other: Transform) -> Self {
+        self.then(other)
+    }
+
+    /// Returns the transformation undoing this one
+    #[name = "inverted"]
+    fn py_inverted(&self) -> Self {
+        self.inverted()
+    }
+}
+
+impl Transform {
+    /// Creates a transformation from the rows of a 4x4 or 3x4 matrix
+    pub fn new(rows: Vec<Vec<f32>>) -> Result<Self> {
+        if (rows.len() != 3 && rows.len() != 4) || rows.iter().any(|r| r.len() != 4) {
+            return Err(Error::Value("need 3 or 4 rows of 4 values".into()));
+        }
+        if rows.len() == 4 && rows[3] != [0.0, 0.0, 0.0, 1.0] {
+            return Err(Error::Value(
+                "the last row of an affine transformation has to be [0, 0, 0, 1]".into(),
+            ));
+        }
+        let mut matrix = [[0.0; 4]; 3];
+        for (row, values) in matrix.iter_mut().zip(rows) {
+            row.copy_from_slice(&values);
+        }
+        Transform::from_matrix(matrix)
+            .ok_or_else(|| Error::Value("transformation is not invertible".into()))
+    }
+
+    pub fn identity() -> Self {
+        Transform {
+            matrix: IDENTITY,
+            inverse: IDENTITY,
+        }
+    }
+
+    pub fn translation(offset: Point) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for i in 0..3 {
+            matrix[i][3] = offset[i];
+            inverse[i][3] = -offset[i];
+        }
+        Transform { matrix, inverse }
+    }
+
+    pub fn scaling(x: f32, y: f32, z: f32) -> Result<Self> {
+        let mut matrix = IDENTITY;
+        matrix[0][0] = x;
+        matrix[1][1] = y;
+        matrix[2][2] = z;
+        Transform::from_matrix(matrix).ok_or_else(|| Error::Value("cannot scale by zero".into()))
+    }
+
+    /// Creates a rotation by `angle` radians around `axis`, counter-clockwise looking down the axis
+    pub fn rotation(axis: Point, angle: f32) -> Self {
+        let a = axis.normalized();
+        let (sin, cos) = angle.sin_cos();
+        let c = 1.0 - cos;
+        let matrix = [
+            [
+                cos + a.x * a.x * c,
+                a.x * a.y * c - a.z * sin,
+                a.x * a.z * c + a.y * sin,
+                0.0,
+            ],
+            [
+                a.y * a.x * c + a.z * sin,
+                cos + a.y * a.y * c,
+                a.y * a.z * c - a.x * sin,
+                0.0,
+            ],
+            [
+                a.z * a.x * c - a.y * sin,
+                a.z * a.y * c + a.x * sin,
+                cos + a.z * a.z * c,
+                0.0,
+            ],
+        ];
+        let mut inverse = IDENTITY;
+        for (i, row) in inverse.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().take(3).enumerate() {
+                *value = matrix[j][i];
+            }
+        }
+        Transform { matrix, inverse }
+    }
+
+    /// Returns the transformation applying this one first, followed by `other`
+    pub fn then(&self, other: Transform) -> Self {
+        Transform {
+            matrix: multiply(&other.matrix, &self.matrix),
+            inverse: multiply(&self.inverse, &other.inverse),
+        }
+    }
+
+    /// Returns the transformation undoing this one
+    pub fn inverted(&self) -> Self {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn from_matrix(matrix: Affine) -> Option<Self> {
+        invert(&matrix).map(|inverse| Transform { matrix, inverse })
+    }
+
+    fn apply(m: &Affine, p: Point, w: f32) -> Point {
+        Point::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + w * m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + w * m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + w * m[2][3],
+        )
+    }
+
+    /// Transforms a position
+    pub fn point(&self, p: Point) -> Point {
+        Transform::apply(&self.matrix, p, 1.0)
+    }
+
+    /// Transforms a direction, ignoring the translation
+    pub fn vector(&self, v: Point) -> Point {
+        Transform::apply(&self.matrix, v, 0.0)
+    }
+
+    /// Transforms a surface normal with the inverse transpose, keeping it perpendicular
+    pub fn normal(&self, n: Point) -> Point {
+        let m = &self.inverse;
+        Point::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+        .normalized()
+    }
+
+    /// Returns bounds containing the transformed bounds
+    pub fn bounds(&self, bounds: Bounds) -> Bounds {
+        if !bounds.is_finite() {
+            return bounds;
+        }
+        let corners: Vec<Point> = bounds.corners().iter().map(|c| self.point(*c)).collect();
+        Bounds::around(&corners)
+    }
+
+    /// Returns a ray in the untransformed space together with the factor to convert distances
+    /// along it back into distances along the original ray
+    pub fn untransform(&self, ray: &Ray) -> (Ray, f32) {
+        let direction = Transform::apply(&self.inverse, ray.direction, 0.0);
+        let base = Transform::apply(&self.inverse, ray.base, 1.0);
+        let scale = direction.norm();
+        (Ray::new(base, direction).with_time(ray.time), 1.0 / scale)
+    }
+}
+
+/// A group of things with its own acceleration structure, to be placed many times by instances
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Geometry {
+    bvh: Arc<Bvh>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Geometry {
+    #[new]
+    fn py_new(objects: &PyList) -> PyResult<Self> {
+        let things = objects
+            .iter()
+            .map(to_thing)
+            .collect::<PyResult<Vec<SharedThing>>>()?;
+        Ok(Geometry::from_things(things))
+    }
+}
+
+impl Geometry {
+    pub fn from_things(things: Vec<SharedThing>) -> Self {
+        Geometry {
+            bvh: Arc::new(Bvh::new(things)),
+        }
+    }
+
+    /// Returns the geometry as a thing, without copying it
+    pub fn shared(&self) -> SharedThing {
+        self.bvh.clone()
+    }
+}
+
+/// A thing placed under a transformation, optionally with a different material
+///
+/// The wrapped thing is shared and not copied, so the same geometry can be placed many times
+/// without using more memory for it.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Instance {
+    geometry: SharedThing,
+    transform: Transform,
+    material: Option<Material>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Instance {
+    #[new]
+    fn py_new(
+        geometry: &PyAny,
+        transform: Transform,
+        material: Option<Material>,
+    ) -> PyResult<Self> {
+        Ok(Instance::place(to_thing(geometry)?, transform, material))
+    }
+}
+
+impl Instance {
+    /// Returns a thing placed under a transformation
+    ///
+    /// # Arguments
+    ///
+    /// * `geometry` - the thing to place, in its own coordinates
+    /// * `transform` - the transformation from the coordinates of the thing into the world
+    /// * `material` - an optional material replacing the one of the thing
+    pub fn place(geometry: SharedThing, transform: Transform, material: Option<Material>) -> Self {
+        Instance {
+            geometry,
+            transform,
+            material,
+        }
+    }
+}
+
+impl Thing for Instance {
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let (local, scale) = self.transform.untransform(ray);
+        let hit = self.geometry.hit_by(&local)?;
+        Some(Hit {
+            distance: hit.distance * scale,
+            normal: self.transform.normal(hit.normal),
+            material: self.material.clone().unwrap_or(hit.material),
+            tangent: hit.tangent.map(|t| self.transform.vector(t).normalized()),
+            ..hit
+        })
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.transform.bounds(self.geometry.bounds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    #[test]
+    fn transform_inverts() {
+        let t = Transform::rotation(Point::new(1.0, 1.0, 0.0), 0.7)
+            .then(Transform::scaling(2.0, 3.0, 0.5).unwrap())
+            .then(Transform::translation(Point::new(1.0, -2.0, 3.0)));
+        let p = Point::new(0.3, -0.2, 4.0);
+        assert!(close(t.inverted().point(t.point(p)), p));
+
+        let u = Transform::from_matrix(t.matrix).unwrap();
+        assert!(close(u.inverted().point(t.point(p)), p));
+
+        let r = Transform::rotation(UNIT_Z, std::f32::consts::FRAC_PI_2);
+        assert!(close(r.point(UNIT_X), UNIT_Y));
+        assert!(Transform::scaling(1.0, 0.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn ray_hits_instance() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let n = Material::light(Color::white());
+        let sphere: SharedThing = Arc::new(Sphere::new(ORIGIN, 1.0, m.clone()));
+
+        let t = Transform::scaling(2.0, 1.0, 1.0)
+            .unwrap()
+            .then(Transform::translation(Point::new(5.0, 0.0, 0.0)));
+        let i = Instance::place(sphere.clone(), t, None);
+        let hit = i.hit_by(&Ray::new(ORIGIN, UNIT_X)).unwrap();
+        assert!((hit.distance - 3.0).abs() < 1e-5);
+        assert!(close(hit.normal, -UNIT_X));
+        assert_eq!(hit.material, m);
+
+        let ray = Ray::new(Point::new(5.0, 0.0, -5.0), UNIT_Z);
+        let hit = i.hit_by(&ray).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+
+        // An ellipsoid has tilted normals away from its axes
+        let ray = Ray::new(Point::new(6.0, 0.0, -5.0), UNIT_Z);
+        let hit = i.hit_by(&ray).unwrap();
+        let impact = ray.at(hit.distance);
+        let expected = Point::new((impact.x - 5.0) / 4.0, 0.0, impact.z).normalized();
+        assert!(close(hit.normal, expected));
+
+        let bounds = i.bounds();
+        assert!(close(bounds.min, Point::new(3.0, -1.0, -1.0)));
+        assert!(close(bounds.max, Point::new(7.0, 1.0, 1.0)));
+
+        let i = Instance::place(sphere, Transform::identity(), Some(n.clone()));
+        assert_eq!(i.hit_by(&Ray::new(ORIGIN, UNIT_X)).unwrap().material, n);
+    }
+}
@@ -1,25 +1,105 @@
+//! A path tracer for scenes of spheres, meshes, implicit surfaces and more
+//!
+//! Scenes are built from things placed in front of a `scene::Camera` and rendered into image
+//! files by `scene::Scene`.  With the `python` feature, the same types make up a Python module.
+
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
+#[cfg(feature = "python")]
 use pyo3::types::PyList;
 
-mod scene;
-mod things;
+pub mod asset;
+pub mod bsdf;
+pub mod bvh;
+pub mod csg;
+pub mod curves;
+pub mod error;
+pub mod graph;
+mod hair;
+pub mod heightfield;
+pub mod instance;
+pub mod mesh;
+pub mod metaballs;
+pub mod microfacet;
+pub mod pbrt;
+mod ply;
+pub mod pointcloud;
+pub mod principled;
+pub mod quadric;
+mod roots;
+pub mod scene;
+pub mod scenefile;
+pub mod sdf;
+pub mod shapes;
+mod stl;
+mod subdivision;
+pub mod terrain;
+pub mod texture;
+pub mod things;
 
-use scene::*;
-use things::*;
+pub use error::{Error, Result};
 
+#[cfg(feature = "python")]
 #[pymodule]
 fn pathetic(_py: Python, m: &PyModule) -> PyResult<()> {
+    use asset::*;
+    use csg::*;
+    use curves::*;
+    use graph::*;
+    use heightfield::*;
+    use instance::*;
+    use mesh::*;
+    use metaballs::*;
+    use pbrt::*;
+    use pointcloud::*;
+    use principled::*;
+    use quadric::*;
+    use scene::*;
+    use scenefile::*;
+    use sdf::*;
+    use shapes::*;
+    use terrain::*;
+    use texture::*;
+    use things::*;
+
     m.add_class::<Point>()?;
     m.add_class::<Ray>()?;
     m.add_class::<Color>()?;
     m.add_class::<Material>()?;
+    m.add_class::<Principled>()?;
 
     m.add_class::<Rhomboid>()?;
     m.add_class::<Sphere>()?;
     m.add_class::<Triangle>()?;
+    m.add_class::<Mesh>()?;
+    m.add_class::<Plane>()?;
+    m.add_class::<Disk>()?;
+    m.add_class::<Cylinder>()?;
+    m.add_class::<Cone>()?;
+    m.add_class::<Torus>()?;
+    m.add_class::<Cuboid>()?;
+    m.add_class::<Quadric>()?;
+    m.add_class::<Csg>()?;
+    m.add_class::<Sdf>()?;
+    m.add_class::<Marched>()?;
+    m.add_class::<Metaballs>()?;
+    m.add_class::<Curves>()?;
+    m.add_class::<PointCloud>()?;
+    m.add_class::<Heightfield>()?;
+    m.add_class::<Elevation>()?;
+    m.add_class::<Texture>()?;
+    m.add_class::<Motion>()?;
+    m.add_class::<Transform>()?;
+    m.add_class::<Geometry>()?;
+    m.add_class::<Instance>()?;
+    m.add_class::<Group>()?;
+    m.add_class::<Asset>()?;
 
     m.add_class::<Camera>()?;
     m.add_class::<Lens>()?;
+    m.add_class::<Shutter>()?;
+    m.add_class::<SceneFile>()?;
+    m.add_class::<PbrtScene>()?;
 
     #[pyfn(m, "render")]
     fn render(
@@ -33,21 +113,12 @@ fn pathetic(_py: Python, m: &PyModule) -> PyResult<()> {
     ) {
         let mut scene = Scene::new(camera, samples, bounces);
         for obj in objects {
-            let rhomboid: Result<Rhomboid, _> = obj.extract();
-            if let Ok(r) = rhomboid {
-                scene.add(r);
-                continue;
-            }
-
-            let sphere: Result<Sphere, _> = obj.extract();
-            if let Ok(s) = sphere {
-                scene.add(s);
-                continue;
-            }
-
-            let triangle: Result<Triangle, _> = obj.extract();
-            if let Ok(r) = triangle {
-                scene.add(r);
+            if let Ok(group) = obj.extract::<Group>() {
+                for thing in group.flatten() {
+                    scene.add(thing);
+                }
+            } else if let Ok(thing) = to_thing(obj) {
+                scene.add(thing);
             }
         }
         scene.render(filename, dpi).unwrap();
@@ -0,0 +1,185 @@
+//! Renders scene files from the command line
+
+use pathetic::pbrt::PbrtScene;
+use pathetic::scenefile::{RenderDescription, SceneFile};
+use std::env;
+use std::process;
+
+static USAGE: &str = "usage: pathetic [options] <scene file>
+
+Renders a TOML, JSON or pbrt scene file, overriding its render settings with the options given.
+
+options:
+  -s, --samples <count>   rays cast per pixel
+  -b, --bounces <count>   reflections followed per ray
+  -d, --dpi <dpi>         pixels per unit of the camera screen
+  -w, --width <pixels>    width of the image instead of the dpi, keeping the aspect ratio
+      --seed <seed>       seed of the random numbers, for independent images
+  -o, --output <file>     image file to write
+  -h, --help              show this message";
+
+/// The render settings given on the command line
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    scene: String,
+    samples: Option<usize>,
+    bounces: Option<usize>,
+    dpi: Option<u32>,
+    width: Option<u32>,
+    seed: Option<u64>,
+    output: Option<String>,
+}
+
+impl Options {
+    /// Parses the arguments following the name of the program, None asking for help
+    fn parse(args: &[String]) -> Result<Option<Options>, String> {
+        fn number<T: std::str::FromStr>(option: &str, value: Option<&String>) -> Result<T, String> {
+            let value = value.ok_or_else(|| format!("missing value for {}", option))?;
+            value
+                .parse()
+                .map_err(|_| format!("invalid value {} for {}", value, option))
+        }
+
+        let mut options = Options::default();
+        let mut scene = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-s" | "--samples" => options.samples = Some(number(arg, args.next())?),
+                "-b" | "--bounces" => options.bounces = Some(number(arg, args.next())?),
+                "-d" | "--dpi" => options.dpi = Some(number(arg, args.next())?),
+                "-w" | "--width" => options.width = Some(number(arg, args.next())?),
+                "--seed" => options.seed = Some(number(arg, args.next())?),
+                "-o" | "--output" => {
+                    let output = args.next().ok_or("missing value for --output")?;
+                    options.output = Some(output.clone());
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ if scene.is_some() => return Err("more than one scene file given".into()),
+                _ => scene = Some(arg.clone()),
+            }
+        }
+        if options.dpi == Some(0) || options.width == Some(0) {
+            return Err("the image needs at least one pixel".into());
+        }
+        if options.dpi.is_some() && options.width.is_some() {
+            return Err("only one of --dpi and --width can be given".into());
+        }
+        options.scene = scene.ok_or("no scene file given")?;
+        Ok(Some(options))
+    }
+
+    /// Replaces the render settings of a scene file
+    fn apply(&self, settings: &mut RenderDescription) {
+        settings.samples = self.samples.or(settings.samples);
+        settings.bounces = self.bounces.or(settings.bounces);
+        settings.seed = self.seed.or(settings.seed);
+        if let Some(dpi) = self.dpi {
+            settings.dpi = dpi;
+        }
+        if let Some(output) = &self.output {
+            settings.output = output.clone();
+        }
+    }
+
+    /// Returns the size of the image in pixels, given the dpi and the size of the camera screen
+    fn pixels(&self, dpi: u32, screen: (f32, f32)) -> (u32, u32) {
+        match self.width {
+            Some(width) => {
+                let height = (width as f32 * screen.1 / screen.0).round() as u32;
+                (width, height.max(1))
+            }
+            None => (
+                (dpi as f32 * screen.0).round() as u32,
+                (dpi as f32 * screen.1).round() as u32,
+            ),
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("pathetic: {}", message);
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(message) => fail(&format!("{}\n\n{}", message, USAGE)),
+    };
+    let (settings, screen, scene) = if options.scene.to_lowercase().ends_with(".pbrt") {
+        let mut pbrt = PbrtScene::load(&options.scene).unwrap_or_else(|e| fail(&e.to_string()));
+        options.apply(&mut pbrt.render);
+        let screen = (pbrt.camera.width(), pbrt.camera.height());
+        (pbrt.render.clone(), screen, pbrt.scene())
+    } else {
+        let mut file = SceneFile::load(&options.scene).unwrap_or_else(|e| fail(&e.to_string()));
+        let description = file.description_mut();
+        options.apply(&mut description.render);
+        let settings = description.render.clone();
+        let screen = (description.camera.width, description.camera.height);
+        (
+            settings,
+            screen,
+            file.scene().unwrap_or_else(|e| fail(&e.to_string())),
+        )
+    };
+    let (width, height) = options.pixels(settings.dpi, screen);
+    if let Err(e) = scene.render_pixels(&settings.output, width, height) {
+        fail(&format!("{}: {}", settings.output, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn options_parse() {
+        let options = parse(&["-s", "10", "scene.toml", "--seed", "3", "-o", "out.png"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.scene, "scene.toml");
+        assert_eq!(options.samples, Some(10));
+        assert_eq!(options.seed, Some(3));
+        assert_eq!(options.output.as_deref(), Some("out.png"));
+        assert_eq!(options.bounces, None);
+
+        assert_eq!(parse(&["--help"]), Ok(None));
+        assert!(parse(&["-s", "many", "scene.toml"]).is_err());
+        assert!(parse(&["scene.toml", "-b"]).is_err());
+        assert!(parse(&["--fast", "scene.toml"]).is_err());
+        assert!(parse(&["a.toml", "b.toml"]).is_err());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["-d", "0", "scene.toml"]).is_err());
+        assert!(parse(&["-d", "50", "-w", "400", "scene.toml"]).is_err());
+    }
+
+    #[test]
+    fn width_sets_pixels() {
+        for &width in &[1, 7, 333, 400] {
+            let options = parse(&["-w", &width.to_string(), "scene.toml"])
+                .unwrap()
+                .unwrap();
+            assert_eq!(options.pixels(100, (0.7, 0.35)).0, width);
+        }
+        let options = parse(&["-w", "400", "scene.toml"]).unwrap().unwrap();
+        assert_eq!(options.pixels(100, (0.7, 0.35)), (400, 200));
+        // Far more pixels than the screen is wide, or far fewer
+        assert_eq!(options.pixels(100, (1000.0, 500.0)), (400, 200));
+        assert_eq!(options.pixels(100, (0.001, 0.001)), (400, 400));
+        let options = parse(&["scene.toml"]).unwrap().unwrap();
+        assert_eq!(options.pixels(100, (0.7, 0.35)), (70, 35));
+    }
+}
@@ -0,0 +1,597 @@
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::ply::*;
+use crate::stl::*;
+use crate::subdivision::*;
+use crate::texture::*;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// How often triangles are split at most while tessellating for displacement
+static MAX_REFINEMENTS: usize = 16;
+
+/// The maximum number of triangles kept in a single leaf of the hierarchy
+static LEAF_SIZE: usize = 4;
+
+/// The shared contents of a mesh
+struct Surface {
+    vertices: Vec<Point>,
+    normals: Vec<Point>,
+    uvs: Option<Vec<(f32, f32)>>,
+    /// Colors of the vertices, replacing the color of the material
+    colors: Option<Vec<Color>>,
+    /// Polygons given by the indices of their vertices, counter-clockwise seen from outside
+    faces: Vec<Vec<usize>>,
+    /// The triangles of all faces, splitting polygons into fans
+    triangles: Vec<[usize; 3]>,
+    /// The hierarchy over the triangles
+    hierarchy: Hierarchy,
+    material: Material,
+}
+
+impl Surface {
+    /// Returns the corners of a triangle
+    fn corners(&self, triangle: usize) -> [Point; 3] {
+        let [a, b, c] = self.triangles[triangle];
+        [self.vertices[a], self.vertices[b], self.vertices[c]]
+    }
+}
+
+/// Checks that faces refer to existing vertices and that there is data for each vertex
+fn check(
+    count: usize,
+    faces: &[Vec<usize>],
+    uvs: &Option<Vec<(f32, f32)>>,
+    normals: &Option<Vec<Point>>,
+) -> Result<()> {
+    if faces
+        .iter()
+        .any(|f| f.len() < 3 || f.iter().any(|i| *i >= count))
+    {
+        return Err(Error::Value(
+            "faces need at least three indices of vertices".into(),
+        ));
+    }
+    if matches!(uvs, Some(ref uvs) if uvs.len() != count)
+        || matches!(normals, Some(ref normals) if normals.len() != count)
+    {
+        return Err(Error::Value(
+            "need surface coordinates and normals for each vertex".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// A surface made of polygons sharing their vertices, shaded smoothly across edges
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Mesh {
+    surface: Arc<Surface>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Mesh {
+    /// Creates a mesh from its vertices and faces
+    ///
+    /// # Arguments
+    ///
+    /// * `vertices` - the positions of the corners of the faces
+    /// * `faces` - lists of indices of at least three vertices, counter-clockwise seen from
+    ///   outside
+    /// * `material` - what the mesh is made of
+    /// * `uvs` - surface coordinates for each vertex
+    /// * `normals` - a normal for each vertex, averaged from the faces around it if missing
+    /// * `subdivision` - either "catmull-clark" or "loop", to refine the faces as a control mesh
+    ///   of a smooth surface, using its normals instead of the given ones
+    /// * `levels` - how often to subdivide, two by default
+    #[new]
+    fn py_new(
+        vertices: Vec<Point>,
+        faces: Vec<Vec<usize>>,
+        material: Material,
+        uvs: Option<Vec<(f32, f32)>>,
+        normals: Option<Vec<Point>>,
+        subdivision: Option<&str>,
+        levels: Option<usize>,
+    ) -> PyResult<Self> {
+        Ok(Mesh::new(
+            vertices,
+            faces,
+            material,
+            uvs,
+            normals,
+            subdivision,
+            levels,
+        )?)
+    }
+
+    /// Returns the mesh with its vertices moved along their normals by a texture
+    ///
+    /// Before displacing, triangles are split until no edge is longer than `max_edge`, so that
+    /// the detail of the texture shows.
+    #[name = "displaced"]
+    fn py_displaced(&self, texture: &Texture, scale: f32, max_edge: Option<f32>) -> Self {
+        self.displaced(texture, scale, max_edge)
+    }
+
+    /// Loads a mesh from a PLY or STL file, telling them apart by the extension
+    #[staticmethod]
+    #[name = "load"]
+    fn py_load(filename: &str, material: Material) -> PyResult<Self> {
+        Ok(Mesh::load(filename, material)?)
+    }
+
+    #[getter]
+    fn get_vertices(&self) -> PyResult<Vec<Point>> {
+        Ok(self.surface.vertices.clone())
+    }
+
+    #[getter]
+    fn get_faces(&self) -> PyResult<Vec<Vec<usize>>> {
+        Ok(self.surface.faces.clone())
+    }
+}
+
+impl Mesh {
+    /// Creates a mesh from its vertices and faces, see the Python constructor for the arguments
+    pub fn new(
+        vertices: Vec<Point>,
+        faces: Vec<Vec<usize>>,
+        material: Material,
+        uvs: Option<Vec<(f32, f32)>>,
+        normals: Option<Vec<Point>>,
+        subdivision: Option<&str>,
+        levels: Option<usize>,
+    ) -> Result<Self> {
+        check(vertices.len(), &faces, &uvs, &normals)?;
+        match subdivision {
+            Some(name) => {
+                let scheme = Scheme::parse(name)
+                    .ok_or_else(|| Error::Value(format!("unknown subdivision {}", name)))?;
+                let control = Polygons {
+                    vertices,
+                    faces,
+                    uvs,
+                };
+                let (refined, normals) = subdivide(&control, scheme, levels.unwrap_or(2));
+                Ok(Mesh::build(
+                    refined.vertices,
+                    refined.faces,
+                    material,
+                    refined.uvs,
+                    Some(normals),
+                    None,
+                ))
+            }
+            None => Ok(Mesh::build(vertices, faces, material, uvs, normals, None)),
+        }
+    }
+
+    /// Returns the mesh with its vertices moved along their normals by a texture
+    ///
+    /// Before displacing, triangles are split until no edge is longer than `max_edge`, so that
+    /// the detail of the texture shows.  Edges are split the same way in all triangles sharing
+    /// them, so the surface does not crack.
+    ///
+    /// # Arguments
+    ///
+    /// * `texture` - how far to move each vertex, looked up at it
+    /// * `scale` - the distance corresponding to a value of one
+    /// * `max_edge` - the longest edge left after tessellation, if any
+    pub fn displaced(&self, texture: &Texture, scale: f32, max_edge: Option<f32>) -> Self {
+        let s = &self.surface;
+        let mut vertices = s.vertices.clone();
+        let mut normals = s.normals.clone();
+        let mut uvs = s
+            .uvs
+            .clone()
+            .unwrap_or_else(|| vec![(0.0, 0.0); vertices.len()]);
+        let mut colors = s
+            .colors
+            .clone()
+            .unwrap_or_else(|| vec![s.material.color; vertices.len()]);
+        let mut triangles = s.triangles.clone();
+        if let Some(max_edge) = max_edge {
+            for _ in 0..MAX_REFINEMENTS {
+                let split = refine(
+                    &mut vertices,
+                    &mut normals,
+                    &mut uvs,
+                    &mut colors,
+                    &triangles,
+                    max_edge,
+                );
+                match split {
+                    Some(refined) => triangles = refined,
+                    None => break,
+                }
+            }
+        }
+        for ((p, n), uv) in vertices.iter_mut().zip(normals.iter()).zip(uvs.iter()) {
+            *p += scale * texture.value_at(*p, uv.0, uv.1) * *n;
+        }
+        let faces = triangles.iter().map(|t| t.to_vec()).collect();
+        let uvs = s.uvs.as_ref().map(|_| uvs);
+        let colors = s.colors.as_ref().map(|_| colors);
+        Mesh::build(vertices, faces, s.material.clone(), uvs, None, colors)
+    }
+
+    /// Loads a mesh from a PLY or STL file, telling them apart by the extension
+    ///
+    /// PLY files may have normals, surface coordinates and colors at the vertices, the latter
+    /// replacing the color of the material.  The triangles of STL files do not share vertices,
+    /// so they are shaded flat.
+    pub fn load(filename: &str, material: Material) -> Result<Self> {
+        let failed = |e| Error::Io(format!("{}: {}", filename, e));
+        let extension = filename.rsplit('.').next().unwrap_or("");
+        match extension.to_lowercase().as_str() {
+            "ply" => Mesh::from_ply(&Ply::open(filename).map_err(failed)?, material),
+            "stl" => Ok(Mesh::from_stl(
+                &Stl::open(filename).map_err(failed)?,
+                material,
+            )),
+            _ => Err(Error::Value(format!("{}: unknown mesh format", filename))),
+        }
+    }
+
+    pub fn vertices(&self) -> &[Point] {
+        &self.surface.vertices
+    }
+
+    pub fn faces(&self) -> &[Vec<usize>] {
+        &self.surface.faces
+    }
+
+    /// Creates a mesh without checking the indices of its faces, see `new`
+    pub fn build(
+        vertices: Vec<Point>,
+        faces: Vec<Vec<usize>>,
+        material: Material,
+        uvs: Option<Vec<(f32, f32)>>,
+        normals: Option<Vec<Point>>,
+        colors: Option<Vec<Color>>,
+    ) -> Self {
+        let triangles: Vec<[usize; 3]> = faces
+            .iter()
+            .flat_map(|f| (1..f.len() - 1).map(move |i| [f[0], f[i], f[i + 1]]))
+            .collect();
+        let bounds: Vec<Bounds> = triangles
+            .iter()
+            .map(|t| Bounds::around(&[vertices[t[0]], vertices[t[1]], vertices[t[2]]]))
+            .collect();
+        let normals = normals.unwrap_or_else(|| vertex_normals(&vertices, &faces));
+        Mesh {
+            surface: Arc::new(Surface {
+                vertices,
+                normals,
+                uvs,
+                colors,
+                faces,
+                triangles,
+                hierarchy: Hierarchy::new(&bounds, LEAF_SIZE),
+                material,
+            }),
+        }
+    }
+
+    /// Creates a mesh after checking the indices of its faces and the data of its vertices
+    pub fn validated(
+        vertices: Vec<Point>,
+        faces: Vec<Vec<usize>>,
+        material: Material,
+        uvs: Option<Vec<(f32, f32)>>,
+        normals: Option<Vec<Point>>,
+        colors: Option<Vec<Color>>,
+    ) -> Result<Self> {
+        check(vertices.len(), &faces, &uvs, &normals)?;
+        if matches!(colors, Some(ref colors) if colors.len() != vertices.len()) {
+            return Err(Error::Value("need a color for each vertex".into()));
+        }
+        Ok(Mesh::build(vertices, faces, material, uvs, normals, colors))
+    }
+
+    /// Creates a mesh from the vertices and faces of a PLY file, see `load`
+    pub fn from_ply(ply: &Ply, material: Material) -> Result<Self> {
+        let missing = |what: &str| Error::Value(format!("no {} in file", what));
+        let vertex = ply.element("vertex").ok_or_else(|| missing("vertices"))?;
+        let vertices: Vec<Point> = vertex
+            .triples(["x", "y", "z"])
+            .ok_or_else(|| missing("positions"))?
+            .into_iter()
+            .map(Point::from)
+            .collect();
+        let face = ply.element("face").ok_or_else(|| missing("faces"))?;
+        let indices = face
+            .lists("vertex_indices")
+            .or_else(|| face.lists("vertex_index"))
+            .ok_or_else(|| missing("faces"))?;
+        let faces: Vec<Vec<usize>> = indices
+            .iter()
+            .map(|f| f.iter().map(|i| *i as usize).collect())
+            .collect();
+        let uvs = ["u", "s", "texture_u"]
+            .iter()
+            .zip(&["v", "t", "texture_v"])
+            .find_map(|(u, v)| {
+                let (u, v) = (vertex.scalars(u)?, vertex.scalars(v)?);
+                Some(
+                    u.iter()
+                        .zip(v)
+                        .map(|(u, v)| (*u as f32, *v as f32))
+                        .collect(),
+                )
+            });
+        let normals = vertex
+            .triples(["nx", "ny", "nz"])
+            .map(|n| n.into_iter().map(Point::from).collect());
+        let colors: Option<Vec<Color>> = vertex
+            .colors()
+            .map(|c| c.into_iter().map(Color::from).collect());
+        Mesh::validated(vertices, faces, material, uvs, normals, colors)
+    }
+
+    /// Creates a mesh from the triangles of an STL file, see `load`
+    pub fn from_stl(stl: &Stl, material: Material) -> Self {
+        let vertices = stl
+            .triangles
+            .iter()
+            .flat_map(|t| t.iter().map(|p| Point::new(p[0], p[1], p[2])))
+            .collect();
+        let faces = (0..stl.triangles.len())
+            .map(|i| vec![3 * i, 3 * i + 1, 3 * i + 2])
+            .collect();
+        Mesh::build(vertices, faces, material, None, None, None)
+    }
+}
+
+/// Splits all edges longer than `max_edge` at their midpoints, returning the new triangles if
+/// any edge was split
+///
+/// Depending on how many of its edges are split, a triangle is replaced by two, three or four
+/// triangles.
+fn refine(
+    vertices: &mut Vec<Point>,
+    normals: &mut Vec<Point>,
+    uvs: &mut Vec<(f32, f32)>,
+    colors: &mut Vec<Color>,
+    triangles: &[[usize; 3]],
+    max_edge: f32,
+) -> Option<Vec<[usize; 3]>> {
+    let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+    for t in triangles {
+        for k in 0..3 {
+            let (a, b) = (t[k], t[(k + 1) % 3]);
+            let key = (a.min(b), a.max(b));
+            if midpoints.contains_key(&key) || (vertices[a] - vertices[b]).norm() <= max_edge {
+                continue;
+            }
+            midpoints.insert(key, vertices.len());
+            vertices.push(0.5 * (vertices[a] + vertices[b]));
+            normals.push((normals[a] + normals[b]).normalized());
+            uvs.push((0.5 * (uvs[a].0 + uvs[b].0), 0.5 * (uvs[a].1 + uvs[b].1)));
+            colors.push(0.5 * (colors[a] + colors[b]));
+        }
+    }
+    if midpoints.is_empty() {
+        return None;
+    }
+    let midpoint = |a: usize, b: usize| midpoints.get(&(a.min(b), a.max(b))).cloned();
+    let mut refined = Vec::new();
+    for t in triangles {
+        let split: Vec<Option<usize>> = (0..3).map(|k| midpoint(t[k], t[(k + 1) % 3])).collect();
+        match split.iter().filter(|m| m.is_some()).count() {
+            0 => refined.push(*t),
+            3 => {
+                let (ab, bc, ca) = (split[0].unwrap(), split[1].unwrap(), split[2].unwrap());
+                refined.push([t[0], ab, ca]);
+                refined.push([ab, t[1], bc]);
+                refined.push([ca, bc, t[2]]);
+                refined.push([ab, bc, ca]);
+            }
+            count => {
+                // Rotate the corners, so that the first edge is split and, for two splits, the
+                // last one is not
+                let k = (0..3)
+                    .find(|k| split[*k].is_some() && (count == 1 || split[(k + 2) % 3].is_none()))
+                    .unwrap();
+                let (a, b, c) = (t[k], t[(k + 1) % 3], t[(k + 2) % 3]);
+                let ab = split[k].unwrap();
+                match split[(k + 1) % 3] {
+                    None => {
+                        refined.push([a, ab, c]);
+                        refined.push([ab, b, c]);
+                    }
+                    Some(bc) => {
+                        refined.push([ab, b, bc]);
+                        refined.push([a, ab, bc]);
+                        refined.push([a, bc, c]);
+                    }
+                }
+            }
+        }
+    }
+    Some(refined)
+}
+
+/// Triangles have normals and surface coordinates interpolated from their corners
+impl Thing for Mesh {
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let s = &self.surface;
+        let mut closest: Option<(f32, f32, f32, usize)> = None;
+        s.hierarchy.visit(ray, f32::INFINITY, |i| {
+            let [a, b, c] = s.corners(i);
+            let (t, u, v) = triangle_distance(a, b, c, ray)?;
+            if matches!(closest, Some(h) if h.0 <= t) {
+                return None;
+            }
+            closest = Some((t, u, v, i));
+            Some(t)
+        });
+        let (t, u, v, i) = closest?;
+        let [a, b, c] = s.triangles[i];
+        let w = 1.0 - u - v;
+        let n = (w * s.normals[a] + u * s.normals[b] + v * s.normals[c]).normalized();
+        let n = if n * ray.direction < 0.0 { n } else { -n };
+        let uv = match &s.uvs {
+            Some(uvs) => (
+                w * uvs[a].0 + u * uvs[b].0 + v * uvs[c].0,
+                w * uvs[a].1 + u * uvs[b].1 + v * uvs[c].1,
+            ),
+            None => (u, v),
+        };
+        let mut material = s.material.clone();
+        if let Some(colors) = &s.colors {
+            material.color = w * colors[a] + u * colors[b] + v * colors[c];
+        }
+        Some(Hit::new(t, n, material).with_uv(uv.0, uv.1))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.surface.hierarchy.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(m: Material) -> Mesh {
+        let vertices = vec![ORIGIN, UNIT_X, Point::new(1.0, 1.0, 0.0), UNIT_Y];
+        let uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        Mesh::new(
+            vertices,
+            vec![vec![0, 1, 2, 3]],
+            m,
+            Some(uvs),
+            None,
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ray_hits_mesh() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let mesh = square(m.clone());
+        let hit = mesh
+            .hit_by(&Ray::new(Point::new(0.25, 0.75, 2.0), -UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-6);
+        assert_eq!(hit.normal, UNIT_Z);
+        assert!((hit.uv.0 - 0.25).abs() < 1e-6 && (hit.uv.1 - 0.75).abs() < 1e-6);
+
+        // Normals are interpolated between the vertices
+        let tent = Mesh::new(
+            vec![ORIGIN, UNIT_X, 2.0 * UNIT_X + UNIT_Z, UNIT_Y + UNIT_X],
+            vec![vec![0, 1, 3], vec![1, 2, 3]],
+            m.clone(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let n = tent
+            .hit_by(&Ray::new(Point::new(0.9, 0.2, 2.0), -UNIT_Z))
+            .unwrap()
+            .normal;
+        assert!(n.x < 0.0 && n.x > -0.5 && n.z > 0.0);
+        assert!(Mesh::new(
+            vec![ORIGIN],
+            vec![vec![0, 1, 2]],
+            m.clone(),
+            None,
+            None,
+            None,
+            None
+        )
+        .is_err());
+
+        let vertices = square(m.clone()).surface.vertices.clone();
+        let faces = vec![vec![0, 1, 2, 3]];
+        let smooth = Mesh::new(
+            vertices.clone(),
+            faces.clone(),
+            m.clone(),
+            None,
+            None,
+            Some("loop"),
+            None,
+        );
+        assert_eq!(smooth.unwrap().surface.faces.len(), 2 * 16);
+        assert!(Mesh::new(vertices, faces, m, None, None, Some("doo-sabin"), None).is_err());
+    }
+
+    #[test]
+    fn displacement_refines() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let mesh = square(m);
+        let raised = mesh.displaced(&Texture::constant(0.5), 2.0, None);
+        assert_eq!(raised.surface.vertices.len(), 4);
+        assert!(raised.surface.vertices.iter().all(|v| v.z == 1.0));
+
+        let fine = mesh.displaced(&Texture::constant(0.0), 1.0, Some(0.3));
+        let s = &fine.surface;
+        for f in &s.faces {
+            for k in 0..3 {
+                let edge = s.vertices[f[k]] - s.vertices[f[(k + 1) % 3]];
+                assert!(edge.norm() <= 0.3);
+            }
+        }
+        // Every edge is shared by two triangles, or lies on the border of the square
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for f in &s.faces {
+            for k in 0..3 {
+                let (a, b) = (f[k], f[(k + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        for ((a, b), count) in edges {
+            let (p, q) = (s.vertices[a], s.vertices[b]);
+            let border = (0..2).any(|i| p[i] == q[i] && (p[i] == 0.0 || p[i] == 1.0));
+            assert_eq!(count, if border { 1 } else { 2 });
+        }
+        let uv = s.uvs.as_ref().unwrap();
+        assert!(s
+            .vertices
+            .iter()
+            .zip(uv)
+            .all(|(p, uv)| (p.x - uv.0).abs() < 1e-6 && (p.y - uv.1).abs() < 1e-6));
+    }
+
+    #[test]
+    fn meshes_load() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let ascii = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+                     property float y\nproperty float z\nproperty uchar red\n\
+                     property uchar green\nproperty uchar blue\nelement face 1\n\
+                     property list uchar int vertex_indices\nend_header\n\
+                     0 0 0 255 0 0\n1 0 0 0 255 0\n0 1 0 0 0 255\n3 0 1 2\n";
+        let ply = Ply::parse(std::io::Cursor::new(ascii)).unwrap();
+        let mesh = Mesh::from_ply(&ply, m.clone()).unwrap();
+        let hit = mesh
+            .hit_by(&Ray::new(Point::new(0.5, 0.25, 1.0), -UNIT_Z))
+            .unwrap();
+        let c = hit.material.color;
+        assert!((c.r - 0.25).abs() < 1e-6 && (c.g - 0.5).abs() < 1e-6 && (c.b - 0.25).abs() < 1e-6);
+
+        let stl = Stl {
+            triangles: vec![
+                [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+                [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            ],
+        };
+        let mesh = Mesh::from_stl(&stl, m.clone());
+        // Shaded flat, as the triangles do not share vertices
+        let hit = mesh
+            .hit_by(&Ray::new(Point::new(0.2, 0.2, 1.0), -UNIT_Z))
+            .unwrap();
+        assert!((hit.normal - Point::new(1.0, 1.0, 1.0).normalized()).norm() < 1e-6);
+        assert!(Mesh::load("mesh.obj", m).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+/// How many field samples are taken along a ray per radius of the smallest blob it passes
+static STEPS_PER_RADIUS: f32 = 16.0;
+
+/// How often a bracketed root is halved
+static REFINEMENTS: usize = 24;
+
+/// The maximum number of blobs kept in a single leaf of the hierarchy
+static LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+struct Blob {
+    center: Point,
+    /// The distance beyond which the blob has no influence
+    radius: f32,
+    weight: f32,
+}
+
+impl Bounded for Blob {
+    fn bounds(&self) -> Bounds {
+        let r = Point::new(self.radius, self.radius, self.radius);
+        Bounds::new(self.center - r, self.center + r)
+    }
+}
+
+impl Blob {
+    /// Returns the distances at which a ray enters and leaves the sphere of influence
+    fn span(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let hypo = self.center - ray.base;
+        let dot = ray.direction * hypo;
+        let root = dot * dot - hypo.norm_sqr() + self.radius * self.radius;
+        if root < 0.0 {
+            return None;
+        }
+        let (near, far) = (dot - root.sqrt(), dot + root.sqrt());
+        if far <= 0.0 {
+            return None;
+        }
+        Some((near.max(0.0), far))
+    }
+}
+
+/// A blobby surface where the fields around several centers add up to a threshold
+///
+/// Each blob contributes `weight * (1 - d² / radius²)³` at a distance `d` from its center,
+/// falling smoothly to zero at its radius, so blobs close to each other merge.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Metaballs {
+    blobs: Vec<Blob>,
+    /// The hierarchy over the spheres of influence
+    hierarchy: Hierarchy,
+    threshold: f32,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Metaballs {
+    #[new]
+    fn py_new(blobs: Vec<(Point, f32, f32)>, threshold: f32, material: Material) -> PyResult<Self> {
+        Ok(Metaballs::new(blobs, threshold, material)?)
+    }
+}
+
+impl Metaballs {
+    /// Creates metaballs from a list of `(center, radius, weight)` tuples
+    pub fn new(blobs: Vec<(Point, f32, f32)>, threshold: f32, material: Material) -> Result<Self> {
+        if threshold <= 0.0 {
+            return Err(Error::Value("threshold must be positive".into()));
+        }
+        if blobs.iter().any(|b| b.1 <= 0.0) {
+            return Err(Error::Value("radii must be positive".into()));
+        }
+        let blobs: Vec<Blob> = blobs
+            .into_iter()
+            .map(|(center, radius, weight)| Blob {
+                center,
+                radius,
+                weight,
+            })
+            .collect();
+        Ok(Metaballs {
+            hierarchy: Hierarchy::new(&blobs, LEAF_SIZE),
+            blobs,
+            threshold,
+            material,
+        })
+    }
+
+    /// Returns the blobs whose spheres of influence the ray passes, with the distances at which
+    /// it enters and leaves them
+    fn spans(&self, ray: &Ray) -> Vec<(f32, f32, Blob)> {
+        let mut spans = Vec::new();
+        self.hierarchy.visit(ray, f32::INFINITY, |i| {
+            let b = self.blobs[i];
+            if let Some((near, far)) = b.span(ray) {
+                spans.push((near, far, b));
+            }
+            None
+        });
+        spans
+    }
+
+    /// Returns the field at a point, summed over some blobs
+    fn field(&self, blobs: &[Blob], p: Point) -> f32 {
+        blobs
+            .iter()
+            .map(|b| {
+                let s = (p - b.center).norm_sqr() / (b.radius * b.radius);
+                if s < 1.0 {
+                    b.weight * (1.0 - s).powi(3)
+                } else {
+                    0.0
+                }
+            })
+            .sum()
+    }
+
+    fn gradient(&self, blobs: &[Blob], p: Point) -> Point {
+        blobs.iter().fold(ORIGIN, |g, b| {
+            let r2 = b.radius * b.radius;
+            let s = (p - b.center).norm_sqr() / r2;
+            if s < 1.0 {
+                g + (-6.0 * b.weight * (1.0 - s).powi(2) / r2) * (p - b.center)
+            } else {
+                g
+            }
+        })
+    }
+}
+
+impl Thing for Metaballs {
+    /// Finds the first crossing of the threshold along the ray
+    ///
+    /// Only blobs whose spheres of influence the ray passes are considered, found through a
+    /// hierarchy over their bounds.  Within the spans
+    /// covered by them, the field is sampled in small steps until the ray crosses the threshold,
+    /// after which the crossing is refined by bisection.
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let mut spans = self.spans(ray);
+        if spans.is_empty() {
+            return None;
+        }
+        spans.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let blobs: Vec<Blob> = spans.iter().map(|s| s.2).collect();
+        let step = blobs.iter().map(|b| b.radius).fold(f32::INFINITY, f32::min) / STEPS_PER_RADIUS;
+        let level = |t: f32| self.field(&blobs, ray.at(t)) - self.threshold;
+
+        // The side the ray starts on, from the field where it first meets a blob
+        let inside = level(spans[0].0) > 0.0;
+        let mut t = spans[0].0;
+        let mut k = 0;
+        while k < spans.len() {
+            // Merge the spans overlapping the current one
+            let mut end = spans[k].1;
+            while k + 1 < spans.len() && spans[k + 1].0 <= end {
+                k += 1;
+                end = end.max(spans[k].1);
+            }
+            while t < end {
+                let next = (t + step).min(end);
+                if (level(next) > 0.0) != inside {
+                    let (mut low, mut high) = (t, next);
+                    for _ in 0..REFINEMENTS {
+                        let mid = 0.5 * (low + high);
+                        if (level(mid) > 0.0) == inside {
+                            low = mid;
+                        } else {
+                            high = mid;
+                        }
+                    }
+                    let normal = -self.gradient(&blobs, ray.at(high)).normalized();
+                    return Some(Hit::new(high, normal, self.material.clone()));
+                }
+                t = next;
+            }
+            k += 1;
+            if k < spans.len() {
+                t = spans[k].0;
+            }
+        }
+        None
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.hierarchy.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).norm() < 1e-3
+    }
+
+    #[test]
+    fn ray_hits_metaballs() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let single = Metaballs::new(vec![(ORIGIN, 2.0, 1.0)], 0.125, m.clone()).unwrap();
+        // (1 - s)³ = 1/8 gives s = 1/2, for a surface at a distance of 2 / √2
+        let surface = 2.0f32.sqrt();
+        let hit = single
+            .hit_by(&Ray::new(Point::new(-5.0, 0.0, 0.0), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - (5.0 - surface)).abs() < 1e-4);
+        assert!(close(hit.normal, -UNIT_X));
+        let inside = single.hit_by(&Ray::new(ORIGIN, UNIT_Y)).unwrap();
+        assert!((inside.distance - surface).abs() < 1e-4);
+        assert!(close(inside.normal, UNIT_Y));
+        assert!(single
+            .hit_by(&Ray::new(Point::new(-5.0, 1.5, 0.0), UNIT_X))
+            .is_none());
+
+        // Two blobs merge into a bridge where neither alone would be hit
+        let pair = Metaballs::new(
+            vec![(-1.5 * UNIT_X, 2.0, 1.0), (1.5 * UNIT_X, 2.0, 1.0)],
+            0.125,
+            m.clone(),
+        )
+        .unwrap();
+        let across = Ray::new(Point::new(0.0, -5.0, 0.0), UNIT_Y);
+        assert!(single
+            .hit_by(&Ray::new(Point::new(1.5, -5.0, 0.0), UNIT_Y))
+            .is_none());
+        let hit = pair.hit_by(&across).unwrap();
+        assert!(close(hit.normal, -UNIT_Y));
+        assert!(hit.distance < 5.0);
+        let far = Ray::new(Point::new(0.0, -5.0, 3.0), UNIT_Y);
+        assert!(pair.hit_by(&far).is_none());
+
+        let b = pair.bounds();
+        assert!(close(b.min, Point::new(-3.5, -2.0, -2.0)));
+
+        // A row of blobs spread over several leaves is hit where the ray meets it
+        let row: Vec<_> = (0..20)
+            .map(|i| (Point::new(i as f32 * 3.0, 0.0, 0.0), 1.0, 1.0))
+            .collect();
+        let row = Metaballs::new(row, 0.125, m.clone()).unwrap();
+        for i in 0..20 {
+            let x = i as f32 * 3.0;
+            let hit = row
+                .hit_by(&Ray::new(Point::new(x, -5.0, 0.0), UNIT_Y))
+                .unwrap();
+            assert!((hit.distance - (5.0 - 0.5f32.sqrt())).abs() < 1e-3);
+        }
+        assert!(row
+            .hit_by(&Ray::new(Point::new(1.5, -5.0, 0.0), UNIT_Y))
+            .is_none());
+        assert!(Metaballs::new(vec![(ORIGIN, 0.0, 1.0)], 0.5, m).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+//! Rough surfaces made of tiny mirrors, with normals following the GGX distribution
+//!
+//! Directions are sampled from the microfacet normals visible from the incoming direction, as
+//! described by Heitz in "Sampling the GGX Distribution of Visible Normals" (2018), which leaves
+//! only the Fresnel term and a ratio of shadowing terms as the weight of a sample.  The
+//! roughness is squared into the width of the distribution, so that surfaces look evenly
+//! rougher between zero and one.
+
+use crate::bsdf::*;
+use crate::error::{Error, Result};
+use crate::things::*;
+use rand::prelude::*;
+
+/// The width of the distribution of normals below which surfaces are perfectly smooth
+static SMOOTH: f32 = 1e-4;
+
+/// Complex indices of refraction of metals for red, green and blue light, as eta and k
+static METALS: [(&str, [f32; 3], [f32; 3]); 5] = [
+    ("gold", [0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+    ("copper", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+    ("aluminium", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+    ("aluminum", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+    ("silver", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+];
+
+/// A rough surface scattering light by the orientation of its microfacets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Microfacet {
+    /// A metal, tinting what it reflects by the Fresnel term of its complex index of refraction
+    Conductor {
+        eta: Color,
+        k: Color,
+        roughness: f32,
+    },
+    /// A transparent surface such as frosted glass, reflecting or refracting light
+    Dielectric { ior: f32, roughness: f32 },
+}
+
+impl Microfacet {
+    /// Returns a metal by name: gold, copper, aluminium or silver
+    pub fn metal(name: &str, roughness: f32) -> Result<Self> {
+        let (_, eta, k) = METALS
+            .iter()
+            .find(|(metal, _, _)| *metal == name)
+            .ok_or_else(|| Error::Value(format!("unknown metal {}", name)))?;
+        Ok(Microfacet::Conductor {
+            eta: Color::new(eta[0], eta[1], eta[2]),
+            k: Color::new(k[0], k[1], k[2]),
+            roughness,
+        })
+    }
+
+    /// Returns a metal reflecting the given color when looked at head-on
+    pub fn from_reflectance(reflectance: Color, roughness: f32) -> Self {
+        let eta = |r: f32| {
+            let r = r.clamp(0.0, 0.999).sqrt();
+            (1.0 + r) / (1.0 - r)
+        };
+        Microfacet::Conductor {
+            eta: Color::new(eta(reflectance.r), eta(reflectance.g), eta(reflectance.b)),
+            k: Color::black(),
+            roughness,
+        }
+    }
+
+    /// Returns how rough the surface is, between zero and one
+    pub fn roughness(&self) -> f32 {
+        match *self {
+            Microfacet::Conductor { roughness, .. } | Microfacet::Dielectric { roughness, .. } => {
+                roughness
+            }
+        }
+    }
+
+    /// Returns the surface as it is where it is hit, its roughness replaced by that there
+    fn at(&self, at: &Shading) -> Microfacet {
+        match (*self, at.roughness) {
+            (Microfacet::Conductor { eta, k, .. }, Some(roughness)) => {
+                Microfacet::Conductor { eta, k, roughness }
+            }
+            (Microfacet::Dielectric { ior, .. }, Some(roughness)) => {
+                Microfacet::Dielectric { ior, roughness }
+            }
+            (surface, None) => surface,
+        }
+    }
+
+    /// Returns a direction light arriving at the surface is scattered into, with its weight
+    ///
+    /// None if the light is absorbed, which happens when the sampled direction points into a
+    /// conductor or out of the side of a dielectric it should have passed through.
+    ///
+    /// # Arguments
+    ///
+    /// * `normal` - the outward surface normal where the surface was hit
+    /// * `direction` - the direction of the incoming ray
+    /// * `rng` - the random number generator to use
+    pub fn scatter(
+        &self,
+        normal: Point,
+        direction: Point,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Point, Color)> {
+        match *self {
+            Microfacet::Conductor { eta, k, roughness } => {
+                let frame = Frame::facing(normal, direction);
+                let wo = frame.local(-direction);
+                let alpha = roughness * roughness;
+                let m = visible_normal(wo, alpha, rng);
+                let wi = reflect(wo, m);
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                let cos = wo * m;
+                let fresnel = Color::new(
+                    fresnel_conductor(cos, eta.r, k.r),
+                    fresnel_conductor(cos, eta.g, k.g),
+                    fresnel_conductor(cos, eta.b, k.b),
+                );
+                Some((frame.world(wi), shadowing(wo, wi, alpha) * fresnel))
+            }
+            Microfacet::Dielectric { ior, roughness } => {
+                let (frame, eta) = Frame::refracting(normal, -direction, ior);
+                let wo = frame.local(-direction);
+                let alpha = roughness * roughness;
+                let m = visible_normal(wo, alpha, rng);
+                let cos = wo * m;
+                let wi = if rng.gen::<f32>() < fresnel_dielectric(cos, eta) {
+                    Some(reflect(wo, m)).filter(|wi| wi.z > 0.0)
+                } else {
+                    refract(wo, m, eta).filter(|wi| wi.z < 0.0)
+                }?;
+                let weight = shadowing(wo, wi, alpha);
+                Some((frame.world(wi), Color::new(weight, weight, weight)))
+            }
+        }
+    }
+}
+
+/// Microfacet surfaces are specular when smooth, with directions sampled as by `scatter`, and
+/// take the roughness where they are hit from textures.
+impl Bsdf for Microfacet {
+    fn sample(&self, at: &Shading, wo: Point, rng: &mut dyn RngCore) -> Option<Sample> {
+        let surface = self.at(at);
+        let (direction, weight) = surface.scatter(at.normal, -wo, rng)?;
+        Some(Sample {
+            direction,
+            weight,
+            specular: smooth(surface.roughness()),
+        })
+    }
+
+    fn eval(&self, at: &Shading, wo: Point, wi: Point) -> Color {
+        let surface = self.at(at);
+        if smooth(surface.roughness()) {
+            return Color::black();
+        }
+        match surface {
+            Microfacet::Conductor { eta, k, roughness } => {
+                let frame = Frame::facing(at.normal, -wo);
+                let (wo, wi) = (frame.local(wo), frame.local(wi));
+                let cos = wo * (wo + wi).normalized();
+                let value = glossy_local(wo, wi, roughness * roughness).0;
+                value
+                    * Color::new(
+                        fresnel_conductor(cos, eta.r, k.r),
+                        fresnel_conductor(cos, eta.g, k.g),
+                        fresnel_conductor(cos, eta.b, k.b),
+                    )
+            }
+            Microfacet::Dielectric { ior, roughness } => {
+                let (frame, eta) = Frame::refracting(at.normal, wo, ior);
+                let value = dielectric(frame.local(wo), frame.local(wi), eta, roughness.powi(2)).0;
+                Color::new(value, value, value)
+            }
+        }
+    }
+
+    fn pdf(&self, at: &Shading, wo: Point, wi: Point) -> f32 {
+        let surface = self.at(at);
+        if smooth(surface.roughness()) {
+            return 0.0;
+        }
+        match surface {
+            Microfacet::Conductor { roughness, .. } => glossy_pdf(at.normal, wo, wi, roughness),
+            Microfacet::Dielectric { ior, roughness } => {
+                let (frame, eta) = Frame::refracting(at.normal, wo, ior);
+                dielectric(frame.local(wo), frame.local(wi), eta, roughness.powi(2)).1
+            }
+        }
+    }
+}
+
+/// Whether a surface of a roughness is a perfect mirror
+pub(crate) fn smooth(roughness: f32) -> bool {
+    roughness * roughness < SMOOTH
+}
+
+/// Returns the value of a GGX reflection without the Fresnel term, and its sampling density,
+/// for local directions
+fn glossy_local(wo: Point, wi: Point, alpha: f32) -> (f32, f32) {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let h = (wo + wi).normalized();
+    let value = distribution(h, alpha) * masking(wo, wi, alpha) / (4.0 * wo.z * wi.z);
+    (value, visible_density(wo, h, alpha) / (4.0 * (wo * h)))
+}
+
+/// Returns the value of `glossy` for a pair of directions pointing away from the surface
+pub(crate) fn glossy_value(normal: Point, wo: Point, wi: Point, roughness: f32) -> f32 {
+    if smooth(roughness) {
+        return 0.0;
+    }
+    let frame = Frame::facing(normal, -wo);
+    glossy_local(frame.local(wo), frame.local(wi), roughness * roughness).0
+}
+
+/// Returns the density `glossy` samples a direction with
+pub(crate) fn glossy_pdf(normal: Point, wo: Point, wi: Point, roughness: f32) -> f32 {
+    if smooth(roughness) {
+        return 0.0;
+    }
+    let frame = Frame::facing(normal, -wo);
+    glossy_local(frame.local(wo), frame.local(wi), roughness * roughness).1
+}
+
+/// Returns a direction glossily reflected off a surface reflecting all light, with its weight
+///
+/// Used for the specular part of plain materials, their hardness taken as roughness.
+pub(crate) fn glossy(
+    normal: Point,
+    direction: Point,
+    roughness: f32,
+    rng: &mut dyn RngCore,
+) -> Option<(Point, f32)> {
+    let frame = Frame::facing(normal, direction);
+    let wo = frame.local(-direction);
+    let alpha = roughness * roughness;
+    let wi = reflect(wo, visible_normal(wo, alpha, rng));
+    if wi.z <= 0.0 {
+        return None;
+    }
+    Some((frame.world(wi), shadowing(wo, wi, alpha)))
+}
+
+/// An orthonormal basis with the surface normal along z
+pub(crate) struct Frame {
+    s: Point,
+    t: Point,
+    n: Point,
+}
+
+impl Frame {
+    /// Returns the basis around a normal, flipped to the side the ray is coming from
+    pub(crate) fn facing(normal: Point, direction: Point) -> Self {
+        let n = if normal * direction > 0.0 {
+            -normal
+        } else {
+            normal
+        };
+        let s = n.perpendicular();
+        Frame {
+            s,
+            t: n.cross(s),
+            n,
+        }
+    }
+
+    /// Returns the basis facing `wo`, with the ratio of the indices of refraction behind and
+    /// in front of the surface
+    pub(crate) fn refracting(normal: Point, wo: Point, ior: f32) -> (Self, f32) {
+        // light leaving the surface sees the inverse ratio of indices
+        let eta = if normal * wo > 0.0 { ior } else { 1.0 / ior };
+        (Frame::facing(normal, -wo), eta)
+    }
+
+    pub(crate) fn local(&self, v: Point) -> Point {
+        Point::new(v * self.s, v * self.t, v * self.n)
+    }
+
+    pub(crate) fn world(&self, v: Point) -> Point {
+        v.x * self.s + v.y * self.t + v.z * self.n
+    }
+}
+
+/// Samples a microfacet normal visible from a local direction
+pub(crate) fn visible_normal(wo: Point, alpha: f32, rng: &mut dyn RngCore) -> Point {
+    if alpha < SMOOTH {
+        return UNIT_Z;
+    }
+    // stretch the view to a hemisphere of unit roughness
+    let v = Point::new(alpha * wo.x, alpha * wo.y, wo.z).normalized();
+    let length = (v.x * v.x + v.y * v.y).sqrt();
+    let t1 = if length > 0.0 {
+        Point::new(-v.y / length, v.x / length, 0.0)
+    } else {
+        UNIT_X
+    };
+    let t2 = v.cross(t1);
+    // sample the projected disk, squeezed to the part of it that is visible
+    let r = rng.gen::<f32>().sqrt();
+    let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + v.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+    // and unstretch the normal found
+    Point::new(alpha * n.x, alpha * n.y, n.z.max(1e-6)).normalized()
+}
+
+/// The density of microfacets facing a local direction, over the projected surface
+pub(crate) fn distribution(m: Point, alpha: f32) -> f32 {
+    let cos2 = m.z * m.z;
+    if cos2 <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let tan2 = (1.0 - cos2) / cos2;
+    1.0 / (std::f32::consts::PI * a2 * cos2 * cos2 * (1.0 + tan2 / a2).powi(2))
+}
+
+/// The density `visible_normal` samples a microfacet normal with
+pub(crate) fn visible_density(wo: Point, m: Point, alpha: f32) -> f32 {
+    distribution(m, alpha) * (wo * m).max(0.0) / (wo.z * (1.0 + lambda(wo, alpha)))
+}
+
+/// Smith's auxiliary function for the GGX distribution
+fn lambda(w: Point, alpha: f32) -> f32 {
+    let cos2 = w.z * w.z;
+    if cos2 >= 1.0 {
+        return 0.0;
+    }
+    let tan2 = (1.0 - cos2) / cos2;
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+/// The share of microfacets seen from both directions
+pub(crate) fn masking(wo: Point, wi: Point, alpha: f32) -> f32 {
+    1.0 / (1.0 + lambda(wo, alpha) + lambda(wi, alpha))
+}
+
+/// The share of light reaching both directions of the light reaching the outgoing one
+pub(crate) fn shadowing(wo: Point, wi: Point, alpha: f32) -> f32 {
+    (1.0 + lambda(wo, alpha)) / (1.0 + lambda(wo, alpha) + lambda(wi, alpha))
+}
+
+pub(crate) fn reflect(wo: Point, m: Point) -> Point {
+    2.0 * (wo * m) * m - wo
+}
+
+/// Returns the direction light is refracted into, None for total internal reflection
+pub(crate) fn refract(wo: Point, m: Point, eta: f32) -> Option<Point> {
+    let cos_in = wo * m;
+    let sin2_out = (1.0 - cos_in * cos_in) / (eta * eta);
+    if sin2_out >= 1.0 {
+        return None;
+    }
+    let cos_out = (1.0 - sin2_out).sqrt();
+    Some((-wo / eta + (cos_in / eta - cos_out) * m).normalized())
+}
+
+/// The share of light reflected by a dielectric, eta being the ratio of the indices
+pub(crate) fn fresnel_dielectric(cos_in: f32, eta: f32) -> f32 {
+    let sin2_out = (1.0 - cos_in * cos_in) / (eta * eta);
+    if sin2_out >= 1.0 {
+        return 1.0;
+    }
+    let cos_out = (1.0 - sin2_out).sqrt();
+    let s = (cos_in - eta * cos_out) / (cos_in + eta * cos_out);
+    let p = (eta * cos_in - cos_out) / (eta * cos_in + cos_out);
+    0.5 * (s * s + p * p)
+}
+
+/// Returns the value of a rough dielectric for a pair of local directions, with the density
+/// `Microfacet::scatter` samples the incoming one with
+///
+/// The outgoing direction `wo` is above the surface, with `eta` the ratio of the index below
+/// to the index above.
+pub(crate) fn dielectric(wo: Point, wi: Point, eta: f32, alpha: f32) -> (f32, f32) {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return (0.0, 0.0);
+    }
+    let reflected = wi.z > 0.0;
+    // the microfacet normal turning one direction into the other
+    let m = if reflected { wi + wo } else { eta * wi + wo };
+    if m.norm_sqr() == 0.0 {
+        return (0.0, 0.0);
+    }
+    let m = if m.z < 0.0 { -m } else { m }.normalized();
+    if (m * wi) * wi.z < 0.0 || m * wo < 0.0 {
+        return (0.0, 0.0);
+    }
+    let fresnel = fresnel_dielectric(wo * m, eta);
+    let d = distribution(m, alpha);
+    let g = masking(wo, wi, alpha);
+    let density = visible_density(wo, m, alpha);
+    if reflected {
+        let value = d * g * fresnel / (4.0 * wi.z * wo.z);
+        (value, density * fresnel / (4.0 * (wo * m)))
+    } else {
+        let denominator = (wi * m + (wo * m) / eta).powi(2);
+        let value =
+            d * g * (1.0 - fresnel) * ((wi * m) * (wo * m) / (denominator * wi.z * wo.z)).abs();
+        (
+            value,
+            density * (1.0 - fresnel) * (wi * m).abs() / denominator,
+        )
+    }
+}
+
+/// The share of light reflected by a conductor, for one channel of its index of refraction
+fn fresnel_conductor(cos_in: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_in * cos_in;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * cos_in * a;
+    let s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let p = s * (t3 - t4) / (t3 + t4);
+    0.5 * (s + p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    #[test]
+    fn metals_reflect() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let gold = Microfacet::metal("gold", 0.0).unwrap();
+        let (out, tint) = gold.scatter(UNIT_Z, -UNIT_Z, &mut rng).unwrap();
+        assert!((out - UNIT_Z).norm() < 1e-6);
+        // gold reflects red light best, and at normal incidence as a plain dielectric would
+        assert!(tint.r > tint.g && tint.g > tint.b);
+        let (n, k) = (0.143f32, 3.983f32);
+        let head_on = ((n - 1.0).powi(2) + k * k) / ((n + 1.0).powi(2) + k * k);
+        assert!((tint.r - head_on).abs() < 1e-4);
+        assert!(Microfacet::metal("unobtainium", 0.0).is_err());
+
+        let red = Microfacet::from_reflectance(Color::new(0.9, 0.1, 0.1), 0.0);
+        let (_, tint) = red.scatter(UNIT_Z, -UNIT_Z, &mut rng).unwrap();
+        assert!((tint.r - 0.9).abs() < 1e-3 && (tint.g - 0.1).abs() < 1e-3);
+
+        // rough surfaces spread reflections around the mirror direction, losing the light
+        // scattered more than once between microfacets: 11% at a roughness of 0.5 and 45 degrees
+        let rough = Microfacet::metal("aluminium", 0.5).unwrap();
+        let direction = Point::new(1.0, 0.0, -1.0).normalized();
+        for _ in 0..100 {
+            if let Some((out, tint)) = rough.scatter(UNIT_Z, direction, &mut rng) {
+                assert!(out.z > 0.0);
+                assert!(tint.r < 0.93);
+            }
+        }
+        let total: f32 = (0..10000)
+            .filter_map(|_| glossy(UNIT_Z, direction, 0.5, &mut rng))
+            .map(|(_, weight)| weight)
+            .sum();
+        assert!((total / 10000.0 - 0.886).abs() < 0.01, "{}", total);
+    }
+
+    #[test]
+    fn dielectrics_refract() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let glass = Microfacet::Dielectric {
+            ior: 1.5,
+            roughness: 0.0,
+        };
+        let direction = Point::new(1.0, 0.0, -1.0).normalized();
+        let (mut reflected, mut refracted) = (0, 0);
+        for _ in 0..1000 {
+            let (out, tint) = glass.scatter(UNIT_Z, direction, &mut rng).unwrap();
+            assert_eq!(tint, Color::white());
+            if out.z > 0.0 {
+                reflected += 1;
+            } else {
+                // Snell's law
+                assert!((out.x * 1.5 - direction.x).abs() < 1e-5);
+                refracted += 1;
+            }
+        }
+        // about 5% of the light is reflected at 45 degrees
+        assert!(reflected > 20 && reflected < 90, "{}", reflected);
+        assert!(refracted > 900);
+
+        // light inside beyond the critical angle is always reflected
+        let inside = Point::new(1.0, 0.0, 0.5).normalized();
+        for _ in 0..10 {
+            let (out, _) = glass.scatter(UNIT_Z, inside, &mut rng).unwrap();
+            assert!(out.z < 0.0);
+        }
+
+        let frosted = Microfacet::Dielectric {
+            ior: 1.5,
+            roughness: 0.5,
+        };
+        let through = (0..1000)
+            .filter_map(|_| frosted.scatter(UNIT_Z, -UNIT_Z, &mut rng))
+            .filter(|(out, _)| out.z < 0.0)
+            .count();
+        assert!(through > 850, "{}", through);
+    }
+}
@@ -0,0 +1,1004 @@
+//! Import of the parts of pbrt-v3 and pbrt-v4 scene files the renderer can show
+//!
+//! Supported are the transformation and attribute directives, perspective cameras with their
+//! lens, the film resolution and file name, the samples per pixel and the path depth, spheres,
+//! triangle meshes, Loop subdivision surfaces and PLY meshes, object instances, named
+//! materials, diffuse area lights and uniform infinite lights.  Other directives and shapes are
+//! skipped, so that larger scenes still render with what is left.
+//!
+//! Materials are approximated by `Material`s: diffuse, plastic and coated materials become
+//! diffuse, while glass, mirrors and metals become microfacet surfaces.  Spectra are reduced to their
+//! average, blackbody emission becomes white and textured parameters fall back to defaults.
+
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::instance::*;
+use crate::mesh::*;
+use crate::microfacet::Microfacet;
+use crate::ply::*;
+use crate::scene::*;
+use crate::scenefile::RenderDescription;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How deeply included files may include others, to catch files including themselves
+static MAX_INCLUDE_DEPTH: usize = 32;
+
+/// The metals pbrt has named spectra for, by their chemical symbol
+static METALS: [(&str, &str); 4] = [
+    ("Au", "gold"),
+    ("Cu", "copper"),
+    ("Al", "aluminium"),
+    ("Ag", "silver"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// The name of a directive, such as `Shape`
+    Word(String),
+    Str(String),
+    /// A number, kept precise enough for large integers such as mesh indices
+    Number(f64),
+    Bool(bool),
+    Open,
+    Close,
+}
+
+/// Splits a scene file into tokens, leaving out comments
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '#' => while !matches!(chars.next(), Some('\n') | None) {},
+            '[' | ']' => {
+                chars.next();
+                tokens.push(if c == '[' { Token::Open } else { Token::Close });
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(Error::Value("unterminated string".into())),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "[]\"#".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "true" => Token::Bool(true),
+                    "false" => Token::Bool(false),
+                    _ => match word.parse() {
+                        Ok(number) => Token::Number(number),
+                        Err(_) if word.starts_with(char::is_alphabetic) => Token::Word(word),
+                        Err(_) => return Err(Error::Value(format!("unexpected {}", word))),
+                    },
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A directive with the tokens following it
+struct Statement {
+    name: String,
+    args: Vec<Token>,
+}
+
+/// Groups tokens into statements, each starting with the name of its directive
+fn statements(tokens: Vec<Token>) -> Result<Vec<Statement>> {
+    let mut statements: Vec<Statement> = Vec::new();
+    for token in tokens {
+        match (token, statements.last_mut()) {
+            (Token::Word(name), _) => statements.push(Statement {
+                name,
+                args: Vec::new(),
+            }),
+            (token, Some(statement)) => statement.args.push(token),
+            (token, None) => return Err(Error::Value(format!("{:?} before any directive", token))),
+        }
+    }
+    Ok(statements)
+}
+
+impl Statement {
+    /// Returns the given number of plain numbers, optionally in brackets, as in `Translate`
+    fn numbers(&self, count: usize) -> Result<Vec<f32>> {
+        let numbers: Vec<f32> = self
+            .args
+            .iter()
+            .filter_map(|t| match t {
+                Token::Number(n) => Some(*n as f32),
+                _ => None,
+            })
+            .collect();
+        if numbers.len() != count {
+            return Err(Error::Value(format!(
+                "{} needs {} numbers",
+                self.name, count
+            )));
+        }
+        Ok(numbers)
+    }
+
+    /// Returns the leading strings, such as the type of a shape, and the parameters after them
+    fn strings(&self, count: usize) -> Result<(Vec<String>, Params)> {
+        let mut strings = Vec::new();
+        for token in self.args.iter().take(count) {
+            match token {
+                Token::Str(s) => strings.push(s.clone()),
+                _ => return Err(Error::Value(format!("{} needs {} names", self.name, count))),
+            }
+        }
+        if strings.len() != count {
+            return Err(Error::Value(format!("{} needs {} names", self.name, count)));
+        }
+        Ok((strings, Params::parse(&self.args[count..])?))
+    }
+}
+
+/// Parameters of a directive by name, with their declared type and values
+struct Params(HashMap<String, (String, Vec<Token>)>);
+
+impl Params {
+    /// Parses parameter lists such as `"float radius" [2]` or `"rgb Kd" [1 0 0]`
+    fn parse(tokens: &[Token]) -> Result<Self> {
+        let mut params = HashMap::new();
+        let mut tokens = tokens.iter();
+        while let Some(token) = tokens.next() {
+            let declaration = match token {
+                Token::Str(s) => s,
+                _ => {
+                    return Err(Error::Value(format!(
+                        "expected a parameter, not {:?}",
+                        token
+                    )))
+                }
+            };
+            let words: Vec<&str> = declaration.split_whitespace().collect();
+            if words.len() != 2 {
+                return Err(Error::Value(format!("bad parameter \"{}\"", declaration)));
+            }
+            let values = match tokens.next() {
+                Some(Token::Open) => {
+                    let mut values = Vec::new();
+                    loop {
+                        match tokens.next() {
+                            Some(Token::Close) => break,
+                            Some(Token::Open) | None => {
+                                return Err(Error::Value(format!("unclosed {}", words[1])))
+                            }
+                            Some(value) => values.push(value.clone()),
+                        }
+                    }
+                    values
+                }
+                Some(value) => vec![value.clone()],
+                None => return Err(Error::Value(format!("no value for {}", words[1]))),
+            };
+            params.insert(words[1].to_string(), (words[0].to_string(), values));
+        }
+        Ok(Params(params))
+    }
+
+    fn numbers(&self, name: &str) -> Option<Vec<f32>> {
+        let (_, values) = self.0.get(name)?;
+        values
+            .iter()
+            .map(|v| match v {
+                Token::Number(n) => Some(*n as f32),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns a list of indices, without rounding them through single precision
+    fn indices(&self, name: &str) -> Result<Option<Vec<usize>>> {
+        let (_, values) = match self.0.get(name) {
+            Some(param) => param,
+            None => return Ok(None),
+        };
+        values
+            .iter()
+            .map(|v| match v {
+                Token::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+                _ => Err(Error::Value(format!(
+                    "{} needs non-negative integers",
+                    name
+                ))),
+            })
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+
+    /// Returns a list of pairs, such as surface coordinates
+    fn pairs(&self, name: &str) -> Result<Option<Vec<(f32, f32)>>> {
+        match self.numbers(name) {
+            Some(numbers) if numbers.len() % 2 == 0 => Ok(Some(
+                numbers.chunks_exact(2).map(|p| (p[0], p[1])).collect(),
+            )),
+            Some(_) => Err(Error::Value(format!("{} needs pairs of numbers", name))),
+            None => Ok(None),
+        }
+    }
+
+    fn float(&self, name: &str, default: f32) -> f32 {
+        match self.numbers(name).as_deref() {
+            Some([value]) => *value,
+            _ => default,
+        }
+    }
+
+    fn bool(&self, name: &str, default: bool) -> bool {
+        match self.0.get(name) {
+            Some((_, values)) => match values.first() {
+                Some(Token::Bool(value)) => *value,
+                // pbrt-v3 quotes them
+                Some(Token::Str(value)) => value == "true",
+                _ => default,
+            },
+            None => default,
+        }
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.0.get(name) {
+            Some((_, values)) => match values.first() {
+                Some(Token::Str(s)) => Some(s),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    /// Returns a list of points, such as the positions of a mesh
+    fn points(&self, name: &str) -> Result<Option<Vec<Point>>> {
+        match self.numbers(name) {
+            Some(numbers) if numbers.len() % 3 == 0 => Ok(Some(
+                numbers
+                    .chunks(3)
+                    .map(|p| Point::new(p[0], p[1], p[2]))
+                    .collect(),
+            )),
+            Some(_) => Err(Error::Value(format!("{} needs triples of numbers", name))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a color given as RGB, a single value or a spectrum, None for textures
+    fn color(&self, name: &str) -> Option<Color> {
+        let (kind, _) = self.0.get(name)?;
+        let numbers = self.numbers(name);
+        match (kind.as_str(), numbers.as_deref()) {
+            ("rgb", Some([r, g, b])) | ("color", Some([r, g, b])) => Some(Color::new(*r, *g, *b)),
+            ("float", Some([v])) => Some(Color::new(*v, *v, *v)),
+            ("spectrum", Some(samples)) if !samples.is_empty() && samples.len() % 2 == 0 => {
+                // pairs of wavelength and value
+                let values: Vec<f32> = samples.iter().skip(1).step_by(2).cloned().collect();
+                let mean = values.iter().sum::<f32>() / values.len() as f32;
+                Some(Color::new(mean, mean, mean))
+            }
+            ("blackbody", _) => Some(Color::white()),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the roughness of a microfacet surface, which pbrt-v4 remaps by a square root
+fn roughness(params: &Params) -> f32 {
+    let roughness = params.float("roughness", params.float("uroughness", 0.0));
+    let alpha = if params.bool("remaproughness", true) {
+        roughness.sqrt()
+    } else {
+        roughness
+    };
+    alpha.sqrt()
+}
+
+/// Returns a metal, from its reflectance or its complex index of refraction
+fn conductor(params: &Params) -> Microfacet {
+    let roughness = roughness(params);
+    if let Some(color) = params.color("reflectance") {
+        return Microfacet::from_reflectance(color, roughness);
+    }
+    if let (Some(eta), Some(k)) = (params.color("eta"), params.color("k")) {
+        return Microfacet::Conductor { eta, k, roughness };
+    }
+    // named spectra such as "metal-Au-eta", pbrt defaulting to copper
+    let named = params.string("eta").unwrap_or("metal-Cu-eta");
+    let (_, metal) = METALS
+        .iter()
+        .find(|(symbol, _)| named.contains(&format!("-{}-", symbol)))
+        .unwrap_or(&METALS[1]);
+    Microfacet::metal(metal, roughness).expect("known metal")
+}
+
+/// Approximates a pbrt material, None for interfaces between media that are not drawn
+fn material(kind: &str, params: &Params) -> Option<Material> {
+    let gray = |v| Color::new(v, v, v);
+    Some(match kind {
+        "interface" | "none" | "" => return None,
+        "glass" | "dielectric" | "thindielectric" => {
+            let ior = params.float("eta", params.float("index", 1.5));
+            Material::dielectric(ior, roughness(params))
+        }
+        "mirror" => {
+            let color = params.color("Kr").unwrap_or_else(|| gray(0.9));
+            Material::microfacet(Microfacet::from_reflectance(color, 0.0))
+        }
+        "metal" | "conductor" => Material::microfacet(conductor(params)),
+        _ => {
+            let color = params
+                .color("Kd")
+                .or_else(|| params.color("reflectance"))
+                .unwrap_or_else(|| gray(0.5));
+            Material::new(0.0, 0.0, 1.0, 0.0, 0.0, color)
+        }
+    })
+}
+
+/// Returns a material emitting light of a color, scaled to the brightest channel
+fn emitter(light: Color) -> Material {
+    let peak = light.r.max(light.g).max(light.b);
+    if peak > 0.0 {
+        Material::new(0.0, 0.0, 0.0, 0.0, peak, light / peak)
+    } else {
+        Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black())
+    }
+}
+
+/// Returns the transformation into camera space of a `LookAt` directive
+fn look_at(v: &[f32]) -> Result<Transform> {
+    let eye = Point::new(v[0], v[1], v[2]);
+    let direction = (Point::new(v[3], v[4], v[5]) - eye).normalized();
+    let right = Point::new(v[6], v[7], v[8]).normalized().cross(direction);
+    if !right.norm().is_normal() {
+        return Err(Error::Value(
+            "LookAt with an up vector along the view".into(),
+        ));
+    }
+    let right = right.normalized();
+    let up = direction.cross(right);
+    let mut rows = [[0.0; 4]; 3];
+    for (i, row) in rows.iter_mut().enumerate() {
+        *row = [right[i], up[i], direction[i], eye[i]];
+    }
+    Transform::from_matrix(rows)
+        .map(|t| t.inverted())
+        .ok_or_else(|| Error::Value("LookAt at the eye".into()))
+}
+
+/// Returns the transformation of a 4x4 matrix, which pbrt lists column by column
+fn matrix(v: &[f32]) -> Result<Transform> {
+    Transform::new(
+        (0..4)
+            .map(|i| (0..4).map(|j| v[4 * j + i]).collect())
+            .collect(),
+    )
+}
+
+/// What the attribute directives save and restore
+#[derive(Clone)]
+struct State {
+    /// The transformation of objects into the world, or into the camera before `WorldBegin`
+    transform: Transform,
+    /// The material of shapes, None for interfaces that are not drawn
+    material: Option<Material>,
+    /// The light emitted by shapes, if they are area lights
+    emission: Option<Color>,
+}
+
+/// Runs the directives of a scene file, collecting things and settings
+struct Importer {
+    directory: PathBuf,
+    state: State,
+    stack: Vec<State>,
+    materials: HashMap<String, Option<Material>>,
+    coordinates: HashMap<String, Transform>,
+    objects: HashMap<String, SharedThing>,
+    /// The name and things of the object being defined
+    object: Option<(String, Vec<SharedThing>)>,
+    /// The files being included, innermost last
+    includes: Vec<PathBuf>,
+    /// The camera transformation and type, with its parameters
+    camera: Option<(Transform, Params)>,
+    resolution: (u32, u32),
+    output: String,
+    samples: Option<usize>,
+    depth: usize,
+    environment: Option<Color>,
+    things: Vec<SharedThing>,
+}
+
+impl Importer {
+    fn new(directory: &Path) -> Self {
+        Importer {
+            directory: directory.to_path_buf(),
+            state: State {
+                transform: Transform::identity(),
+                material: material("diffuse", &Params(HashMap::new())),
+                emission: None,
+            },
+            stack: Vec::new(),
+            materials: HashMap::new(),
+            coordinates: HashMap::new(),
+            objects: HashMap::new(),
+            object: None,
+            includes: Vec::new(),
+            camera: None,
+            resolution: (640, 480),
+            output: "render.png".to_string(),
+            samples: None,
+            depth: 5,
+            environment: None,
+            things: Vec::new(),
+        }
+    }
+
+    fn run(&mut self, text: &str) -> Result<()> {
+        for statement in statements(tokenize(text)?)? {
+            self.statement(&statement)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a thing to the object being defined, or to the scene
+    fn add(&mut self, thing: SharedThing) {
+        match &mut self.object {
+            Some((_, things)) => things.push(thing),
+            None => self.things.push(thing),
+        }
+    }
+
+    /// Applies a transformation before the current one
+    fn apply(&mut self, transform: Transform) {
+        self.state.transform = transform.then(self.state.transform);
+    }
+
+    fn statement(&mut self, s: &Statement) -> Result<()> {
+        match s.name.as_str() {
+            "Identity" => self.state.transform = Transform::identity(),
+            "Translate" => {
+                let v = s.numbers(3)?;
+                self.apply(Transform::translation(Point::new(v[0], v[1], v[2])));
+            }
+            "Scale" => {
+                let v = s.numbers(3)?;
+                self.apply(Transform::scaling(v[0], v[1], v[2])?);
+            }
+            "Rotate" => {
+                let v = s.numbers(4)?;
+                let axis = Point::new(v[1], v[2], v[3]);
+                self.apply(Transform::rotation(axis, v[0].to_radians()));
+            }
+            "LookAt" => self.apply(look_at(&s.numbers(9)?)?),
+            "Transform" => self.state.transform = matrix(&s.numbers(16)?)?,
+            "ConcatTransform" => self.apply(matrix(&s.numbers(16)?)?),
+            "CoordinateSystem" => {
+                let (names, _) = s.strings(1)?;
+                self.coordinates
+                    .insert(names[0].clone(), self.state.transform);
+            }
+            "CoordSysTransform" => {
+                let (names, _) = s.strings(1)?;
+                self.state.transform = *self.coordinates.get(&names[0]).ok_or_else(|| {
+                    Error::Value(format!("unknown coordinate system {}", names[0]))
+                })?;
+            }
+            "AttributeBegin" | "TransformBegin" => self.stack.push(self.state.clone()),
+            "AttributeEnd" | "TransformEnd" => {
+                let saved = self
+                    .stack
+                    .pop()
+                    .ok_or_else(|| Error::Value(format!("unmatched {}", s.name)))?;
+                if s.name == "AttributeEnd" {
+                    self.state = saved;
+                } else {
+                    self.state.transform = saved.transform;
+                }
+            }
+            "Camera" => {
+                let (_, params) = s.strings(1)?;
+                self.coordinates
+                    .insert("camera".to_string(), self.state.transform.inverted());
+                self.camera = Some((self.state.transform, params));
+            }
+            "Film" => {
+                let (_, params) = s.strings(1)?;
+                let (width, height) = self.resolution;
+                self.resolution = (
+                    params.float("xresolution", width as f32) as u32,
+                    params.float("yresolution", height as f32) as u32,
+                );
+                if let Some(filename) = params.string("filename") {
+                    let path = Path::new(filename);
+                    self.output = match path.extension().and_then(|e| e.to_str()) {
+                        Some("png") | Some("jpg") | Some("jpeg") => filename.to_string(),
+                        _ => path.with_extension("png").to_string_lossy().into_owned(),
+                    };
+                }
+            }
+            "Sampler" => {
+                let (_, params) = s.strings(1)?;
+                self.samples = params.numbers("pixelsamples").and_then(|n| match n[..] {
+                    [samples] => Some(samples as usize),
+                    _ => None,
+                });
+            }
+            "Integrator" => {
+                let (_, params) = s.strings(1)?;
+                self.depth = params.float("maxdepth", 5.0) as usize;
+            }
+            "WorldBegin" => self.state.transform = Transform::identity(),
+            "Material" => {
+                let (kinds, params) = s.strings(1)?;
+                self.state.material = material(&kinds[0], &params);
+            }
+            "MakeNamedMaterial" => {
+                let (names, params) = s.strings(1)?;
+                let kind = params.string("type").unwrap_or("diffuse");
+                self.materials
+                    .insert(names[0].clone(), material(kind, &params));
+            }
+            "NamedMaterial" => {
+                let (names, _) = s.strings(1)?;
+                self.state.material = self
+                    .materials
+                    .get(&names[0])
+                    .ok_or_else(|| Error::Value(format!("unknown material {}", names[0])))?
+                    .clone();
+            }
+            "AreaLightSource" => {
+                let (_, params) = s.strings(1)?;
+                let light = params.color("L").unwrap_or_else(Color::white);
+                self.state.emission = Some(params.float("scale", 1.0) * light);
+            }
+            "LightSource" => {
+                let (kinds, params) = s.strings(1)?;
+                if kinds[0] == "infinite" {
+                    let light = params.color("L").unwrap_or_else(Color::white);
+                    self.environment = Some(params.float("scale", 1.0) * light);
+                }
+            }
+            "Shape" => {
+                let (kinds, params) = s.strings(1)?;
+                if let Some(thing) = self.shape(&kinds[0], &params)? {
+                    self.add(thing);
+                }
+            }
+            "ObjectBegin" => {
+                let (names, _) = s.strings(1)?;
+                self.stack.push(self.state.clone());
+                self.object = Some((names[0].clone(), Vec::new()));
+            }
+            "ObjectEnd" => {
+                let (name, things) = self
+                    .object
+                    .take()
+                    .ok_or_else(|| Error::Value("unmatched ObjectEnd".into()))?;
+                self.objects
+                    .insert(name, Geometry::from_things(things).shared());
+                self.state = self
+                    .stack
+                    .pop()
+                    .ok_or_else(|| Error::Value("unmatched ObjectEnd".into()))?;
+            }
+            "ObjectInstance" => {
+                let (names, _) = s.strings(1)?;
+                let object = self
+                    .objects
+                    .get(&names[0])
+                    .ok_or_else(|| Error::Value(format!("unknown object {}", names[0])))?;
+                let instance = Instance::place(object.clone(), self.state.transform, None);
+                self.add(Arc::new(instance));
+            }
+            "Include" | "Import" => {
+                let (names, _) = s.strings(1)?;
+                let path = self.directory.join(&names[0]);
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| Error::Io(format!("{}: {}", path.display(), e)))?;
+                let path = path.canonicalize().unwrap_or(path);
+                if self.includes.contains(&path) {
+                    return Err(Error::Value(format!("{} includes itself", path.display())));
+                }
+                if self.includes.len() >= MAX_INCLUDE_DEPTH {
+                    return Err(Error::Value(format!(
+                        "{} is included too deeply",
+                        path.display()
+                    )));
+                }
+                self.includes.push(path);
+                let result = self.run(&text);
+                self.includes.pop();
+                result?;
+            }
+            _ => {} // everything the renderer cannot show
+        }
+        Ok(())
+    }
+
+    /// Converts a shape, None if it is not drawn or not supported
+    fn shape(&self, kind: &str, params: &Params) -> Result<Option<SharedThing>> {
+        let material = match (self.state.emission, self.state.material.clone()) {
+            (Some(light), _) => emitter(light),
+            (None, Some(material)) => material,
+            (None, None) => return Ok(None),
+        };
+        let transform = self.state.transform;
+        let missing = |what: &str| Error::Value(format!("{} without {}", kind, what));
+        Ok(Some(match kind {
+            "sphere" => {
+                let sphere = Sphere::new(ORIGIN, params.float("radius", 1.0), material);
+                Arc::new(Instance::place(Arc::new(sphere), transform, None))
+            }
+            "trianglemesh" | "loopsubdiv" => {
+                let positions = params.points("P")?.ok_or_else(|| missing("positions"))?;
+                let vertices: Vec<Point> = positions.iter().map(|p| transform.point(*p)).collect();
+                let indices = match params.indices("indices")? {
+                    Some(indices) => indices,
+                    None if vertices.len() == 3 => vec![0, 1, 2],
+                    None => return Err(missing("indices")),
+                };
+                if indices.len() % 3 != 0 {
+                    return Err(Error::Value(format!("{} needs triples of indices", kind)));
+                }
+                let faces = indices.chunks_exact(3).map(|face| face.to_vec()).collect();
+                let uvs = match params.pairs("uv")? {
+                    Some(uvs) => Some(uvs),
+                    None => params.pairs("st")?,
+                };
+                if kind == "loopsubdiv" {
+                    let levels = params.float("levels", 3.0) as usize;
+                    let mesh = Mesh::new(
+                        vertices,
+                        faces,
+                        material,
+                        uvs,
+                        None,
+                        Some("loop"),
+                        Some(levels),
+                    );
+                    Arc::new(mesh?)
+                } else {
+                    let normals = params
+                        .points("N")?
+                        .map(|normals| normals.iter().map(|n| transform.normal(*n)).collect());
+                    Arc::new(Mesh::validated(
+                        vertices, faces, material, uvs, normals, None,
+                    )?)
+                }
+            }
+            "plymesh" => {
+                let filename = params
+                    .string("filename")
+                    .ok_or_else(|| missing("filename"))?;
+                let path = self.directory.join(filename);
+                let name = path.to_string_lossy();
+                let ply = Ply::open(&name).map_err(|e| Error::Io(format!("{}: {}", name, e)))?;
+                let mesh = Mesh::from_ply(&ply, material)
+                    .map_err(|e| Error::Value(format!("{}: {}", name, e)))?;
+                Arc::new(Instance::place(Arc::new(mesh), transform, None))
+            }
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Returns the camera, pbrt's default one looking along z if the file has none
+    fn camera(&self) -> Camera {
+        let (from_world, params) = match &self.camera {
+            Some((transform, params)) => (*transform, Some(params)),
+            None => (Transform::identity(), None),
+        };
+        let float = |name, default| params.map_or(default, |p| p.float(name, default));
+        let to_world = from_world.inverted();
+        let eye = to_world.point(ORIGIN);
+        let forward = to_world.vector(UNIT_Z).normalized();
+        let right = to_world.vector(UNIT_X).normalized();
+        let down = to_world.vector(-UNIT_Y).normalized();
+        // the screen is one unit wide, the field of view spanning its shorter side
+        let (width, height) = self.resolution;
+        let aspect = height as f32 / width as f32;
+        let fov = float("fov", 90.0).to_radians();
+        let distance = 0.5 * aspect.min(1.0) / (0.5 * fov).tan();
+        let normal = Ray::new(eye + distance * forward, forward);
+        let camera = Camera::oriented(normal, right, aspect * down, distance);
+        match float("lensradius", 0.0) {
+            radius if radius > 0.0 => {
+                let focus = float("focaldistance", 1e6) - distance;
+                camera.with_lens(Lens::new(focus, radius))
+            }
+            _ => camera,
+        }
+    }
+
+    /// Returns a large sphere around all things, glowing with the infinite light
+    fn environment(&self, light: Color) -> SharedThing {
+        let bounds = self
+            .things
+            .iter()
+            .fold(Bounds::empty(), |b, t| b.union(t.bounds()));
+        let (center, radius) = if bounds.is_finite() {
+            (
+                bounds.center(),
+                10.0 * (bounds.max - bounds.min).norm() + 1.0,
+            )
+        } else {
+            (ORIGIN, 1e4)
+        };
+        Arc::new(Sphere::new(center, radius, emitter(light)))
+    }
+}
+
+/// The contents of a pbrt scene file, ready to be rendered
+///
+/// The render settings come from the film, sampler and integrator of the file and can be
+/// changed before rendering.  The camera screen is one unit wide, rendered at as many dots
+/// per unit as the film is wide.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct PbrtScene {
+    pub camera: Camera,
+    pub things: Vec<SharedThing>,
+    pub render: RenderDescription,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl PbrtScene {
+    /// Loads a scene, together with the files it includes and the PLY meshes it refers to
+    #[staticmethod]
+    #[name = "load"]
+    fn py_load(filename: &str) -> PyResult<Self> {
+        Ok(PbrtScene::load(filename)?)
+    }
+
+    /// Renders the scene into the output file of its render settings
+    #[name = "render"]
+    fn py_render(&self) -> PyResult<()> {
+        Ok(self.render()?)
+    }
+
+    #[getter]
+    fn get_samples(&self) -> PyResult<Option<usize>> {
+        Ok(self.render.samples)
+    }
+
+    #[setter]
+    fn set_samples(&mut self, samples: Option<usize>) -> PyResult<()> {
+        self.render.samples = samples;
+        Ok(())
+    }
+
+    #[getter]
+    fn get_bounces(&self) -> PyResult<Option<usize>> {
+        Ok(self.render.bounces)
+    }
+
+    #[setter]
+    fn set_bounces(&mut self, bounces: Option<usize>) -> PyResult<()> {
+        self.render.bounces = bounces;
+        Ok(())
+    }
+
+    #[getter]
+    fn get_output(&self) -> PyResult<String> {
+        Ok(self.render.output.clone())
+    }
+
+    #[setter]
+    fn set_output(&mut self, output: String) -> PyResult<()> {
+        self.render.output = output;
+        Ok(())
+    }
+}
+
+impl PbrtScene {
+    /// Loads a scene, together with the files it includes and the PLY meshes it refers to
+    pub fn load(filename: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(filename)
+            .map_err(|e| Error::Io(format!("{}: {}", filename, e)))?;
+        let directory = Path::new(filename)
+            .parent()
+            .map_or_else(PathBuf::new, Path::to_path_buf);
+        PbrtScene::parse(&text, &directory).map_err(|e| match e {
+            Error::Value(message) => Error::Value(format!("{}: {}", filename, message)),
+            e => e,
+        })
+    }
+
+    /// Parses a scene, finding included files relative to a directory
+    pub fn parse(text: &str, directory: &Path) -> Result<Self> {
+        let mut importer = Importer::new(directory);
+        importer.run(text)?;
+        if let Some(light) = importer.environment {
+            let environment = importer.environment(light);
+            importer.things.push(environment);
+        }
+        let render = RenderDescription {
+            dpi: importer.resolution.0,
+            samples: importer.samples,
+            bounces: Some(importer.depth + 1),
+            output: importer.output.clone(),
+            seed: None,
+        };
+        Ok(PbrtScene {
+            camera: importer.camera(),
+            things: importer.things,
+            render,
+        })
+    }
+
+    /// Returns the scene ready for rendering
+    pub fn scene(&self) -> Scene {
+        let settings = &self.render;
+        let mut scene = Scene::new(self.camera.clone(), settings.samples, settings.bounces)
+            .with_seed(settings.seed.unwrap_or(0));
+        for thing in &self.things {
+            scene.add(thing.clone());
+        }
+        scene
+    }
+
+    /// Renders the scene into the output file of its render settings
+    pub fn render(&self) -> Result<()> {
+        let settings = &self.render;
+        self.scene()
+            .render(&settings.output, settings.dpi)
+            .map_err(|e| Error::Io(format!("{}: {}", settings.output, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    #[test]
+    fn statements_parse() {
+        let text = "# a comment\nShape \"sphere\" \"float radius\" [ 2.5 ] # another\n\
+                    \"bool flag\" true \"string name\" \"a # b\"\nTranslate 1 -2 .5";
+        let parsed = statements(tokenize(text).unwrap()).unwrap();
+        assert_eq!(parsed.len(), 2);
+        let (kinds, params) = parsed[0].strings(1).unwrap();
+        assert_eq!(kinds, vec!["sphere"]);
+        assert_eq!(params.float("radius", 1.0), 2.5);
+        assert_eq!(params.string("name"), Some("a # b"));
+        assert_eq!(params.0["flag"].1, vec![Token::Bool(true)]);
+        assert_eq!(parsed[1].numbers(3).unwrap(), vec![1.0, -2.0, 0.5]);
+        assert!(parsed[1].numbers(4).is_err());
+
+        let text = "Material \"conductor\" \"spectrum eta\" \"metal-Au-eta\" \
+                    \"float roughness\" 0.0625 \"bool remaproughness\" \"false\"";
+        let (kinds, params) = statements(tokenize(text).unwrap()).unwrap()[0]
+            .strings(1)
+            .unwrap();
+        let gold = material(&kinds[0], &params).unwrap();
+        let expected = Material::metal("gold", 0.25).unwrap();
+        assert_eq!(format!("{:?}", gold), format!("{:?}", expected));
+
+        assert!(tokenize("Shape \"sphere").is_err());
+        assert!(statements(tokenize("[ 1 ]").unwrap()).is_err());
+        assert!(Params::parse(&[Token::Str("radius".into()), Token::Number(1.0)]).is_err());
+    }
+
+    #[test]
+    fn scenes_import() {
+        let text = r#"
+            LookAt 0 0 -5  0 0 0  0 1 0
+            Camera "perspective" "float fov" 45
+            Film "rgb" "integer xresolution" 200 "integer yresolution" 100
+                "string filename" "out.exr"
+            Sampler "halton" "integer pixelsamples" 16
+            Integrator "path" "integer maxdepth" 3
+            WorldBegin
+            MakeNamedMaterial "red" "string type" "diffuse" "rgb reflectance" [1 0 0]
+            AttributeBegin
+                NamedMaterial "red"
+                Translate 0 0 2
+                Shape "sphere" "float radius" 1
+            AttributeEnd
+            ObjectBegin "triangle"
+                AreaLightSource "diffuse" "rgb L" [4 2 2]
+                Shape "trianglemesh" "point3 P" [0 0 0 1 0 0 0 1 0] "integer indices" [0 1 2]
+            ObjectEnd
+            Translate 0 10 0
+            ObjectInstance "triangle"
+            Shape "plymesh" "string filename" "missing.ply"
+        "#;
+        let err = PbrtScene::parse(text, Path::new("nowhere")).err().unwrap();
+        assert!(matches!(err, Error::Io(_)));
+
+        let text = text.replace("Shape \"plymesh\"", "Shape \"unsupported\"");
+        let pbrt = PbrtScene::parse(&text, Path::new("")).unwrap();
+        assert_eq!(pbrt.things.len(), 2);
+        assert_eq!(pbrt.render.samples, Some(16));
+        assert_eq!(pbrt.render.bounces, Some(4));
+        assert_eq!(pbrt.render.output, "out.png");
+        assert_eq!(pbrt.render.dpi, 200);
+        assert!((pbrt.camera.width() - 1.0).abs() < 1e-6);
+
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let ray = pbrt.camera.view(0.5, 0.5, &mut rng);
+        assert!((ray.direction.normalized() - UNIT_Z).norm() < 1e-5);
+        let (hit, index) = ray.intersect(&pbrt.things, None).unwrap();
+        assert_eq!(index, 0);
+        assert!((ray.at(hit.distance).z - 1.0).abs() < 1e-4);
+        assert_eq!(hit.material.color, Color::red());
+
+        let ray = Ray::new(Point::new(0.2, 10.2, -1.0), UNIT_Z);
+        let (hit, _) = ray.intersect(&pbrt.things, None).unwrap();
+        assert_eq!(hit.material.emittance, 4.0);
+        assert_eq!(hit.material.color, Color::new(1.0, 0.5, 0.5));
+
+        let unmatched = PbrtScene::parse("AttributeEnd", Path::new(""));
+        assert!(unmatched.is_err());
+    }
+
+    #[test]
+    fn malformed_scenes_are_rejected() {
+        let mesh = "Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0]";
+        let odd = format!("{} \"float uv\" [0 0 1 0 0]", mesh);
+        assert!(PbrtScene::parse(&odd, Path::new("")).is_err());
+        let fractional = format!("{} \"integer indices\" [0 1 1.5]", mesh);
+        assert!(PbrtScene::parse(&fractional, Path::new("")).is_err());
+
+        // Indices beyond the precision of single floats are kept exactly
+        let text = "Shape \"trianglemesh\" \"integer indices\" [0 16777217 2]";
+        let (_, params) = statements(tokenize(text).unwrap()).unwrap()[0]
+            .strings(1)
+            .unwrap();
+        assert_eq!(
+            params.indices("indices").unwrap(),
+            Some(vec![0, 16777217, 2])
+        );
+
+        let directory = std::env::temp_dir().join("pathetic-pbrt-includes");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("a.pbrt"), "Include \"b.pbrt\"").unwrap();
+        std::fs::write(directory.join("b.pbrt"), "Include \"a.pbrt\"").unwrap();
+        let cycle = PbrtScene::parse("Include \"a.pbrt\"", &directory);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(matches!(cycle, Err(Error::Value(_))));
+    }
+
+    #[test]
+    fn instances_nest_in_objects() {
+        let text = r#"
+            ObjectBegin "triangle"
+                Shape "trianglemesh" "point3 P" [0 0 0 1 0 0 0 1 0]
+            ObjectEnd
+            ObjectBegin "pair"
+                ObjectInstance "triangle"
+                Translate 0 0 1
+                ObjectInstance "triangle"
+            ObjectEnd
+            ObjectInstance "pair"
+        "#;
+        let pbrt = PbrtScene::parse(text, Path::new("")).unwrap();
+        assert_eq!(pbrt.things.len(), 1);
+        let ray = Ray::new(Point::new(0.2, 0.2, -1.0), UNIT_Z);
+        let (hit, _) = ray.intersect(&pbrt.things, None).unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-4);
+        assert!((pbrt.things[0].bounds().max.z - 1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,301 @@
+//! Reading of Stanford PLY files, in ASCII as well as in binary
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// The type of a value stored in a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Result<Kind, Box<dyn Error>> {
+        Ok(match name {
+            "char" | "int8" => Kind::Char,
+            "uchar" | "uint8" => Kind::UChar,
+            "short" | "int16" => Kind::Short,
+            "ushort" | "uint16" => Kind::UShort,
+            "int" | "int32" => Kind::Int,
+            "uint" | "uint32" => Kind::UInt,
+            "float" | "float32" => Kind::Float,
+            "double" | "float64" => Kind::Double,
+            _ => return Err(format!("unknown property type {}", name).into()),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Kind::Char | Kind::UChar => 1,
+            Kind::Short | Kind::UShort => 2,
+            Kind::Int | Kind::UInt | Kind::Float => 4,
+            Kind::Double => 8,
+        }
+    }
+
+    /// Returns the largest value of integer types, to scale colors to between zero and one
+    pub fn range(self) -> f64 {
+        match self {
+            Kind::UChar => 255.0,
+            Kind::UShort => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+/// The values of a property for all items of an element
+#[derive(Debug, PartialEq)]
+pub enum Values {
+    Scalars(Vec<f64>),
+    Lists(Vec<Vec<f64>>),
+}
+
+#[derive(Debug)]
+pub struct Property {
+    pub name: String,
+    pub kind: Kind,
+    /// The type of the length of a list property
+    count: Option<Kind>,
+    pub values: Values,
+}
+
+/// A kind of item in a file, like vertices or faces, with all its values
+#[derive(Debug)]
+pub struct Element {
+    pub name: String,
+    pub count: usize,
+    pub properties: Vec<Property>,
+}
+
+impl Element {
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    /// Returns the values of a scalar property
+    pub fn scalars(&self, name: &str) -> Option<&[f64]> {
+        match self.property(name).map(|p| &p.values) {
+            Some(Values::Scalars(values)) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Returns the values of a list property
+    pub fn lists(&self, name: &str) -> Option<&[Vec<f64>]> {
+        match self.property(name).map(|p| &p.values) {
+            Some(Values::Lists(lists)) => Some(lists),
+            _ => None,
+        }
+    }
+
+    /// Returns the values of three scalar properties together, like the coordinates of points
+    pub fn triples(&self, names: [&str; 3]) -> Option<Vec<[f64; 3]>> {
+        let (x, y, z) = (
+            self.scalars(names[0])?,
+            self.scalars(names[1])?,
+            self.scalars(names[2])?,
+        );
+        Some((0..self.count).map(|i| [x[i], y[i], z[i]]).collect())
+    }
+
+    /// Returns the red, green and blue properties, scaled to between zero and one
+    pub fn colors(&self) -> Option<Vec<[f64; 3]>> {
+        let range = self.property("red")?.kind.range();
+        let colors = self.triples(["red", "green", "blue"])?;
+        Some(
+            colors
+                .into_iter()
+                .map(|[r, g, b]| [r / range, g / range, b / range])
+                .collect(),
+        )
+    }
+}
+
+/// The contents of a PLY file
+#[derive(Debug)]
+pub struct Ply {
+    pub elements: Vec<Element>,
+}
+
+impl Ply {
+    pub fn open(filename: &str) -> Result<Ply, Box<dyn Error>> {
+        Ply::parse(BufReader::new(File::open(filename)?))
+    }
+
+    pub fn parse<R: BufRead>(mut reader: R) -> Result<Ply, Box<dyn Error>> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim() != "ply" {
+            return Err("not a PLY file".into());
+        }
+        let mut format = None;
+        let mut elements: Vec<Element> = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err("unexpected end of header".into());
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["format", f, _] => {
+                    format = Some(match *f {
+                        "ascii" => Format::Ascii,
+                        "binary_little_endian" => Format::LittleEndian,
+                        "binary_big_endian" => Format::BigEndian,
+                        _ => return Err(format!("unknown format {}", f).into()),
+                    })
+                }
+                ["element", name, count] => elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse()?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count, kind, name] => {
+                    let element = elements.last_mut().ok_or("property outside of element")?;
+                    element.properties.push(Property {
+                        name: name.to_string(),
+                        kind: Kind::parse(kind)?,
+                        count: Some(Kind::parse(count)?),
+                        values: Values::Lists(Vec::new()),
+                    });
+                }
+                ["property", kind, name] => {
+                    let element = elements.last_mut().ok_or("property outside of element")?;
+                    element.properties.push(Property {
+                        name: name.to_string(),
+                        kind: Kind::parse(kind)?,
+                        count: None,
+                        values: Values::Scalars(Vec::new()),
+                    });
+                }
+                ["end_header"] => break,
+                _ => {} // comments and object information
+            }
+        }
+        let format = format.ok_or("missing format")?;
+
+        let mut values = Reader::new(reader, format)?;
+        for element in elements.iter_mut() {
+            for _ in 0..element.count {
+                for property in element.properties.iter_mut() {
+                    let kind = property.kind;
+                    match (&mut property.values, property.count) {
+                        (Values::Lists(lists), Some(count)) => {
+                            let length = values.next(count)? as usize;
+                            let list = (0..length)
+                                .map(|_| values.next(kind))
+                                .collect::<Result<Vec<f64>, _>>()?;
+                            lists.push(list);
+                        }
+                        (Values::Scalars(scalars), _) => scalars.push(values.next(kind)?),
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        }
+        Ok(Ply { elements })
+    }
+
+    pub fn element(&self, name: &str) -> Option<&Element> {
+        self.elements.iter().find(|e| e.name == name)
+    }
+}
+
+/// Reads the values following the header one by one
+enum Reader<R: BufRead> {
+    Ascii(std::vec::IntoIter<String>),
+    Binary(R, Format),
+}
+
+impl<R: BufRead> Reader<R> {
+    fn new(mut reader: R, format: Format) -> Result<Self, Box<dyn Error>> {
+        if format == Format::Ascii {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            let words: Vec<String> = text.split_whitespace().map(String::from).collect();
+            Ok(Reader::Ascii(words.into_iter()))
+        } else {
+            Ok(Reader::Binary(reader, format))
+        }
+    }
+
+    fn next(&mut self, kind: Kind) -> Result<f64, Box<dyn Error>> {
+        match self {
+            Reader::Ascii(words) => Ok(words.next().ok_or("unexpected end of data")?.parse()?),
+            Reader::Binary(reader, format) => {
+                let mut bytes = [0u8; 8];
+                let bytes = &mut bytes[..kind.size()];
+                reader.read_exact(bytes)?;
+                if *format == Format::BigEndian {
+                    bytes.reverse();
+                }
+                let mut b = [0u8; 8];
+                b[..bytes.len()].copy_from_slice(bytes);
+                Ok(match kind {
+                    Kind::Char => b[0] as i8 as f64,
+                    Kind::UChar => b[0] as f64,
+                    Kind::Short => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    Kind::UShort => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    Kind::Int => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Kind::UInt => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Kind::Float => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Kind::Double => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn ply_parses() {
+        let ascii = "ply\nformat ascii 1.0\ncomment made by hand\nelement vertex 2\n\
+                     property float x\nproperty uchar red\nelement face 1\n\
+                     property list uchar int vertex_indices\nend_header\n\
+                     1.5 255\n-2 0\n3 0 1 1\n";
+        let ply = Ply::parse(Cursor::new(ascii)).unwrap();
+        let vertex = ply.element("vertex").unwrap();
+        assert_eq!(vertex.scalars("x").unwrap(), &[1.5, -2.0]);
+        assert_eq!(vertex.colors(), None);
+        let faces = ply.element("face").unwrap();
+        assert_eq!(
+            faces.lists("vertex_indices").unwrap(),
+            &[vec![0.0, 1.0, 1.0]]
+        );
+
+        let mut binary = b"ply\nformat binary_big_endian 1.0\nelement vertex 2\n\
+                           property float x\nproperty short y\nend_header\n"
+            .to_vec();
+        for (x, y) in [(0.25f32, -3i16), (8.0, 7)].iter() {
+            binary.extend_from_slice(&x.to_be_bytes());
+            binary.extend_from_slice(&y.to_be_bytes());
+        }
+        let ply = Ply::parse(Cursor::new(binary)).unwrap();
+        let vertex = ply.element("vertex").unwrap();
+        assert_eq!(vertex.scalars("x").unwrap(), &[0.25, 8.0]);
+        assert_eq!(vertex.scalars("y").unwrap(), &[-3.0, 7.0]);
+
+        assert!(Ply::parse(Cursor::new("obj\n")).is_err());
+        let short = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nend_header\n1\n";
+        assert!(Ply::parse(Cursor::new(short)).is_err());
+    }
+}
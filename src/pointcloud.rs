@@ -0,0 +1,336 @@
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::ply::*;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::buffer::PyBuffer;
+#[cfg(feature = "python")]
+use pyo3::exceptions;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::sync::Arc;
+
+/// The maximum number of points kept in a single leaf of the hierarchy
+static LEAF_SIZE: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+struct Splat {
+    center: Point,
+    /// The orientation of discs, unused for spheres
+    normal: Point,
+    radius: f32,
+    color: Color,
+}
+
+impl Bounded for Splat {
+    fn bounds(&self) -> Bounds {
+        let r = Point::new(self.radius, self.radius, self.radius);
+        Bounds::new(self.center - r, self.center + r)
+    }
+}
+
+impl Splat {
+    /// Returns the distance to the disc and its normal, facing the ray
+    fn disc(&self, ray: &Ray) -> Option<(f32, Point)> {
+        let facing = ray.direction * self.normal;
+        if facing.abs() < 1e-9 {
+            return None;
+        }
+        let t = (self.center - ray.base) * self.normal / facing;
+        if t <= 0.0 || (ray.at(t) - self.center).norm_sqr() > self.radius * self.radius {
+            return None;
+        }
+        Some((
+            t,
+            if facing < 0.0 {
+                self.normal
+            } else {
+                -self.normal
+            },
+        ))
+    }
+
+    fn sphere(&self, ray: &Ray) -> Option<(f32, Point)> {
+        let hypo = self.center - ray.base;
+        let dot = ray.direction * hypo;
+        let root = dot * dot - hypo.norm_sqr() + self.radius * self.radius;
+        if root < 0.0 {
+            return None;
+        }
+        let t = [dot - root.sqrt(), dot + root.sqrt()]
+            .iter()
+            .cloned()
+            .find(|t| *t > 0.0)?;
+        Some((t, (ray.at(t) - self.center) / self.radius))
+    }
+}
+
+/// Points with their own bounding volume hierarchy, which is far smaller than one over
+/// separate things
+struct Cloud {
+    splats: Vec<Splat>,
+    hierarchy: Hierarchy,
+    discs: bool,
+    material: Material,
+}
+
+/// A cloud of points rendered as small oriented discs, or as spheres without orientation
+///
+/// Each point has its own color, replacing the color of the material.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct PointCloud {
+    cloud: Arc<Cloud>,
+}
+
+/// Returns the values of a float32 or float64 array with the given number of columns
+#[cfg(feature = "python")]
+fn rows(obj: &PyAny, columns: usize) -> PyResult<Vec<f64>> {
+    let py = obj.py();
+    let buffer = PyBuffer::get(py, obj)?;
+    let shape = buffer.shape();
+    let fits = match shape {
+        [_] => columns == 1,
+        [_, c] => *c == columns,
+        _ => false,
+    };
+    if !fits {
+        return Err(exceptions::ValueError::py_err(format!(
+            "need an array with {} columns",
+            columns
+        )));
+    }
+    to_floats(py, &buffer)
+}
+
+#[cfg(feature = "python")]
+fn points(values: Vec<f64>) -> Vec<Point> {
+    values
+        .chunks(3)
+        .map(|c| Point::new(c[0] as f32, c[1] as f32, c[2] as f32))
+        .collect()
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl PointCloud {
+    /// Creates a point cloud from arrays with one row per point
+    ///
+    /// # Arguments
+    ///
+    /// * `positions` - the centers of the points, of shape `(count, 3)`
+    /// * `radii` - a radius for all points, or one per point
+    /// * `material` - what the points are made of
+    /// * `colors` - colors of shape `(count, 3)`, between zero and one
+    /// * `normals` - orientations of shape `(count, 3)`, rendering points as discs if given
+    #[new]
+    fn py_new(
+        positions: &PyAny,
+        radii: &PyAny,
+        material: Material,
+        colors: Option<&PyAny>,
+        normals: Option<&PyAny>,
+    ) -> PyResult<Self> {
+        let centers = points(rows(positions, 3)?);
+        let radii = match radii.extract::<f32>() {
+            Ok(r) => vec![r; centers.len()],
+            Err(_) => rows(radii, 1)?.into_iter().map(|r| r as f32).collect(),
+        };
+        let colors = match colors {
+            Some(c) => Some(
+                points(rows(c, 3)?)
+                    .into_iter()
+                    .map(|p| Color::new(p.x, p.y, p.z))
+                    .collect(),
+            ),
+            None => None,
+        };
+        let normals = match normals {
+            Some(n) => Some(points(rows(n, 3)?)),
+            None => None,
+        };
+        Ok(PointCloud::from_points(
+            centers, radii, material, colors, normals,
+        )?)
+    }
+
+    /// Loads a point cloud from a PLY file, with colors and normals if present
+    ///
+    /// Points are rendered as discs if the file has normals, unless `discs` is false.
+    #[staticmethod]
+    #[name = "load"]
+    fn py_load(
+        filename: &str,
+        radius: f32,
+        material: Material,
+        discs: Option<bool>,
+    ) -> PyResult<Self> {
+        Ok(PointCloud::load(filename, radius, material, discs)?)
+    }
+}
+
+impl PointCloud {
+    /// Loads a point cloud from a PLY file, with colors and normals if present
+    ///
+    /// Points are rendered as discs if the file has normals, unless `discs` is false.
+    pub fn load(
+        filename: &str,
+        radius: f32,
+        material: Material,
+        discs: Option<bool>,
+    ) -> Result<Self> {
+        let ply = Ply::open(filename).map_err(|e| Error::Io(format!("{}: {}", filename, e)))?;
+        PointCloud::from_ply(&ply, radius, material, discs)
+    }
+
+    /// Creates a point cloud, see the Python constructor
+    pub fn from_points(
+        centers: Vec<Point>,
+        radii: Vec<f32>,
+        material: Material,
+        colors: Option<Vec<Color>>,
+        normals: Option<Vec<Point>>,
+    ) -> Result<Self> {
+        let count = centers.len();
+        if radii.len() != count
+            || matches!(colors, Some(ref c) if c.len() != count)
+            || matches!(normals, Some(ref n) if n.len() != count)
+        {
+            return Err(Error::Value(
+                "need the same number of values for each point".into(),
+            ));
+        }
+        let splats: Vec<Splat> = (0..count)
+            .map(|i| Splat {
+                center: centers[i],
+                normal: normals.as_ref().map_or(UNIT_Z, |n| n[i].normalized()),
+                radius: radii[i],
+                color: colors.as_ref().map_or(material.color, |c| c[i]),
+            })
+            .collect();
+        Ok(PointCloud {
+            cloud: Arc::new(Cloud {
+                hierarchy: Hierarchy::new(&splats, LEAF_SIZE),
+                splats,
+                discs: normals.is_some(),
+                material,
+            }),
+        })
+    }
+
+    /// Creates a point cloud from the vertices of a PLY file, see `load`
+    pub fn from_ply(
+        ply: &Ply,
+        radius: f32,
+        material: Material,
+        discs: Option<bool>,
+    ) -> Result<Self> {
+        let missing = |what: &str| Error::Value(format!("no {} in file", what));
+        let vertex = ply.element("vertex").ok_or_else(|| missing("vertices"))?;
+        let centers = vertex
+            .triples(["x", "y", "z"])
+            .ok_or_else(|| missing("positions"))?;
+        let centers: Vec<Point> = centers.into_iter().map(Point::from).collect();
+        let colors = vertex
+            .colors()
+            .map(|c| c.into_iter().map(Color::from).collect());
+        let normals = vertex
+            .triples(["nx", "ny", "nz"])
+            .map(|n| n.into_iter().map(Point::from).collect());
+        let normals = if discs.unwrap_or(true) { normals } else { None };
+        if discs == Some(true) && normals.is_none() {
+            return Err(missing("normals"));
+        }
+        let radii = vec![radius; centers.len()];
+        PointCloud::from_points(centers, radii, material, colors, normals)
+    }
+}
+
+impl Thing for PointCloud {
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let cloud = &self.cloud;
+        let mut closest: Option<(f32, Point, Color)> = None;
+        cloud.hierarchy.visit(ray, f32::INFINITY, |i| {
+            let splat = &cloud.splats[i];
+            let (t, normal) = if cloud.discs {
+                splat.disc(ray)
+            } else {
+                splat.sphere(ray)
+            }?;
+            if matches!(closest, Some(c) if c.0 <= t) {
+                return None;
+            }
+            closest = Some((t, normal, splat.color));
+            Some(t)
+        });
+        let (t, normal, color) = closest?;
+        let material = Material {
+            color,
+            ..cloud.material.clone()
+        };
+        Some(Hit::new(t, normal, material))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.cloud.hierarchy.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn ray_hits_point_cloud() {
+        let m = Material::new(0.0, 0.0, 1.0, 0.0, 0.0, Color::white());
+        let mut centers = Vec::new();
+        let mut colors = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                centers.push(Point::new(0.1 * i as f32, 0.1 * j as f32, 0.01 * i as f32));
+                colors.push(Color::new(i as f32 / 20.0, j as f32 / 20.0, 0.0));
+            }
+        }
+        let normals = vec![UNIT_Z; centers.len()];
+        let radii = vec![0.04; centers.len()];
+        let discs = PointCloud::from_points(
+            centers.clone(),
+            radii.clone(),
+            m.clone(),
+            Some(colors.clone()),
+            Some(normals),
+        )
+        .unwrap();
+        let spheres =
+            PointCloud::from_points(centers, radii, m.clone(), Some(colors), None).unwrap();
+
+        let ray = Ray::new(Point::new(0.5, 0.7, 5.0), -UNIT_Z);
+        let hit = discs.hit_by(&ray).unwrap();
+        assert!((hit.distance - 4.95).abs() < 1e-5);
+        assert_eq!(hit.normal, UNIT_Z);
+        assert_eq!(hit.material.color, Color::new(0.25, 0.35, 0.0));
+        let hit = spheres.hit_by(&ray).unwrap();
+        assert!((hit.distance - 4.91).abs() < 1e-5);
+        assert!((hit.normal - UNIT_Z).norm() < 1e-5);
+
+        let between = Ray::new(Point::new(0.55, 0.75, 5.0), -UNIT_Z);
+        assert!(discs.hit_by(&between).is_none());
+        let below = Ray::new(Point::new(0.5, 0.7, -5.0), UNIT_Z);
+        assert_eq!(discs.hit_by(&below).unwrap().normal, -UNIT_Z);
+        let b = discs.bounds();
+        assert!((b.max.x - 1.94).abs() < 1e-5);
+
+        let ply = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\n\
+                   property float y\nproperty float z\nproperty uchar red\n\
+                   property uchar green\nproperty uchar blue\nend_header\n\
+                   0 0 0 255 0 0\n1 0 0 0 255 0\n";
+        let ply = Ply::parse(Cursor::new(ply)).unwrap();
+        let cloud = PointCloud::from_ply(&ply, 0.1, m.clone(), None).unwrap();
+        let hit = cloud
+            .hit_by(&Ray::new(Point::new(1.0, 0.0, 5.0), -UNIT_Z))
+            .unwrap();
+        assert_eq!(hit.material.color, Color::green());
+        assert!(PointCloud::from_ply(&ply, 0.1, m, Some(true)).is_err());
+    }
+}
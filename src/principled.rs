@@ -0,0 +1,340 @@
+//! A principled material in the style of the Disney BSDF, easier to set up than `Material`
+//!
+//! All parameters are between zero and one and the lobes are layered so that no light is
+//! created: a diffuse base with sheen at grazing angles, a GGX specular layer tinted towards the
+//! base color for metals, a rough dielectric taking over from the diffuse base with
+//! transmission, and a clear coat on top.  The index of refraction follows from the specular
+//! reflectance, 0.5 being the 4% of common dielectrics with an index of 1.5.
+//!
+//! Directions are sampled from one lobe chosen at random, and weighted by the value of all
+//! lobes over the density of sampling them from any, so that each lobe helps where it is good.
+
+use crate::bsdf::*;
+use crate::microfacet::*;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use rand::prelude::*;
+use std::f32::consts::PI;
+
+/// The smallest width of the microfacet distributions, as perfectly smooth surfaces are not
+/// handled by the layered lobes
+static MIN_ALPHA: f32 = 2e-3;
+/// The roughness of the clear coat
+static CLEARCOAT_ROUGHNESS: f32 = 0.1;
+
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Principled {
+    /// The color of diffuse reflection, and of the specular one of metals
+    pub base_color: Color,
+    /// Whether the surface is a dielectric at zero or a metal at one
+    pub metallic: f32,
+    /// How blurry reflections and refractions are
+    pub roughness: f32,
+    /// The reflectance of dielectrics, scaled so that 0.5 reflects 4% head-on
+    pub specular: f32,
+    /// The share of light passing through dielectrics rather than being scattered diffusely
+    pub transmission: f32,
+    /// The strength of a clear coat on top
+    pub clearcoat: f32,
+    /// The strength of a soft reflection at grazing angles, as of cloth
+    pub sheen: f32,
+    /// The light emitted
+    pub emission: Color,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Principled {
+    #[new]
+    fn py_new(
+        base_color: Color,
+        metallic: Option<f32>,
+        roughness: Option<f32>,
+        specular: Option<f32>,
+        transmission: Option<f32>,
+        clearcoat: Option<f32>,
+        sheen: Option<f32>,
+        emission: Option<Color>,
+    ) -> Self {
+        let default = Principled::new(base_color);
+        Principled {
+            base_color,
+            metallic: metallic.unwrap_or(default.metallic),
+            roughness: roughness.unwrap_or(default.roughness),
+            specular: specular.unwrap_or(default.specular),
+            transmission: transmission.unwrap_or(default.transmission),
+            clearcoat: clearcoat.unwrap_or(default.clearcoat),
+            sheen: sheen.unwrap_or(default.sheen),
+            emission: emission.unwrap_or(default.emission),
+        }
+    }
+}
+
+/// The probabilities of sampling each lobe
+struct Lobes {
+    diffuse: f32,
+    specular: f32,
+    transmission: f32,
+    clearcoat: f32,
+}
+
+impl Principled {
+    /// Returns a rough, diffuse dielectric of a color, to be changed by updating its fields
+    pub fn new(base_color: Color) -> Self {
+        Principled {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            transmission: 0.0,
+            clearcoat: 0.0,
+            sheen: 0.0,
+            emission: Color::black(),
+        }
+    }
+
+    /// The index of refraction giving the specular reflectance
+    fn ior(&self) -> f32 {
+        let r = (0.08 * self.specular).clamp(0.0, 0.999).sqrt();
+        (1.0 + r) / (1.0 - r)
+    }
+
+    fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+
+    fn lobes(&self) -> Lobes {
+        let nonmetal = 1.0 - self.metallic;
+        let diffuse = nonmetal * (1.0 - self.transmission).max(self.sheen);
+        let transmission = nonmetal * self.transmission;
+        let specular = 1.0 - transmission;
+        let clearcoat = 0.25 * self.clearcoat;
+        let total = diffuse + specular + transmission + clearcoat;
+        Lobes {
+            diffuse: diffuse / total,
+            specular: specular / total,
+            transmission: transmission / total,
+            clearcoat: clearcoat / total,
+        }
+    }
+
+    /// Returns the value and sampling density for local directions, `wo` being above the
+    /// surface and `eta` the ratio of the indices of refraction below and above it
+    fn local(&self, wo: Point, wi: Point, eta: f32) -> (Color, f32) {
+        let lobes = self.lobes();
+        let nonmetal = 1.0 - self.metallic;
+        let alpha = self.alpha();
+        let (transmitted, transmission_pdf) = dielectric(wo, wi, eta, alpha);
+        let mut value = nonmetal * self.transmission * transmitted * self.tint(wi);
+        let mut pdf = lobes.transmission * transmission_pdf;
+        if wi.z <= 0.0 {
+            return (value, pdf);
+        }
+
+        let h = (wo + wi).normalized();
+        let schlick = (1.0 - wi * h).max(0.0).powi(5);
+        let (coat, coat_pdf) = glossy_lobe(wo, wi, h, CLEARCOAT_ROUGHNESS.powi(2));
+        let coat_fresnel = 0.04 + 0.96 * schlick;
+        value += 0.25 * self.clearcoat * coat_fresnel * coat * Color::white();
+        pdf += lobes.clearcoat * coat_pdf;
+        // the layers below only see what the coat lets through
+        let below = 1.0 - 0.25 * self.clearcoat * coat_fresnel;
+
+        // and the diffuse base only what is not reflected specularly
+        let entering = 1.0 - fresnel_dielectric(wo.z, eta);
+        let diffuse = entering * nonmetal * (1.0 - self.transmission) / PI * self.base_color
+            + nonmetal * self.sheen * schlick * Color::white();
+        value += below * diffuse;
+        pdf += lobes.diffuse * wi.z / PI;
+
+        let f0 = (1.0 - self.metallic) * (0.08 * self.specular) * Color::white()
+            + self.metallic * self.base_color;
+        let fresnel = f0 + schlick * (Color::white() - f0);
+        let (specular, specular_pdf) = glossy_lobe(wo, wi, h, alpha);
+        value += below * (1.0 - nonmetal * self.transmission) * specular * fresnel;
+        pdf += lobes.specular * specular_pdf;
+        (value, pdf)
+    }
+
+    /// The color of transmitted light, only that going through the surface being tinted
+    fn tint(&self, wi: Point) -> Color {
+        if wi.z < 0.0 {
+            self.base_color
+        } else {
+            Color::white()
+        }
+    }
+
+    /// Returns the material as it is where a surface is hit
+    fn at(&self, at: &Shading) -> Principled {
+        Principled {
+            base_color: at.color,
+            roughness: at.roughness.unwrap_or(self.roughness),
+            ..*self
+        }
+    }
+
+    /// Returns the frame around the normal on the side of `wo`, and the ratio of indices
+    fn frame(&self, normal: Point, wo: Point) -> (Frame, f32) {
+        let eta = if normal * wo > 0.0 {
+            self.ior()
+        } else {
+            1.0 / self.ior()
+        };
+        (Frame::facing(normal, -wo), eta)
+    }
+}
+
+/// The base color and roughness are those at the hit, so that vertex colors and textures apply
+impl Bsdf for Principled {
+    fn sample(&self, at: &Shading, wo: Point, rng: &mut dyn RngCore) -> Option<Sample> {
+        let material = self.at(at);
+        let (frame, eta) = material.frame(at.normal, wo);
+        let wo = frame.local(wo);
+        let lobes = material.lobes();
+        let pick = rng.gen::<f32>();
+        let wi = if pick < lobes.diffuse {
+            cosine_weighted(rng)
+        } else if pick < lobes.diffuse + lobes.specular {
+            reflect(wo, visible_normal(wo, material.alpha(), rng))
+        } else if pick < lobes.diffuse + lobes.specular + lobes.transmission {
+            let m = visible_normal(wo, material.alpha(), rng);
+            if rng.gen::<f32>() < fresnel_dielectric(wo * m, eta) {
+                reflect(wo, m)
+            } else {
+                refract(wo, m, eta)?
+            }
+        } else {
+            reflect(wo, visible_normal(wo, CLEARCOAT_ROUGHNESS.powi(2), rng))
+        };
+        let (value, pdf) = material.local(wo, wi, eta);
+        if pdf.is_nan() || pdf <= 0.0 || wi.z == 0.0 {
+            return None;
+        }
+        Some(Sample {
+            direction: frame.world(wi),
+            weight: wi.z.abs() / pdf * value,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, at: &Shading, wo: Point, wi: Point) -> Color {
+        let material = self.at(at);
+        let (frame, eta) = material.frame(at.normal, wo);
+        material.local(frame.local(wo), frame.local(wi), eta).0
+    }
+
+    fn pdf(&self, at: &Shading, wo: Point, wi: Point) -> f32 {
+        let material = self.at(at);
+        let (frame, eta) = material.frame(at.normal, wo);
+        material.local(frame.local(wo), frame.local(wi), eta).1
+    }
+
+    fn emission(&self, _at: &Shading, _wo: Point) -> Color {
+        self.emission
+    }
+}
+
+/// Returns the value of a GGX reflection without the Fresnel term, and its sampling density
+fn glossy_lobe(wo: Point, wi: Point, h: Point, alpha: f32) -> (f32, f32) {
+    let value = distribution(h, alpha) * masking(wo, wi, alpha) / (4.0 * wo.z * wi.z);
+    (value, visible_density(wo, h, alpha) / (4.0 * (wo * h)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    fn shading(material: &Principled) -> Shading {
+        Shading {
+            point: ORIGIN,
+            normal: UNIT_Z,
+            color: material.base_color,
+            uv: (0.0, 0.0),
+            tangent: None,
+            roughness: None,
+            emission: Color::white(),
+            opacity: 1.0,
+        }
+    }
+
+    /// Returns the share of light leaving the surface, and that of it passing through
+    fn albedo(material: &Principled, direction: Point) -> (f32, f32) {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let at = shading(material);
+        let (mut total, mut through) = (0.0, 0.0);
+        let n = 20000;
+        for _ in 0..n {
+            if let Some(sample) = material.sample(&at, -direction, &mut rng) {
+                let (out, weight) = (sample.direction, sample.weight);
+                let weight = (weight.r + weight.g + weight.b) / 3.0;
+                total += weight;
+                if out.z < 0.0 {
+                    through += weight;
+                }
+            }
+        }
+        (total / n as f32, through / n as f32)
+    }
+
+    #[test]
+    fn materials_conserve_energy() {
+        let down = Point::new(0.3, 0.0, -1.0).normalized();
+        let white = Principled::new(Color::white());
+        let (total, through) = albedo(&white, down);
+        assert!(total > 0.9 && total < 1.02, "{}", total);
+        assert_eq!(through, 0.0);
+
+        let metal = Principled {
+            metallic: 1.0,
+            roughness: 0.2,
+            ..Principled::new(Color::white())
+        };
+        let (total, _) = albedo(&metal, down);
+        assert!(total > 0.9 && total < 1.02, "{}", total);
+
+        let glass = Principled {
+            transmission: 1.0,
+            roughness: 0.1,
+            clearcoat: 1.0,
+            ..Principled::new(Color::white())
+        };
+        let (total, through) = albedo(&glass, down);
+        assert!(total > 0.9 && total < 1.02, "{}", total);
+        assert!(through > 0.85, "{}", through);
+
+        let velvet = Principled {
+            sheen: 1.0,
+            ..Principled::new(Color::new(0.5, 0.0, 0.0))
+        };
+        let (total, _) = albedo(&velvet, down);
+        assert!(total < 1.0, "{}", total);
+    }
+
+    #[test]
+    fn samples_agree_with_values() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(1);
+        let material = Principled {
+            metallic: 0.3,
+            roughness: 0.4,
+            clearcoat: 0.5,
+            transmission: 0.5,
+            ..Principled::new(Color::new(0.8, 0.4, 0.2))
+        };
+        let at = shading(&material);
+        let wo = -Point::new(0.5, 0.2, -1.0).normalized();
+        for _ in 0..100 {
+            if let Some(sample) = material.sample(&at, wo, &mut rng) {
+                let (out, weight) = (sample.direction, sample.weight);
+                let value = material.eval(&at, wo, out);
+                let pdf = material.pdf(&at, wo, out);
+                let expected = out.z.abs() / pdf * value;
+                assert!((expected - weight).r.abs() < 1e-3 * (1.0 + weight.r));
+            }
+        }
+    }
+}
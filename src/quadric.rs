@@ -0,0 +1,321 @@
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::roots;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+type Matrix = [[f64; 4]; 4];
+
+/// A surface where `pᵀ Q p = 0` for points `p = (x, y, z, 1)` and a symmetric matrix `Q`
+///
+/// The value `pᵀ Q p` is negative inside, so that normals point to where it grows.  Quadrics
+/// without finite extent, like paraboloids, can be clipped to bounds.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Quadric {
+    matrix: Matrix,
+    clip: Option<Bounds>,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Quadric {
+    #[new]
+    fn py_new(
+        rows: Vec<Vec<f32>>,
+        material: Material,
+        min: Option<Point>,
+        max: Option<Point>,
+    ) -> PyResult<Self> {
+        Ok(Quadric::new(rows, material, min, max)?)
+    }
+
+    /// Creates an ellipsoid with the given radii along the coordinate axes
+    #[staticmethod]
+    #[name = "ellipsoid"]
+    fn py_ellipsoid(center: Point, radii: Point, material: Material) -> Self {
+        Quadric::ellipsoid(center, radii, material)
+    }
+
+    /// Creates a paraboloid opening up along z from `vertex`, cut off at `height` above it
+    #[staticmethod]
+    #[name = "paraboloid"]
+    fn py_paraboloid(vertex: Point, focal: f32, height: f32, material: Material) -> Self {
+        Quadric::paraboloid(vertex, focal, height, material)
+    }
+
+    /// Creates a hyperboloid around the z axis, cut off at half the height above and below
+    /// `center`
+    #[staticmethod]
+    #[name = "hyperboloid"]
+    fn py_hyperboloid(
+        center: Point,
+        radii: Point,
+        height: f32,
+        material: Material,
+        two_sheets: Option<bool>,
+    ) -> Self {
+        Quadric::hyperboloid(center, radii, height, material, two_sheets)
+    }
+}
+
+impl Quadric {
+    /// Creates a quadric from the rows of a symmetric 4x4 matrix, optionally only keeping the
+    /// parts of the surface between `min` and `max`
+    pub fn new(
+        rows: Vec<Vec<f32>>,
+        material: Material,
+        min: Option<Point>,
+        max: Option<Point>,
+    ) -> Result<Self> {
+        if rows.len() != 4 || rows.iter().any(|r| r.len() != 4) {
+            return Err(Error::Value("need 4 rows of 4 values".into()));
+        }
+        let mut matrix = [[0.0; 4]; 4];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                if (rows[i][j] - rows[j][i]).abs() > 1e-6 {
+                    return Err(Error::Value("matrix is not symmetric".into()));
+                }
+                *value = rows[i][j] as f64;
+            }
+        }
+        let clip = match (min, max) {
+            (None, None) => None,
+            (min, max) => Some(Bounds::new(
+                min.unwrap_or_else(|| Bounds::infinite().min),
+                max.unwrap_or_else(|| Bounds::infinite().max),
+            )),
+        };
+        Ok(Quadric {
+            matrix,
+            clip,
+            material,
+        })
+    }
+
+    /// Creates an ellipsoid with the given radii along the coordinate axes
+    pub fn ellipsoid(center: Point, radii: Point, material: Material) -> Self {
+        let q = Quadric::diagonal(
+            [radii.x.powi(-2), radii.y.powi(-2), radii.z.powi(-2), -1.0],
+            Bounds::new(-radii, radii),
+            material,
+        );
+        q.translated(center)
+    }
+
+    /// Creates a paraboloid opening up along z from `vertex`, cut off at `height` above it
+    ///
+    /// Rays parallel to the z axis are reflected through the focus, `focal` above the vertex.
+    pub fn paraboloid(vertex: Point, focal: f32, height: f32, material: Material) -> Self {
+        let f = focal as f64;
+        let mut matrix = [[0.0; 4]; 4];
+        matrix[0][0] = 1.0;
+        matrix[1][1] = 1.0;
+        matrix[2][3] = -2.0 * f;
+        matrix[3][2] = -2.0 * f;
+        let r = 2.0 * (focal * height).sqrt();
+        let q = Quadric {
+            matrix,
+            clip: Some(Bounds::new(
+                Point::new(-r, -r, 0.0),
+                Point::new(r, r, height),
+            )),
+            material,
+        };
+        q.translated(vertex)
+    }
+
+    /// Creates a hyperboloid around the z axis, cut off at half the height above and below
+    /// `center`
+    ///
+    /// A hyperboloid of one sheet has a waist with the radii along x and y, one of two sheets
+    /// opens up and down from its vertices, the radius along z away from the center.
+    pub fn hyperboloid(
+        center: Point,
+        radii: Point,
+        height: f32,
+        material: Material,
+        two_sheets: Option<bool>,
+    ) -> Self {
+        let (a, b, c) = (radii.x.powi(-2), radii.y.powi(-2), radii.z.powi(-2));
+        let half = 0.5 * height;
+        let (diagonal, growth) = if two_sheets.unwrap_or(false) {
+            ([a, b, -c, 1.0], (half * half * c - 1.0).max(0.0).sqrt())
+        } else {
+            ([a, b, -c, -1.0], (1.0 + half * half * c).sqrt())
+        };
+        let extent = Point::new(radii.x * growth, radii.y * growth, half);
+        Quadric::diagonal(diagonal, Bounds::new(-extent, extent), material).translated(center)
+    }
+}
+
+impl Quadric {
+    fn diagonal(values: [f32; 4], clip: Bounds, material: Material) -> Self {
+        let mut matrix = [[0.0; 4]; 4];
+        for (i, v) in values.iter().enumerate() {
+            matrix[i][i] = *v as f64;
+        }
+        Quadric {
+            matrix,
+            clip: Some(clip),
+            material,
+        }
+    }
+
+    /// Returns the quadric moved by an offset
+    ///
+    /// Points on the moved surface satisfy `(p - o)ᵀ Q (p - o) = 0`, i.e., `pᵀ Tᵀ Q T p = 0`
+    /// with `T` the translation by `-o`.
+    fn translated(&self, offset: Point) -> Self {
+        let mut t = [[0.0; 4]; 4];
+        for (i, row) in t.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        for i in 0..3 {
+            t[i][3] = -offset[i] as f64;
+        }
+        let mut matrix = [[0.0; 4]; 4];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4)
+                    .flat_map(|k| (0..4).map(move |l| (k, l)))
+                    .map(|(k, l)| t[k][i] * self.matrix[k][l] * t[l][j])
+                    .sum();
+            }
+        }
+        Quadric {
+            matrix,
+            clip: self
+                .clip
+                .map(|b| Bounds::new(b.min + offset, b.max + offset)),
+            material: self.material.clone(),
+        }
+    }
+
+    /// Returns `Q p` for `p = (x, y, z, w)`
+    fn apply(&self, p: [f64; 4]) -> [f64; 4] {
+        let mut q = [0.0; 4];
+        for (value, row) in q.iter_mut().zip(self.matrix.iter()) {
+            *value = row.iter().zip(p.iter()).map(|(a, b)| a * b).sum();
+        }
+        q
+    }
+
+    fn contains(&self, p: Point) -> bool {
+        match self.clip {
+            Some(b) => (0..3).all(|i| b.min[i] <= p[i] && p[i] <= b.max[i]),
+            None => true,
+        }
+    }
+}
+
+impl Thing for Quadric {
+    /// Solves `(o + t d)ᵀ Q (o + t d) = 0` for the distance `t`, in double precision
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let o = [ray.base.x as f64, ray.base.y as f64, ray.base.z as f64, 1.0];
+        let d = [
+            ray.direction.x as f64,
+            ray.direction.y as f64,
+            ray.direction.z as f64,
+            0.0,
+        ];
+        let dot = |a: [f64; 4], b: [f64; 4]| a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+        let qo = self.apply(o);
+        let a: f64 = dot(d, self.apply(d));
+        let b: f64 = 2.0 * dot(d, qo);
+        let c: f64 = dot(o, qo);
+        let t = roots::quadratic(a, b, c)
+            .into_iter()
+            .map(|t| t as f32)
+            .find(|t| *t > 0.0 && self.contains(ray.at(*t)))?;
+        let p = ray.at(t);
+        let g = self.apply([p.x as f64, p.y as f64, p.z as f64, 1.0]);
+        let normal = Point::new(g[0] as f32, g[1] as f32, g[2] as f32).normalized();
+        Some(Hit::new(t, normal, self.material.clone()))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.clip.unwrap_or_else(Bounds::infinite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).norm() < 1e-4
+    }
+
+    #[test]
+    fn ray_hits_quadrics() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let e = Quadric::ellipsoid(UNIT_Z, Point::new(2.0, 1.0, 1.0), m.clone());
+        let hit = e
+            .hit_by(&Ray::new(Point::new(-5.0, 0.0, 1.0), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - 3.0).abs() < 1e-5);
+        assert!(close(hit.normal, -UNIT_X));
+        let inside = e.hit_by(&Ray::new(UNIT_Z, UNIT_Y)).unwrap();
+        assert!((inside.distance - 1.0).abs() < 1e-5);
+        assert!(close(inside.normal, UNIT_Y));
+        assert!(close(e.bounds().max, Point::new(2.0, 1.0, 2.0)));
+
+        // Reflecting rays along the axis passes through the focus
+        let p = Quadric::paraboloid(ORIGIN, 0.5, 2.0, m.clone());
+        let ray = Ray::new(Point::new(0.7, 0.3, 5.0), -UNIT_Z);
+        let hit = p.hit_by(&ray).unwrap();
+        let n = hit.normal;
+        let reflected = ray.direction - 2.0 * n * (n * ray.direction);
+        let impact = ray.at(hit.distance);
+        let towards = (0.5 * UNIT_Z - impact).normalized();
+        assert!(close(reflected.normalized(), towards));
+        assert!(p
+            .hit_by(&Ray::new(Point::new(3.0, 0.0, 5.0), -UNIT_Z))
+            .is_none());
+
+        let h = Quadric::hyperboloid(ORIGIN, Point::new(1.0, 1.0, 1.0), 2.0, m.clone(), None);
+        let hit = h.hit_by(&Ray::new(ORIGIN, UNIT_X)).unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-5);
+        assert!(close(hit.normal, UNIT_X));
+        let hit = h.hit_by(&Ray::new(Point::new(0.0, 0.0, 5.0), -UNIT_Z));
+        assert!(hit.is_none());
+        let two = Quadric::hyperboloid(
+            ORIGIN,
+            Point::new(1.0, 1.0, 1.0),
+            4.0,
+            m.clone(),
+            Some(true),
+        );
+        let hit = two
+            .hit_by(&Ray::new(Point::new(0.0, 0.0, 5.0), -UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!(close(hit.normal, -UNIT_Z));
+
+        let rows = vec![
+            vec![1.0, 1.0, 0.0, 0.0],
+            vec![0.0; 4],
+            vec![0.0; 4],
+            vec![0.0; 4],
+        ];
+        assert!(Quadric::new(rows, m.clone(), None, None).is_err());
+        let plane = vec![
+            vec![0.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.5],
+            vec![0.0, 0.0, 0.5, -1.0],
+        ];
+        let q = Quadric::new(plane, m, None, Some(Point::new(1.0, 1.0, 1.0))).unwrap();
+        let hit = q
+            .hit_by(&Ray::new(Point::new(0.0, 0.0, -3.0), UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!(q
+            .hit_by(&Ray::new(Point::new(2.0, 0.0, -3.0), UNIT_Z))
+            .is_none());
+    }
+}
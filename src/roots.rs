@@ -0,0 +1,141 @@
+//! Real roots of low order polynomials
+//!
+//! All solvers work in double precision, as the intersection with higher order surfaces is
+//! prone to cancellation.  Roots are returned in ascending order.
+
+use std::f64::consts::PI;
+
+/// Returns the real roots of `a x² + b x + c`
+pub fn quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return if b == 0.0 { vec![] } else { vec![-c / b] };
+    }
+    let disc = b * b - 4.0 * a * c;
+    if disc < 0.0 {
+        return vec![];
+    }
+    // Avoids subtracting numbers of similar size
+    let q = -0.5 * (b + disc.sqrt().copysign(b));
+    if q == 0.0 {
+        return vec![0.0, 0.0];
+    }
+    let (x0, x1) = (q / a, c / q);
+    if x0 < x1 {
+        vec![x0, x1]
+    } else {
+        vec![x1, x0]
+    }
+}
+
+/// Returns the real roots of `x³ + a x² + b x + c`
+pub fn cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let shift = a / 3.0;
+    let p = b - a * shift;
+    let q = 2.0 * shift * shift * shift - shift * b + c;
+    let disc = 0.25 * q * q + p * p * p / 27.0;
+    let mut roots = if disc > 0.0 {
+        let s = disc.sqrt();
+        vec![(-0.5 * q + s).cbrt() + (-0.5 * q - s).cbrt()]
+    } else if p == 0.0 {
+        vec![0.0]
+    } else {
+        let r = 2.0 * (-p / 3.0).sqrt();
+        let phi = (1.5 * q / p * (-3.0 / p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        (0..3)
+            .map(|k| r * (phi - 2.0 * PI * k as f64 / 3.0).cos())
+            .collect()
+    };
+    for x in roots.iter_mut() {
+        *x -= shift;
+    }
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal));
+    roots
+}
+
+/// Returns the real roots of `x⁴ + a x³ + b x² + c x + d`
+///
+/// Uses Ferrari's method, polishing the roots with a few Newton iterations afterwards.
+pub fn quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    let shift = a / 4.0;
+    let s2 = shift * shift;
+    let p = b - 6.0 * s2;
+    let q = c - 2.0 * b * shift + 8.0 * s2 * shift;
+    let r = d - c * shift + b * s2 - 3.0 * s2 * s2;
+
+    let mut roots = Vec::new();
+    if q.abs() < 1e-12 {
+        for z in quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                roots.push(z.sqrt());
+                roots.push(-z.sqrt());
+            }
+        }
+    } else {
+        let m = cubic(p, 0.25 * p * p - r, -0.125 * q * q)
+            .into_iter()
+            .fold(0.0f64, f64::max);
+        if m <= 0.0 {
+            return vec![];
+        }
+        let s = (2.0 * m).sqrt();
+        let t = q / (2.0 * s);
+        roots.extend(quadratic(1.0, -s, 0.5 * p + m + t));
+        roots.extend(quadratic(1.0, s, 0.5 * p + m - t));
+    }
+
+    let f = |x: f64| (((x + a) * x + b) * x + c) * x + d;
+    let df = |x: f64| ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+    let mut roots: Vec<f64> = roots
+        .into_iter()
+        .map(|y| {
+            let mut x = y - shift;
+            for _ in 0..2 {
+                let slope = df(x);
+                if slope != 0.0 {
+                    x -= f(x) / slope;
+                }
+            }
+            x
+        })
+        .collect();
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal));
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &[f64], b: &[f64]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-9)
+    }
+
+    #[test]
+    fn polynomial_roots() {
+        assert!(close(&quadratic(1.0, -3.0, 2.0), &[1.0, 2.0]));
+        assert!(close(&quadratic(1.0, 0.0, 1.0), &[]));
+        assert!(close(&quadratic(0.0, 2.0, -1.0), &[0.5]));
+
+        // (x - 1)(x - 2)(x - 3)
+        assert!(close(&cubic(-6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]));
+        // (x - 1)(x² + 1)
+        assert!(close(&cubic(-1.0, 1.0, -1.0), &[1.0]));
+
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert!(close(
+            &quartic(-10.0, 35.0, -50.0, 24.0),
+            &[1.0, 2.0, 3.0, 4.0]
+        ));
+        // (x² - 1)(x² - 4)
+        assert!(close(
+            &quartic(0.0, -5.0, 0.0, 4.0),
+            &[-2.0, -1.0, 1.0, 2.0]
+        ));
+        // (x² + 1)(x² + 2)
+        assert!(close(&quartic(0.0, 3.0, 0.0, 2.0), &[]));
+
+        // Invalid coefficients give invalid roots rather than a panic
+        assert!(cubic(f64::NAN, 1.0, 1.0).iter().all(|x| x.is_nan()));
+        assert!(quartic(f64::NAN, 1.0, 1.0, 1.0).iter().all(|x| x.is_nan()));
+    }
+}
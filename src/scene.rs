@@ -4,8 +4,12 @@ extern crate rand;
 extern crate rand_xoshiro;
 extern crate rayon;
 
+use crate::bsdf::Shading;
+use crate::bvh::*;
+use crate::error;
 use crate::things::*;
 use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
 use rand::prelude::*;
 use rand_xoshiro::rand_core::SeedableRng;
@@ -13,7 +17,10 @@ use rand_xoshiro::Xoshiro256Plus;
 use rayon::prelude::*;
 use std::error::Error;
 
-#[pyclass]
+/// The most transparent surfaces a ray passes through before it is taken as absorbed
+static MAX_CROSSINGS: usize = 64;
+
+#[cfg_attr(feature = "python", pyclass)]
 #[derive(Clone)]
 pub struct Lens {
     /// The distance of the focal plane from the screen
@@ -22,9 +29,16 @@ pub struct Lens {
     pub aperture: f32,
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Lens {
     #[new]
+    fn py_new(focus_distance: f32, aperture: f32) -> Self {
+        Lens::new(focus_distance, aperture)
+    }
+}
+
+impl Lens {
     pub fn new(focus_distance: f32, aperture: f32) -> Self {
         Lens {
             focus_distance,
@@ -33,7 +47,38 @@ impl Lens {
     }
 }
 
-#[pyclass]
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Shutter {
+    /// The time the shutter opens at
+    pub open: f32,
+    /// The time the shutter closes at
+    pub close: f32,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Shutter {
+    #[new]
+    fn py_new(open: f32, close: f32) -> PyResult<Self> {
+        Ok(Shutter::new(open, close)?)
+    }
+}
+
+impl Shutter {
+    /// Returns a shutter open between two times, the first not after the second
+    pub fn new(open: f32, close: f32) -> error::Result<Self> {
+        if close < open || open.is_nan() || close.is_nan() {
+            return Err(error::Error::Value(format!(
+                "shutter closes at {} before opening at {}",
+                close, open
+            )));
+        }
+        Ok(Shutter { open, close })
+    }
+}
+
+#[cfg_attr(feature = "python", pyclass)]
 #[derive(Clone)]
 pub struct Camera {
     /// The normal vector of the plain of the screen
@@ -46,12 +91,35 @@ pub struct Camera {
     distance: f32,
     /// An optional lens
     lens: Option<Lens>,
+    /// An optional shutter interval to sample ray times from
+    shutter: Option<Shutter>,
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Camera {
     #[new]
-    pub fn new(normal: Ray, width: f32, height: f32, distance: f32, lens: Option<Lens>) -> Self {
+    fn py_new(
+        normal: Ray,
+        width: f32,
+        height: f32,
+        distance: f32,
+        lens: Option<Lens>,
+        shutter: Option<Shutter>,
+    ) -> Self {
+        Camera::new(normal, width, height, distance, lens, shutter)
+    }
+}
+
+impl Camera {
+    pub fn new(
+        normal: Ray,
+        width: f32,
+        height: f32,
+        distance: f32,
+        lens: Option<Lens>,
+        shutter: Option<Shutter>,
+    ) -> Self {
         let x = -width * normal.direction.cross(UNIT_Y).normalized();
         let y = height * normal.direction.cross(UNIT_X).normalized();
         Camera {
@@ -60,13 +128,51 @@ impl Camera {
             y,
             distance,
             lens,
+            shutter,
         }
     }
-}
 
-impl Camera {
+    /// Returns a camera with a screen spanned by the given vectors
+    ///
+    /// # Arguments
+    ///
+    /// * `normal` - the ray from the center of the screen into the scene
+    /// * `x` - the vector along the width of the screen, pointing to the right of the image
+    /// * `y` - the vector along the height of the screen, pointing down the image
+    /// * `distance` - the distance of the eye behind the screen
+    pub fn oriented(normal: Ray, x: Point, y: Point, distance: f32) -> Self {
+        Camera {
+            normal,
+            x,
+            y,
+            distance,
+            lens: None,
+            shutter: None,
+        }
+    }
+
+    /// Returns the camera with a lens, blurring what is out of focus
+    pub fn with_lens(self, lens: Lens) -> Self {
+        Camera {
+            lens: Some(lens),
+            ..self
+        }
+    }
+
+    /// Returns the width of the screen
+    pub fn width(&self) -> f32 {
+        self.x.norm()
+    }
+
+    /// Returns the height of the screen
+    pub fn height(&self) -> f32 {
+        self.y.norm()
+    }
+
     /// Returns a ray for a given point of the screen
     ///
+    /// With a shutter, the ray is cast at a random time while the shutter is open.
+    ///
     /// # Arguments
     ///
     /// * `x` - the fractional position along the screen width
@@ -76,6 +182,10 @@ impl Camera {
         let base = self.normal.base + (x - 0.5) * self.x + (y - 0.5) * self.y;
         let direction = base - self.normal.at(-self.distance);
         let ray = Ray::new(base, direction);
+        let time = match &self.shutter {
+            Some(shutter) => shutter.open + rng.gen::<f32>() * (shutter.close - shutter.open),
+            None => 0.0,
+        };
         if let Some(lens) = &self.lens {
             let focal_point = ray.at(lens.focus_distance / (direction * self.normal.direction));
             let mut x = 1.0;
@@ -88,9 +198,9 @@ impl Camera {
             x *= lens.aperture;
             y *= lens.aperture;
             let direction = focal_point - (base + x * self.x + y * self.y);
-            Ray::new(base, direction)
+            Ray::new(base, direction).with_time(time)
         } else {
-            ray
+            ray.with_time(time)
         }
     }
 }
@@ -99,7 +209,9 @@ pub struct Scene {
     camera: Camera,
     samples: usize,
     bounces: usize,
-    things: Vec<Box<dyn Thing + Sync>>,
+    /// Mixed into the seeds of the random numbers of each pixel
+    seed: u64,
+    things: Vec<SharedThing>,
 }
 
 impl Scene {
@@ -115,104 +227,56 @@ impl Scene {
             camera,
             samples: samples.unwrap_or(500),
             bounces: bounces.unwrap_or(6),
+            seed: 0,
             things: Vec::new(),
         }
     }
 
-    pub fn add<T>(&mut self, thing: T)
-    where
-        T: Thing + Sync + 'static,
-    {
-        self.things.push(Box::new(thing))
+    /// Returns the scene rendering with different random numbers, for independent images
+    pub fn with_seed(self, seed: u64) -> Self {
+        Scene { seed, ..self }
     }
 
-    /// Schlick's approximation for the reflection coefficient
-    fn reflect(n_frac: f32, cos_in: f32) -> f32 {
-        let r0 = ((n_frac - 1.0) / (n_frac + 1.0)).powi(2);
-        r0 + (1.0 - r0) * (1.0 - cos_in).powi(5)
+    pub fn add(&mut self, thing: SharedThing) {
+        self.things.push(thing)
     }
 
-    fn bounce(
-        &self,
-        ray: &Ray,
-        depth: usize,
-        skip: Option<usize>,
-        mut rng: &mut dyn RngCore,
-    ) -> Color {
-        if depth == 0 {
-            return Color::black();
-        }
+    /// Returns the acceleration structure holding all things of the scene
+    pub fn world(&self) -> Bvh {
+        Bvh::new(self.things.clone())
+    }
 
-        let hit = ray.intersect(&self.things, skip);
-        if hit.is_none() {
+    fn bounce(&self, world: &Bvh, ray: &Ray, depth: usize, rng: &mut dyn RngCore) -> Color {
+        if depth == 0 {
             return Color::black();
         }
 
-        let (distance, index) = hit.unwrap();
-        let thing = &self.things[index];
-        let material = thing.material();
-        let impact = ray.at(distance);
-        let normal = thing.normal(&impact, &ray.direction);
-
-        let mut intensity = material.emittance * material.color;
-        if material.specularity > 0.0 {
-            let reflected = ray.direction - 2.0 * normal * (normal * ray.direction);
-            let reflection = Ray::new(
-                impact,
-                (reflected + material.hardness * reflected.randomize(rng)).normalized(),
-            );
-            intensity +=
-                material.specularity * self.bounce(&reflection, depth - 1, Some(index), rng);
-        }
-
-        if material.diffusion > 0.0 {
-            let scatter = Ray::new(impact, normal.randomize(&mut rng));
-            intensity += material.color
-                * material.diffusion
-                * self.bounce(&scatter, depth - 1, Some(index), rng);
-        }
-
-        if material.refraction > 0.0 {
-            let cos_in = normal * ray.direction;
-            let n_frac = if cos_in < 0.0 {
-                1.0 / 1.5 // outside material
-            } else {
-                1.5
+        // passing through transparent parts does not count as a bounce, but is limited
+        let mut ray = *ray;
+        let mut crossings = 0;
+        let (hit, at) = loop {
+            let hit = match world.hit_by(&ray) {
+                Some(hit) => hit,
+                None => return Color::black(),
             };
-            let cos_out_sqr = 1.0 - n_frac * n_frac * (1.0 - cos_in * cos_in);
-            let reflection = Ray::new(impact, ray.direction - 2.0 * normal * cos_in);
-            if cos_out_sqr < 0.0 {
-                intensity += material.refraction
-                    * self.bounce(&reflection, depth - 1, Some(index), &mut rng);
-            } else {
-                let in_plane = (ray.direction - normal * cos_in) * n_frac;
-                let along_normal =
-                    normal * 1.0f32.copysign(cos_in) * (1.0 - in_plane.norm_sqr()).sqrt();
-                let transmission = Ray::new(impact, in_plane + along_normal);
-                let refl = Scene::reflect(n_frac, cos_in.abs());
-                let trans = 1.0 - refl;
-                if self.bounces - depth < 2 {
-                    intensity += material.refraction
-                        * (refl * self.bounce(&reflection, depth - 1, Some(index), &mut rng)
-                            + trans * self.bounce(&transmission, depth - 1, Some(index), &mut rng));
-                } else {
-                    let p = 0.25 + 0.5 * refl; // values: 0.25 - 0.75
-                    let dist = rand::distributions::Uniform::new_inclusive(0.0, 1.0);
-                    if rng.sample(dist) < p {
-                        intensity += material.refraction
-                            * refl
-                            * self.bounce(&reflection, depth - 1, Some(index), &mut rng)
-                            / p;
-                    } else {
-                        intensity += material.refraction
-                            * trans
-                            * self.bounce(&transmission, depth - 1, Some(index), &mut rng)
-                            / (1.0 - p);
-                    }
-                }
+            let at = Shading::at(&ray, &hit);
+            if at.opacity >= 1.0 || rng.gen::<f32>() < at.opacity {
+                break (hit, at);
             }
-        }
+            crossings += 1;
+            if crossings > MAX_CROSSINGS {
+                return Color::black();
+            }
+            ray = ray.spawn(at.point, ray.direction);
+        };
+        let wo = -ray.direction;
+        let bsdf = hit.material.bsdf();
 
+        let mut intensity = at.emission * bsdf.emission(&at, wo);
+        if let Some(sample) = bsdf.sample(&at, wo, rng) {
+            let scattered = ray.spawn(at.point, sample.direction);
+            intensity += sample.weight * self.bounce(world, &scattered, depth - 1, rng);
+        }
         intensity
     }
 
@@ -220,13 +284,14 @@ impl Scene {
     ///
     /// # Arguments
     ///
+    /// * `world` - the things of the scene, as returned by `world`
     /// * `x` - the fractional position along the width of the screen
     /// * `y` - the fractional position along the height of the screen
     /// * `rng` - the random number generator to use
-    fn render_point(&self, x: f32, y: f32, mut rng: &mut dyn RngCore) -> [u8; 3] {
+    fn render_point(&self, world: &Bvh, x: f32, y: f32, mut rng: &mut dyn RngCore) -> [u8; 3] {
         let intensity = (0..self.samples).fold(Color::black(), |sum, _i| {
             let ray = self.camera.view(x, y, &mut rng);
-            sum + self.bounce(&ray, self.bounces, None, &mut rng)
+            sum + self.bounce(world, &ray, self.bounces, &mut rng)
         }) / self.samples as f32;
 
         [
@@ -243,8 +308,24 @@ impl Scene {
     /// * `filename` - the name to save the final image under
     /// * `dpi` - the scaling factor for the image resolution
     pub fn render(&self, filename: &str, dpi: u32) -> Result<(), Box<dyn Error>> {
-        let width = (dpi as f32 * self.camera.x.norm()) as u32;
-        let height = (dpi as f32 * self.camera.y.norm()) as u32;
+        let width = (dpi as f32 * self.camera.width()).round() as u32;
+        let height = (dpi as f32 * self.camera.height()).round() as u32;
+        self.render_pixels(filename, width, height)
+    }
+
+    /// Render the defined scene into an image of the given size in pixels
+    ///
+    /// The screen of the camera is stretched to the image if their aspect ratios differ.
+    pub fn render_pixels(
+        &self,
+        filename: &str,
+        width: u32,
+        height: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        if width == 0 || height == 0 {
+            return Err(format!("{}: the image would be empty", filename).into());
+        }
+        let world = self.world();
         let mut imgbuf: image::RgbImage = image::ImageBuffer::new(width, height);
         let bar = ProgressBar::new(width as u64 * height as u64);
         bar.set_style(
@@ -258,9 +339,12 @@ impl Scene {
             .par_bridge()
             .progress_with(bar)
             .for_each(|(x, y, pixel)| {
-                let mut rng =
-                    Xoshiro256Plus::seed_from_u64((x as u64) << 32 | (y as u64 & 0xffffffff));
+                let pixel_seed = (x as u64) << 32 | (y as u64 & 0xffffffff);
+                let mut rng = Xoshiro256Plus::seed_from_u64(
+                    pixel_seed ^ self.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15),
+                );
                 *pixel = image::Rgb(self.render_point(
+                    &world,
                     x as f32 / width as f32,
                     y as f32 / height as f32,
                     &mut rng,
@@ -274,11 +358,13 @@ impl Scene {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::texture::{Texture, Textures};
+    use std::sync::Arc;
 
     #[test]
     fn camera_rays() {
         let normal = Ray::new(Point::new(0.0, 0.0, -1.0), Point::new(0.0, 0.0, 1.0));
-        let c = Camera::new(normal, 2.0, 2.0, 2.0, None);
+        let c = Camera::new(normal, 2.0, 2.0, 2.0, None, None);
 
         let mut rng = thread_rng();
 
@@ -289,22 +375,71 @@ mod tests {
         assert_eq!(c.view(0.5, 1.0, &mut rng), edge_ray);
     }
 
+    #[test]
+    fn camera_shutter() {
+        let normal = Ray::new(Point::new(0.0, 0.0, -1.0), Point::new(0.0, 0.0, 1.0));
+        let c = Camera::new(
+            normal,
+            2.0,
+            2.0,
+            2.0,
+            None,
+            Some(Shutter::new(0.5, 1.5).unwrap()),
+        );
+
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        for _i in 0..10 {
+            let ray = c.view(0.5, 0.5, &mut rng);
+            assert!((0.5..=1.5).contains(&ray.time));
+            assert_eq!(ray.direction, Point::new(0.0, 0.0, 1.0));
+        }
+        assert!(Shutter::new(1.5, 0.5).is_err());
+    }
+
     #[test]
     fn basic_scene() {
         let normal = Ray::new(Point::new(0.0, 0.0, -1.0), Point::new(0.0, 0.0, 1.0));
-        let c = Camera::new(normal, 2.0, 2.0, 2.0, None);
+        let c = Camera::new(normal, 2.0, 2.0, 2.0, None, None);
 
         let mut scene = Scene::new(c, None, None);
-        scene.add(Sphere::new(
+        scene.add(Arc::new(Sphere::new(
             Point::new(0.0, 0.0, 0.0),
             0.5,
             Material::light(Color::white()),
-        ));
+        )));
 
+        let world = scene.world();
         let mut rng = Xoshiro256Plus::seed_from_u64(0);
-        let color = scene.render_point(0.5, 0.5, &mut rng);
+        let color = scene.render_point(&world, 0.5, 0.5, &mut rng);
         assert_eq!(color, [255, 255, 255]);
-        let color = scene.render_point(0.0, 0.0, &mut rng);
+        let color = scene.render_point(&world, 0.0, 0.0, &mut rng);
         assert_eq!(color, [0, 0, 0]);
     }
+
+    #[test]
+    fn transparent_surfaces() {
+        let normal = Ray::new(Point::new(0.0, 0.0, -10.0), Point::new(0.0, 0.0, 1.0));
+        let c = Camera::new(normal, 2.0, 2.0, 2.0, None, None);
+        let clear = Material::new(0.0, 0.0, 1.0, 0.0, 0.0, Color::white()).textured(Textures {
+            opacity: Some(Texture::constant(0.0)),
+            ..Textures::default()
+        });
+        let shells = |count: usize| {
+            let mut scene = Scene::new(c.clone(), None, None);
+            scene.add(Arc::new(Sphere::new(
+                ORIGIN,
+                0.5,
+                Material::light(Color::white()),
+            )));
+            for k in 0..count {
+                let radius = 1.0 + 0.1 * k as f32;
+                scene.add(Arc::new(Sphere::new(ORIGIN, radius, clear.clone())));
+            }
+            let mut rng = Xoshiro256Plus::seed_from_u64(0);
+            scene.render_point(&scene.world(), 0.5, 0.5, &mut rng)
+        };
+        assert_eq!(shells(10), [255, 255, 255]);
+        // rays crossing too many surfaces are absorbed
+        assert_eq!(shells(80), [0, 0, 0]);
+    }
 }
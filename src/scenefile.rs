@@ -0,0 +1,990 @@
+//! A declarative description of scenes, stored as TOML or JSON
+//!
+//! A scene file names its materials once and refers to them from objects by name:
+//!
+//! ```toml
+//! [camera]
+//! center = [0.0, 0.0, -10.0]
+//! direction = [0.0, 0.0, 1.0]
+//! width = 0.7
+//! height = 0.7
+//! distance = 2.0
+//!
+//! [materials.red]
+//! diffusion = 1.0
+//! color = [1.0, 0.0, 0.0]
+//!
+//! [[objects]]
+//! type = "sphere"
+//! center = [0.0, 0.0, 1.0]
+//! radius = 0.3
+//! material = "red"
+//!
+//! [[lights]]
+//! type = "sphere"
+//! center = [-5.0, -5.0, -5.0]
+//! radius = 5.0
+//! color = [1.0, 1.0, 1.0]
+//!
+//! [render]
+//! samples = 600
+//! output = "example.jpg"
+//! ```
+//!
+//! Things do not keep what they were built from, so a scene built from things cannot be saved
+//! as a whole.  Plain materials, spheres, triangles and rhomboids can be added to a scene file
+//! after being built, see `SceneFile::add`; everything else has to be described directly.
+
+use crate::asset::*;
+use crate::error::{Error, Result};
+use crate::mesh::*;
+use crate::scene::*;
+use crate::shapes::*;
+use crate::texture::*;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use pyo3::types::{PyDict, PyFloat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type Vector = [f32; 3];
+
+fn point(v: Vector) -> Point {
+    Point::new(v[0], v[1], v[2])
+}
+
+fn color(c: Vector) -> Color {
+    Color::new(c[0], c[1], c[2])
+}
+
+fn vector(p: Point) -> Vector {
+    [p.x, p.y, p.z]
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LensDescription {
+    pub focus_distance: f32,
+    pub aperture: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShutterDescription {
+    pub open: f32,
+    pub close: f32,
+}
+
+/// The camera, with the arguments of `Camera`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraDescription {
+    /// The center of the screen
+    pub center: Vector,
+    /// The direction the camera looks in
+    pub direction: Vector,
+    pub width: f32,
+    pub height: f32,
+    /// The distance of the eye behind the screen
+    pub distance: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lens: Option<LensDescription>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shutter: Option<ShutterDescription>,
+}
+
+/// A material, with all properties but the color zero if missing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaterialDescription {
+    #[serde(default)]
+    pub specularity: f32,
+    #[serde(default)]
+    pub hardness: f32,
+    #[serde(default)]
+    pub diffusion: f32,
+    #[serde(default)]
+    pub refraction: f32,
+    #[serde(default)]
+    pub emittance: f32,
+    pub color: Vector,
+}
+
+impl MaterialDescription {
+    fn material(&self) -> Material {
+        Material::new(
+            self.specularity,
+            self.hardness,
+            self.diffusion,
+            self.refraction,
+            self.emittance,
+            color(self.color),
+        )
+    }
+}
+
+/// Only plain materials can be described, as BSDFs and textures have no description
+impl TryFrom<&Material> for MaterialDescription {
+    type Error = Error;
+
+    fn try_from(material: &Material) -> Result<Self> {
+        if material.bsdf.is_some() || material.textures != Textures::default() {
+            return Err(Error::Value(
+                "materials with a BSDF or textures cannot be described".into(),
+            ));
+        }
+        let c = material.color;
+        Ok(MaterialDescription {
+            specularity: material.specularity,
+            hardness: material.hardness,
+            diffusion: material.diffusion,
+            refraction: material.refraction,
+            emittance: material.emittance,
+            color: [c.r, c.g, c.b],
+        })
+    }
+}
+
+/// Things that can be added to a scene file after being built
+pub trait Describe {
+    /// Returns the material the thing is made of
+    fn material(&self) -> &Material;
+
+    /// Returns the description of the thing, made of the material of the given name
+    fn describe(&self, material: String) -> ObjectDescription;
+}
+
+impl Describe for Sphere {
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn describe(&self, material: String) -> ObjectDescription {
+        ObjectDescription::Sphere {
+            center: vector(self.center),
+            radius: self.radius,
+            material,
+        }
+    }
+}
+
+impl Describe for Triangle {
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn describe(&self, material: String) -> ObjectDescription {
+        ObjectDescription::Triangle {
+            a: vector(self.a),
+            b: vector(self.b),
+            c: vector(self.c),
+            material,
+        }
+    }
+}
+
+impl Describe for Rhomboid {
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn describe(&self, material: String) -> ObjectDescription {
+        ObjectDescription::Rhomboid {
+            base: vector(self.base),
+            x: vector(self.width * self.x),
+            y: vector(self.height * self.y),
+            material,
+        }
+    }
+}
+
+/// A thing of the scene, made of a material given by name
+///
+/// Files of models and assets are found relative to the scene file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ObjectDescription {
+    Sphere {
+        center: Vector,
+        radius: f32,
+        material: String,
+    },
+    Triangle {
+        a: Vector,
+        b: Vector,
+        c: Vector,
+        material: String,
+    },
+    Rhomboid {
+        base: Vector,
+        x: Vector,
+        y: Vector,
+        material: String,
+    },
+    Plane {
+        point: Vector,
+        normal: Vector,
+        material: String,
+    },
+    Disk {
+        center: Vector,
+        normal: Vector,
+        radius: f32,
+        material: String,
+    },
+    Cylinder {
+        base: Vector,
+        axis: Vector,
+        radius: f32,
+        material: String,
+    },
+    Cone {
+        base: Vector,
+        axis: Vector,
+        radius: f32,
+        material: String,
+    },
+    Torus {
+        center: Vector,
+        axis: Vector,
+        major: f32,
+        minor: f32,
+        material: String,
+    },
+    Cuboid {
+        corner: Vector,
+        x: Vector,
+        y: Vector,
+        z: Vector,
+        material: String,
+    },
+    /// A mesh given by its vertices and faces, optionally subdivided
+    Mesh {
+        vertices: Vec<Vector>,
+        faces: Vec<Vec<usize>>,
+        material: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subdivision: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        levels: Option<usize>,
+    },
+    /// A mesh loaded from a PLY or STL file
+    Model { file: String, material: String },
+    /// The things of a glTF file, with their own materials
+    Asset { file: String },
+}
+
+/// A light, emitting its color times its intensity
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LightDescription {
+    Sphere {
+        center: Vector,
+        radius: f32,
+        color: Vector,
+        #[serde(default = "unit")]
+        intensity: f32,
+    },
+    Rhomboid {
+        base: Vector,
+        x: Vector,
+        y: Vector,
+        color: Vector,
+        #[serde(default = "unit")]
+        intensity: f32,
+    },
+}
+
+fn unit() -> f32 {
+    1.0
+}
+
+/// How to render the scene, see `render`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RenderDescription {
+    #[serde(default = "RenderDescription::default_dpi")]
+    pub dpi: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounces: Option<usize>,
+    #[serde(default = "RenderDescription::default_output")]
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl RenderDescription {
+    fn default_dpi() -> u32 {
+        100
+    }
+
+    fn default_output() -> String {
+        "render.png".to_string()
+    }
+}
+
+impl Default for RenderDescription {
+    fn default() -> Self {
+        RenderDescription {
+            dpi: RenderDescription::default_dpi(),
+            samples: None,
+            bounces: None,
+            output: RenderDescription::default_output(),
+            seed: None,
+        }
+    }
+}
+
+/// The contents of a scene file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Description {
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub render: RenderDescription,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// Returns the format of a file by its extension
+    pub fn of(filename: &str) -> Result<Format> {
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("");
+        match extension.to_lowercase().as_str() {
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::Value(format!("{}: unknown scene format", filename))),
+        }
+    }
+}
+
+impl Description {
+    pub fn parse(
+        text: &str,
+        format: Format,
+    ) -> std::result::Result<Self, Box<dyn std::error::Error>> {
+        Ok(match format {
+            Format::Toml => toml::from_str(text)?,
+            Format::Json => serde_json::from_str(text)?,
+        })
+    }
+
+    pub fn write(&self, format: Format) -> std::result::Result<String, Box<dyn std::error::Error>> {
+        Ok(match format {
+            Format::Toml => toml::to_string_pretty(self)?,
+            Format::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+
+    pub fn camera(&self) -> Result<Camera> {
+        let c = &self.camera;
+        let shutter = match &c.shutter {
+            Some(s) => Some(Shutter::new(s.open, s.close)?),
+            None => None,
+        };
+        Ok(Camera::new(
+            Ray::new(point(c.center), point(c.direction)),
+            c.width,
+            c.height,
+            c.distance,
+            c.lens
+                .as_ref()
+                .map(|l| Lens::new(l.focus_distance, l.aperture)),
+            shutter,
+        ))
+    }
+
+    /// Returns the things of the scene, with files found relative to `directory`
+    pub fn things(&self, directory: &Path) -> Result<Vec<SharedThing>> {
+        let materials: BTreeMap<&str, Material> = self
+            .materials
+            .iter()
+            .map(|(name, m)| (name.as_str(), m.material()))
+            .collect();
+        let material = |name: &str| {
+            materials
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::Value(format!("unknown material {}", name)))
+        };
+        let file = |name: &str| directory.join(name).to_string_lossy().into_owned();
+
+        let mut things: Vec<SharedThing> = Vec::new();
+        for object in &self.objects {
+            use ObjectDescription as O;
+            match object {
+                O::Sphere {
+                    center,
+                    radius,
+                    material: m,
+                } => things.push(Arc::new(Sphere::new(point(*center), *radius, material(m)?))),
+                O::Triangle {
+                    a,
+                    b,
+                    c,
+                    material: m,
+                } => things.push(Arc::new(Triangle::new(
+                    point(*a),
+                    point(*b),
+                    point(*c),
+                    material(m)?,
+                ))),
+                O::Rhomboid {
+                    base,
+                    x,
+                    y,
+                    material: m,
+                } => things.push(Arc::new(Rhomboid::new(
+                    point(*base),
+                    point(*x),
+                    point(*y),
+                    material(m)?,
+                ))),
+                O::Plane {
+                    point: p,
+                    normal,
+                    material: m,
+                } => things.push(Arc::new(Plane::new(
+                    point(*p),
+                    point(*normal),
+                    material(m)?,
+                )?)),
+                O::Disk {
+                    center,
+                    normal,
+                    radius,
+                    material: m,
+                } => things.push(Arc::new(Disk::new(
+                    point(*center),
+                    point(*normal),
+                    *radius,
+                    material(m)?,
+                )?)),
+                O::Cylinder {
+                    base,
+                    axis,
+                    radius,
+                    material: m,
+                } => things.push(Arc::new(Cylinder::new(
+                    point(*base),
+                    point(*axis),
+                    *radius,
+                    material(m)?,
+                )?)),
+                O::Cone {
+                    base,
+                    axis,
+                    radius,
+                    material: m,
+                } => things.push(Arc::new(Cone::new(
+                    point(*base),
+                    point(*axis),
+                    *radius,
+                    material(m)?,
+                )?)),
+                O::Torus {
+                    center,
+                    axis,
+                    major,
+                    minor,
+                    material: m,
+                } => things.push(Arc::new(Torus::new(
+                    point(*center),
+                    point(*axis),
+                    *major,
+                    *minor,
+                    material(m)?,
+                )?)),
+                O::Cuboid {
+                    corner,
+                    x,
+                    y,
+                    z,
+                    material: m,
+                } => things.push(Arc::new(Cuboid::new(
+                    point(*corner),
+                    point(*x),
+                    point(*y),
+                    point(*z),
+                    material(m)?,
+                )?)),
+                O::Mesh {
+                    vertices,
+                    faces,
+                    material: m,
+                    subdivision,
+                    levels,
+                } => things.push(Arc::new(Mesh::new(
+                    vertices.iter().cloned().map(point).collect(),
+                    faces.clone(),
+                    material(m)?,
+                    None,
+                    None,
+                    subdivision.as_deref(),
+                    *levels,
+                )?)),
+                O::Model {
+                    file: f,
+                    material: m,
+                } => things.push(Arc::new(Mesh::load(&file(f), material(m)?)?)),
+                O::Asset { file: f } => things.extend(Asset::load(&file(f))?.root().flatten()),
+            }
+        }
+        for light in &self.lights {
+            let emitting = |c: &Vector, intensity: f32| {
+                Material::new(0.0, 0.0, 0.0, 0.0, intensity, color(*c))
+            };
+            match light {
+                LightDescription::Sphere {
+                    center,
+                    radius,
+                    color: c,
+                    intensity,
+                } => things.push(Arc::new(Sphere::new(
+                    point(*center),
+                    *radius,
+                    emitting(c, *intensity),
+                ))),
+                LightDescription::Rhomboid {
+                    base,
+                    x,
+                    y,
+                    color: c,
+                    intensity,
+                } => things.push(Arc::new(Rhomboid::new(
+                    point(*base),
+                    point(*x),
+                    point(*y),
+                    emitting(c, *intensity),
+                ))),
+            }
+        }
+        Ok(things)
+    }
+}
+
+/// Converts a Python value into the JSON value of a scene file, with points and colors as lists
+#[cfg(feature = "python")]
+fn value(obj: &PyAny) -> PyResult<serde_json::Value> {
+    use serde_json::Value;
+
+    let vector = |v: Vector| Value::from(v.iter().map(|&c| f64::from(c)).collect::<Vec<_>>());
+    if obj.is_none() {
+        return Ok(Value::Null);
+    }
+    if let Ok(p) = obj.extract::<Point>() {
+        return Ok(vector([p.x, p.y, p.z]));
+    }
+    if let Ok(c) = obj.extract::<Color>() {
+        return Ok(vector([c.r, c.g, c.b]));
+    }
+    if let Ok(b) = obj.extract::<bool>() {
+        return Ok(Value::Bool(b));
+    }
+    if let Ok(f) = obj.extract::<&PyFloat>() {
+        return serde_json::Number::from_f64(f.value())
+            .map(Value::Number)
+            .ok_or_else(|| Error::Value("numbers must be finite".into()).into());
+    }
+    if let Ok(i) = obj.extract::<i64>() {
+        return Ok(Value::from(i));
+    }
+    if let Ok(s) = obj.extract::<String>() {
+        return Ok(Value::String(s));
+    }
+    if let Ok(d) = obj.extract::<&PyDict>() {
+        return table(Some(d));
+    }
+    let items = obj.extract::<Vec<&PyAny>>()?;
+    Ok(Value::Array(
+        items.into_iter().map(value).collect::<PyResult<_>>()?,
+    ))
+}
+
+/// Converts Python keyword arguments into a table of a scene file
+#[cfg(feature = "python")]
+fn table(kwargs: Option<&PyDict>) -> PyResult<serde_json::Value> {
+    let mut map = serde_json::Map::new();
+    for (key, item) in kwargs.into_iter().flat_map(|d| d.iter()) {
+        map.insert(key.extract()?, value(item)?);
+    }
+    Ok(serde_json::Value::Object(map))
+}
+
+/// Reads a description of part of a scene from Python keyword arguments
+#[cfg(feature = "python")]
+fn describe<T: serde::de::DeserializeOwned>(what: &str, kwargs: Option<&PyDict>) -> PyResult<T> {
+    serde_json::from_value(table(kwargs)?)
+        .map_err(|e| Error::Value(format!("{}: {}", what, e)).into())
+}
+
+/// A scene read from a TOML or JSON file, or built from Python
+///
+/// The render settings can be changed before rendering or saving the scene again.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct SceneFile {
+    description: Description,
+    /// The directory to find files of models and assets in
+    directory: PathBuf,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl SceneFile {
+    /// Creates an empty scene, taking the camera settings of a scene file as keyword arguments
+    ///
+    /// Files of models and assets are found relative to the current directory.
+    #[new]
+    #[args(camera = "**")]
+    fn py_new(camera: Option<&PyDict>) -> PyResult<Self> {
+        Ok(SceneFile::new(describe("camera", camera)?))
+    }
+
+    /// Loads a scene, telling TOML and JSON apart by the extension
+    #[staticmethod]
+    #[name = "load"]
+    fn py_load(filename: &str) -> PyResult<Self> {
+        Ok(SceneFile::load(filename)?)
+    }
+
+    /// Saves the scene, in TOML or JSON depending on the extension
+    #[name = "save"]
+    fn py_save(&self, filename: &str) -> PyResult<()> {
+        Ok(self.save(filename)?)
+    }
+
+    /// Renders the scene into the output file of its render settings
+    #[name = "render"]
+    fn py_render(&self) -> PyResult<()> {
+        Ok(self.render()?)
+    }
+
+    /// Adds or replaces a named material, with its properties as keyword arguments
+    #[name = "add_material"]
+    #[args(properties = "**")]
+    fn py_add_material(&mut self, name: String, properties: Option<&PyDict>) -> PyResult<()> {
+        let material = describe(&name, properties)?;
+        self.description.materials.insert(name, material);
+        Ok(())
+    }
+
+    /// Adds an object, with its type and properties as keyword arguments as in a scene file
+    #[name = "add_object"]
+    #[args(object = "**")]
+    fn py_add_object(&mut self, object: Option<&PyDict>) -> PyResult<()> {
+        self.description.objects.push(describe("object", object)?);
+        Ok(())
+    }
+
+    /// Adds a light, with its type and properties as keyword arguments as in a scene file
+    #[name = "add_light"]
+    #[args(light = "**")]
+    fn py_add_light(&mut self, light: Option<&PyDict>) -> PyResult<()> {
+        self.description.lights.push(describe("light", light)?);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_samples(&self) -> PyResult<Option<usize>> {
+        Ok(self.description.render.samples)
+    }
+
+    #[setter]
+    fn set_samples(&mut self, samples: Option<usize>) -> PyResult<()> {
+        self.description.render.samples = samples;
+        Ok(())
+    }
+
+    #[getter]
+    fn get_bounces(&self) -> PyResult<Option<usize>> {
+        Ok(self.description.render.bounces)
+    }
+
+    #[setter]
+    fn set_bounces(&mut self, bounces: Option<usize>) -> PyResult<()> {
+        self.description.render.bounces = bounces;
+        Ok(())
+    }
+
+    #[getter]
+    fn get_dpi(&self) -> PyResult<u32> {
+        Ok(self.description.render.dpi)
+    }
+
+    #[setter]
+    fn set_dpi(&mut self, dpi: u32) -> PyResult<()> {
+        self.description.render.dpi = dpi;
+        Ok(())
+    }
+
+    #[getter]
+    fn get_output(&self) -> PyResult<String> {
+        Ok(self.description.render.output.clone())
+    }
+
+    #[setter]
+    fn set_output(&mut self, output: String) -> PyResult<()> {
+        self.description.render.output = output;
+        Ok(())
+    }
+}
+
+impl SceneFile {
+    /// Creates a scene without any materials, objects or lights, with default render settings
+    pub fn new(camera: CameraDescription) -> Self {
+        SceneFile {
+            description: Description {
+                camera,
+                materials: BTreeMap::new(),
+                objects: Vec::new(),
+                lights: Vec::new(),
+                render: RenderDescription::default(),
+            },
+            directory: PathBuf::new(),
+        }
+    }
+
+    /// Loads a scene, telling TOML and JSON apart by the extension
+    pub fn load(filename: &str) -> Result<Self> {
+        let format = Format::of(filename)?;
+        let text = std::fs::read_to_string(filename)
+            .map_err(|e| Error::Io(format!("{}: {}", filename, e)))?;
+        let description = Description::parse(&text, format)
+            .map_err(|e| Error::Value(format!("{}: {}", filename, e)))?;
+        let directory = Path::new(filename)
+            .parent()
+            .map_or_else(PathBuf::new, Path::to_path_buf);
+        Ok(SceneFile {
+            description,
+            directory,
+        })
+    }
+
+    /// Saves the scene, in TOML or JSON depending on the extension
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let text = self
+            .description
+            .write(Format::of(filename)?)
+            .map_err(|e| Error::Value(format!("{}: {}", filename, e)))?;
+        std::fs::write(filename, text).map_err(|e| Error::Io(format!("{}: {}", filename, e)))
+    }
+
+    /// Renders the scene into the output file of its render settings
+    pub fn render(&self) -> Result<()> {
+        let settings = &self.description.render;
+        self.scene()?
+            .render(&settings.output, settings.dpi)
+            .map_err(|e| Error::Io(format!("{}: {}", settings.output, e)))
+    }
+
+    /// Adds a thing built before, naming its material after the materials already there
+    ///
+    /// A material equal to one already in the scene is shared rather than added again.
+    pub fn add(&mut self, thing: &dyn Describe) -> Result<()> {
+        let material = MaterialDescription::try_from(thing.material())?;
+        let materials = &mut self.description.materials;
+        let name = match materials.iter().find(|(_, known)| **known == material) {
+            Some((name, _)) => name.clone(),
+            None => {
+                let name = (materials.len()..)
+                    .map(|i| format!("material{}", i))
+                    .find(|name| !materials.contains_key(name))
+                    .unwrap();
+                materials.insert(name.clone(), material);
+                name
+            }
+        };
+        self.description.objects.push(thing.describe(name));
+        Ok(())
+    }
+
+    /// Returns the description of the scene, to change it before rendering
+    pub fn description_mut(&mut self) -> &mut Description {
+        &mut self.description
+    }
+
+    /// Returns the scene ready for rendering
+    pub fn scene(&self) -> Result<Scene> {
+        let settings = &self.description.render;
+        let mut scene = Scene::new(
+            self.description.camera()?,
+            settings.samples,
+            settings.bounces,
+        )
+        .with_seed(settings.seed.unwrap_or(0));
+        for thing in self.description.things(&self.directory)? {
+            scene.add(thing);
+        }
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scene_files_round_trip() {
+        let text = r#"
+            [camera]
+            center = [0.0, 0.0, -10.0]
+            direction = [0.0, 0.0, 1.0]
+            width = 0.7
+            height = 0.7
+            distance = 2.0
+            lens = {focus_distance = 10.0, aperture = 0.1}
+
+            [materials.red]
+            diffusion = 1.0
+            color = [1.0, 0.0, 0.0]
+
+            [[objects]]
+            type = "sphere"
+            center = [0.0, 0.0, 1.0]
+            radius = 0.3
+            material = "red"
+
+            [[objects]]
+            type = "mesh"
+            vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            faces = [[0, 1, 2]]
+            material = "red"
+
+            [[lights]]
+            type = "rhomboid"
+            base = [-1.0, -5.0, -1.0]
+            x = [2.0, 0.0, 0.0]
+            y = [0.0, 0.0, 2.0]
+            color = [1.0, 1.0, 1.0]
+            intensity = 4.0
+
+            [render]
+            samples = 10
+        "#;
+        let description = Description::parse(text, Format::Toml).unwrap();
+        assert_eq!(description.render.dpi, 100);
+        assert_eq!(description.render.samples, Some(10));
+        assert_eq!(description.objects.len(), 2);
+
+        let things = description.things(Path::new("")).unwrap();
+        assert_eq!(things.len(), 3);
+        let light = things[2]
+            .hit_by(&Ray::new(ORIGIN, -UNIT_Y))
+            .unwrap()
+            .material;
+        assert_eq!(light.emittance, 4.0);
+
+        for &format in &[Format::Toml, Format::Json] {
+            let written = description.write(format).unwrap();
+            assert_eq!(Description::parse(&written, format).unwrap(), description);
+        }
+
+        let mut unknown = description;
+        unknown.materials.clear();
+        assert!(unknown.things(Path::new("")).is_err());
+        assert!(Description::parse("[camera]\nwidth = 1.0\n", Format::Toml).is_err());
+        assert!(Format::of("scene.yaml").is_err());
+
+        let example = Description::parse(include_str!("../example.toml"), Format::Toml).unwrap();
+        assert_eq!(example.things(Path::new("")).unwrap().len(), 12);
+    }
+
+    #[test]
+    fn built_scenes_can_be_saved() {
+        let mut built = SceneFile::new(CameraDescription {
+            center: [0.0, 0.0, -10.0],
+            direction: [0.0, 0.0, 1.0],
+            width: 0.7,
+            height: 0.7,
+            distance: 2.0,
+            lens: None,
+            shutter: None,
+        });
+        let description = built.description_mut();
+        description.materials.insert(
+            "red".into(),
+            MaterialDescription {
+                specularity: 0.0,
+                hardness: 0.0,
+                diffusion: 1.0,
+                refraction: 0.0,
+                emittance: 0.0,
+                color: [1.0, 0.0, 0.0],
+            },
+        );
+        description.objects.push(ObjectDescription::Sphere {
+            center: [0.0, 0.0, 1.0],
+            radius: 0.3,
+            material: "red".into(),
+        });
+        assert_eq!(built.description.things(Path::new("")).unwrap().len(), 1);
+
+        let filename = std::env::temp_dir().join("pathetic-built-scene.json");
+        let filename = filename.to_str().unwrap();
+        built.save(filename).unwrap();
+        let loaded = SceneFile::load(filename).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        assert_eq!(loaded.description, built.description);
+    }
+
+    #[test]
+    fn built_things_can_be_added() {
+        let mut built = SceneFile::new(CameraDescription {
+            center: [0.0, 0.0, -10.0],
+            direction: [0.0, 0.0, 1.0],
+            width: 0.7,
+            height: 0.7,
+            distance: 2.0,
+            lens: None,
+            shutter: None,
+        });
+        let red = Material::new(0.0, 0.0, 1.0, 0.0, 0.0, Color::red());
+        let sphere = Sphere::new(UNIT_Z, 0.3, red.clone());
+        let floor = Rhomboid::new(-UNIT_Y, 2.0 * UNIT_X, 3.0 * UNIT_Z, red.clone());
+        let light = Triangle::new(ORIGIN, UNIT_X, UNIT_Y, Material::light(Color::white()));
+        for thing in &[&sphere as &dyn Describe, &floor, &light] {
+            built.add(*thing).unwrap();
+        }
+        let description = &built.description;
+        assert_eq!(description.materials.len(), 2);
+        assert_eq!(description.materials["material0"].color, [1.0, 0.0, 0.0]);
+        assert_eq!(
+            description.objects[1],
+            ObjectDescription::Rhomboid {
+                base: [0.0, -1.0, 0.0],
+                x: [2.0, 0.0, 0.0],
+                y: [0.0, 0.0, 3.0],
+                material: "material0".into(),
+            }
+        );
+        let ray = Ray::new(-UNIT_Z, UNIT_Z);
+        let things = description.things(Path::new("")).unwrap();
+        let hit = things[0].hit_by(&ray).unwrap();
+        assert_eq!(hit.material, red);
+        assert_eq!(hit.distance, sphere.hit_by(&ray).unwrap().distance);
+
+        let textured = Material {
+            textures: Textures {
+                color: Some(Texture::checker(0.5, Space::Uv).unwrap()),
+                ..Textures::default()
+            },
+            ..red
+        };
+        assert!(built.add(&Sphere::new(ORIGIN, 1.0, textured)).is_err());
+    }
+}
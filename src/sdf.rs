@@ -0,0 +1,407 @@
+use crate::bvh::*;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::sync::Arc;
+
+/// How far rays are marched through fields without finite bounds
+static MAX_DISTANCE: f32 = 1000.0;
+
+/// The radius beyond which points escape the iteration of the Mandelbulb
+///
+/// Points farther out escape right away with a positive distance, so the Mandelbulb of any power
+/// and number of iterations lies within a sphere of this radius.
+static MANDELBULB_BAILOUT: f32 = 2.0;
+
+/// The building blocks of signed distance functions
+enum Node {
+    Sphere(f32),
+    Cuboid(Point),
+    Torus(f32, f32),
+    Mandelbulb(f32, usize),
+    Translated(Point, Arc<Node>),
+    Union(Arc<Node>, Arc<Node>),
+    SmoothUnion(Arc<Node>, Arc<Node>, f32),
+    Repeated(Point, Arc<Node>),
+}
+
+impl Node {
+    /// Returns the distance from a point to the surface, negative inside
+    fn distance(&self, p: Point) -> f32 {
+        match self {
+            Node::Sphere(radius) => p.norm() - radius,
+            Node::Cuboid(half) => {
+                let q = Point::new(p.x.abs() - half.x, p.y.abs() - half.y, p.z.abs() - half.z);
+                let outside = Point::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+                outside.norm() + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            Node::Torus(major, minor) => {
+                let ring = (p.x * p.x + p.y * p.y).sqrt() - major;
+                (ring * ring + p.z * p.z).sqrt() - minor
+            }
+            Node::Mandelbulb(power, iterations) => Node::mandelbulb(p, *power, *iterations),
+            Node::Translated(offset, node) => node.distance(p - *offset),
+            Node::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Node::SmoothUnion(a, b, k) => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db + (da - db) * h - k * h * (1.0 - h)
+            }
+            Node::Repeated(period, node) => {
+                let wrap = |x: f32, period: f32| {
+                    if period > 0.0 {
+                        x - period * (x / period).round()
+                    } else {
+                        x
+                    }
+                };
+                node.distance(Point::new(
+                    wrap(p.x, period.x),
+                    wrap(p.y, period.y),
+                    wrap(p.z, period.z),
+                ))
+            }
+        }
+    }
+
+    /// Estimates the distance to the Mandelbulb from the growth of its iteration
+    fn mandelbulb(p: Point, power: f32, iterations: usize) -> f32 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.norm();
+        for _ in 0..iterations {
+            if r > MANDELBULB_BAILOUT || r == 0.0 {
+                break;
+            }
+            let theta = (z.z / r).acos() * power;
+            let phi = z.y.atan2(z.x) * power;
+            dr = r.powf(power - 1.0) * power * dr + 1.0;
+            z = r.powf(power)
+                * Point::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                )
+                + p;
+            r = z.norm();
+        }
+        if r == 0.0 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+
+    fn bounds(&self) -> Bounds {
+        match self {
+            Node::Sphere(radius) => Bounds::new(
+                Point::new(-radius, -radius, -radius),
+                Point::new(*radius, *radius, *radius),
+            ),
+            Node::Cuboid(half) => Bounds::new(-*half, *half),
+            Node::Torus(major, minor) => {
+                let w = major + minor;
+                Bounds::new(Point::new(-w, -w, -minor), Point::new(w, w, *minor))
+            }
+            Node::Mandelbulb(..) => Node::Sphere(MANDELBULB_BAILOUT).bounds(),
+            Node::Translated(offset, node) => {
+                let b = node.bounds();
+                Bounds::new(b.min + *offset, b.max + *offset)
+            }
+            Node::Union(a, b) => a.bounds().union(b.bounds()),
+            Node::SmoothUnion(a, b, k) => {
+                // Blending only ever adds up to a quarter of the smoothing distance
+                let b = a.bounds().union(b.bounds());
+                let grow = Point::new(0.25 * k, 0.25 * k, 0.25 * k);
+                Bounds::new(b.min - grow, b.max + grow)
+            }
+            Node::Repeated(..) => Bounds::infinite(),
+        }
+    }
+}
+
+/// A signed distance function, built from simple shapes
+///
+/// Distance functions are combined with the methods below, each returning a new function.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Sdf {
+    node: Arc<Node>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Sdf {
+    #[staticmethod]
+    #[name = "sphere"]
+    fn py_sphere(center: Point, radius: f32) -> Self {
+        Sdf::sphere(center, radius)
+    }
+
+    /// Returns an axis aligned box, given its center and the lengths of its sides
+    #[staticmethod]
+    #[name = "cuboid"]
+    fn py_cuboid(center: Point, size: Point) -> Self {
+        Sdf::cuboid(center, size)
+    }
+
+    /// Returns a torus lying in the plane spanned by the x and y axes
+    #[staticmethod]
+    #[name = "torus"]
+    fn py_torus(center: Point, major: f32, minor: f32) -> Self {
+        Sdf::torus(center, major, minor)
+    }
+
+    /// Returns the power 8 Mandelbulb for the defaults, which fits in a sphere of radius 1.2
+    #[staticmethod]
+    #[name = "mandelbulb"]
+    fn py_mandelbulb(center: Point, power: Option<f32>, iterations: Option<usize>) -> Self {
+        Sdf::mandelbulb(center, power, iterations)
+    }
+
+    #[name = "translated"]
+    fn py_translated(&self, offset: Point) -> Self {
+        self.translated(offset)
+    }
+
+    #[name = "union"]
+    fn py_union(&self, other: &Sdf) -> Self {
+        self.union(other)
+    }
+
+    /// Returns the union of two shapes, blended where they are closer than `k`, or not blended if
+    /// `k` is not positive
+    #[name = "smooth_union"]
+    fn py_smooth_union(&self, other: &Sdf, k: f32) -> Self {
+        self.smooth_union(other, k)
+    }
+
+    /// Returns the shape repeated infinitely, with a period of zero along axes not repeated
+    #[name = "repeated"]
+    fn py_repeated(&self, period: Point) -> Self {
+        self.repeated(period)
+    }
+
+    #[name = "distance"]
+    fn py_distance(&self, p: Point) -> f32 {
+        self.distance(p)
+    }
+}
+
+impl Sdf {
+    pub fn sphere(center: Point, radius: f32) -> Self {
+        Sdf::from(Node::Sphere(radius)).translated(center)
+    }
+
+    /// Returns an axis aligned box, given its center and the lengths of its sides
+    pub fn cuboid(center: Point, size: Point) -> Self {
+        Sdf::from(Node::Cuboid(0.5 * size)).translated(center)
+    }
+
+    /// Returns a torus lying in the plane spanned by the x and y axes
+    pub fn torus(center: Point, major: f32, minor: f32) -> Self {
+        Sdf::from(Node::Torus(major, minor)).translated(center)
+    }
+
+    /// Returns the power 8 Mandelbulb for the defaults, which fits in a sphere of radius 1.2
+    pub fn mandelbulb(center: Point, power: Option<f32>, iterations: Option<usize>) -> Self {
+        let node = Node::Mandelbulb(power.unwrap_or(8.0), iterations.unwrap_or(12));
+        Sdf::from(node).translated(center)
+    }
+
+    pub fn translated(&self, offset: Point) -> Self {
+        Sdf::from(Node::Translated(offset, self.node.clone()))
+    }
+
+    pub fn union(&self, other: &Sdf) -> Self {
+        Sdf::from(Node::Union(self.node.clone(), other.node.clone()))
+    }
+
+    /// Returns the union of two shapes, blended where they are closer than `k`, or not blended if
+    /// `k` is not positive
+    pub fn smooth_union(&self, other: &Sdf, k: f32) -> Self {
+        if k > 0.0 && k.is_finite() {
+            Sdf::from(Node::SmoothUnion(self.node.clone(), other.node.clone(), k))
+        } else {
+            self.union(other)
+        }
+    }
+
+    /// Returns the shape repeated infinitely, with a period of zero along axes not repeated
+    pub fn repeated(&self, period: Point) -> Self {
+        Sdf::from(Node::Repeated(period, self.node.clone()))
+    }
+
+    pub fn distance(&self, p: Point) -> f32 {
+        self.node.distance(p)
+    }
+}
+
+impl Sdf {
+    fn from(node: Node) -> Self {
+        Sdf {
+            node: Arc::new(node),
+        }
+    }
+
+    /// Estimates the direction in which the distance grows fastest, using central differences
+    fn gradient(&self, p: Point, h: f32) -> Point {
+        let d = |offset: Point| self.distance(p + offset) - self.distance(p - offset);
+        Point::new(d(h * UNIT_X), d(h * UNIT_Y), d(h * UNIT_Z))
+    }
+}
+
+/// A surface given by a signed distance function, found by sphere tracing
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Marched {
+    sdf: Sdf,
+    material: Material,
+    /// The distance below which a point counts as being on the surface
+    tolerance: f32,
+    /// The most steps taken along a ray before giving up
+    steps: usize,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Marched {
+    #[new]
+    fn py_new(sdf: Sdf, material: Material, tolerance: Option<f32>, steps: Option<usize>) -> Self {
+        Marched::new(sdf, material, tolerance, steps)
+    }
+}
+
+impl Marched {
+    pub fn new(sdf: Sdf, material: Material, tolerance: Option<f32>, steps: Option<usize>) -> Self {
+        Marched {
+            sdf,
+            material,
+            tolerance: tolerance.unwrap_or(1e-4),
+            steps: steps.unwrap_or(256),
+        }
+    }
+}
+
+impl Thing for Marched {
+    /// Steps along the ray by the distance to the surface, until it is closer than the tolerance
+    ///
+    /// Rays may start on either side of the surface.  A ray starting on the surface and moving
+    /// away from it, like one spawned there, does not hit it again right away.
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let bounds = self.sdf.node.bounds();
+        let (mut t, end) = bounds.entry(ray, MAX_DISTANCE)?;
+        let start = ray.at(t);
+        let side = self.sdf.distance(start).signum();
+        let mut leaving = side * self.sdf.distance(start) < self.tolerance
+            && side * (self.sdf.gradient(start, self.tolerance) * ray.direction) > 0.0;
+        for _ in 0..self.steps {
+            let p = ray.at(t);
+            let d = side * self.sdf.distance(p);
+            if d < self.tolerance {
+                if !leaving {
+                    let normal = self.sdf.gradient(p, self.tolerance).normalized();
+                    return Some(Hit::new(t, normal, self.material.clone()));
+                }
+            } else {
+                leaving = false;
+            }
+            t += d.max(self.tolerance);
+            if t > end {
+                break;
+            }
+        }
+        None
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.sdf.node.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).norm() < 1e-2
+    }
+
+    #[test]
+    fn distance_functions() {
+        let s = Sdf::sphere(UNIT_X, 1.0);
+        assert!((s.distance(ORIGIN) - 0.0).abs() < 1e-6);
+        assert!((s.distance(4.0 * UNIT_X) - 2.0).abs() < 1e-6);
+
+        let b = Sdf::cuboid(ORIGIN, Point::new(2.0, 2.0, 2.0));
+        assert!((b.distance(3.0 * UNIT_Y) - 2.0).abs() < 1e-6);
+        assert!((b.distance(ORIGIN) + 1.0).abs() < 1e-6);
+
+        let t = Sdf::torus(ORIGIN, 2.0, 0.5);
+        assert!((t.distance(ORIGIN) - 1.5).abs() < 1e-6);
+        assert!((t.distance(2.0 * UNIT_X) + 0.5).abs() < 1e-6);
+
+        let blend = Sdf::sphere(-UNIT_X, 0.8).smooth_union(&Sdf::sphere(UNIT_X, 0.8), 0.5);
+        assert!(blend.distance(ORIGIN) < Sdf::sphere(UNIT_X, 0.8).distance(ORIGIN));
+        let sharp = Sdf::sphere(-UNIT_X, 0.8).smooth_union(&Sdf::sphere(UNIT_X, 0.8), 0.0);
+        assert!((sharp.distance(ORIGIN) - 0.2).abs() < 1e-6);
+
+        let row = Sdf::sphere(ORIGIN, 0.5).repeated(Point::new(3.0, 0.0, 0.0));
+        assert!((row.distance(Point::new(30.0, 1.0, 0.0)) - 0.5).abs() < 1e-4);
+        assert!(
+            !Marched::new(row, Material::light(Color::white()), None, None)
+                .bounds()
+                .is_finite()
+        );
+
+        let bulb = Sdf::mandelbulb(ORIGIN, None, None);
+        assert!(bulb.distance(5.0 * UNIT_Z) > 1.0);
+        assert!(bulb.distance(ORIGIN) <= 0.0);
+        // Lower powers reach beyond the radius 1.2 of the default, but stay within the bailout
+        let quadratic = Sdf::mandelbulb(ORIGIN, Some(2.0), Some(4));
+        assert!(quadratic.distance(-1.6 * UNIT_Z) <= 0.0);
+        for &p in &[2.01 * UNIT_X, -2.01 * UNIT_Y, -2.01 * UNIT_Z] {
+            assert!(quadratic.distance(p) > 0.0);
+        }
+        assert!((quadratic.node.bounds().max.x - MANDELBULB_BAILOUT).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ray_marches_to_surface() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let sphere = Marched::new(Sdf::sphere(ORIGIN, 1.0), m.clone(), None, None);
+
+        let hit = sphere
+            .hit_by(&Ray::new(Point::new(-5.0, 0.0, 0.0), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-3);
+        assert!(close(hit.normal, -UNIT_X));
+        assert!(sphere
+            .hit_by(&Ray::new(Point::new(-5.0, 2.0, 0.0), UNIT_X))
+            .is_none());
+
+        let inside = sphere.hit_by(&Ray::new(ORIGIN, UNIT_Y)).unwrap();
+        assert!((inside.distance - 1.0).abs() < 1e-3);
+        assert!(close(inside.normal, UNIT_Y));
+
+        let leaving = Ray::new(UNIT_X, UNIT_X).spawn(UNIT_X, UNIT_X);
+        assert!(sphere.hit_by(&leaving).is_none());
+        let entering = Ray::new(UNIT_X, -UNIT_X).spawn(UNIT_X, -UNIT_X);
+        let hit = sphere.hit_by(&entering).unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-3);
+
+        let row =
+            Sdf::cuboid(ORIGIN, Point::new(1.0, 1.0, 1.0)).repeated(Point::new(0.0, 4.0, 0.0));
+        let boxes = Marched::new(row, m.clone(), None, None);
+        let hit = boxes
+            .hit_by(&Ray::new(Point::new(-5.0, 8.0, 0.0), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-3);
+        assert!(close(hit.normal, -UNIT_X));
+
+        let bulb = Marched::new(Sdf::mandelbulb(ORIGIN, None, None), m, None, None);
+        let hit = bulb
+            .hit_by(&Ray::new(Point::new(0.0, 0.0, -5.0), UNIT_Z))
+            .unwrap();
+        assert!(hit.distance > 5.0 - 1.2 && hit.distance < 5.0);
+    }
+}
@@ -0,0 +1,712 @@
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::roots;
+use crate::things::*;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::f32::consts::PI;
+
+/// How far the cosine between edges of a cuboid may be from zero
+static ORTHOGONALITY: f32 = 1e-4;
+
+/// An orthonormal frame to describe shapes in their own coordinates
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    origin: Point,
+    x: Point,
+    y: Point,
+    z: Point,
+}
+
+impl Frame {
+    /// Returns a frame with its z axis pointing along `axis`, named `what` in errors
+    fn along(origin: Point, axis: Point, what: &str) -> Result<Frame> {
+        let length = axis.norm();
+        if !(length > 0.0 && length.is_finite()) {
+            return Err(Error::Value(format!("{} must not be zero", what)));
+        }
+        let z = axis / length;
+        let x = z.perpendicular();
+        Ok(Frame {
+            origin,
+            x,
+            y: z.cross(x),
+            z,
+        })
+    }
+
+    /// Converts a direction into the coordinates of the frame
+    fn vector(&self, v: Point) -> Point {
+        Point::new(v * self.x, v * self.y, v * self.z)
+    }
+
+    /// Converts a position into the coordinates of the frame
+    fn point(&self, p: Point) -> Point {
+        self.vector(p - self.origin)
+    }
+
+    /// Converts a direction in frame coordinates back into world coordinates
+    fn world(&self, v: Point) -> Point {
+        v.x * self.x + v.y * self.y + v.z * self.z
+    }
+
+    fn ray(&self, ray: &Ray) -> Ray {
+        Ray {
+            base: self.point(ray.base),
+            direction: self.vector(ray.direction),
+            time: ray.time,
+        }
+    }
+
+    /// Returns world bounds around a box given in frame coordinates
+    fn bounds(&self, min: Point, max: Point) -> Bounds {
+        let corners: Vec<Point> = Bounds::new(min, max)
+            .corners()
+            .iter()
+            .map(|c| self.origin + self.world(*c))
+            .collect();
+        Bounds::around(&corners)
+    }
+}
+
+/// Checks that a size, such as a radius, is positive and finite
+fn positive(value: f32, what: &str) -> Result<()> {
+    if value > 0.0 && value.is_finite() {
+        Ok(())
+    } else {
+        Err(Error::Value(format!("{} must be positive", what)))
+    }
+}
+
+/// Returns the angle around the z axis, scaled to lie between zero and one
+fn azimuth(p: Point) -> f32 {
+    0.5 + p.y.atan2(p.x) / (2.0 * PI)
+}
+
+/// Returns the closest candidate in front of the ray base, if any
+///
+/// Candidates consist of a distance, the normal in frame coordinates, and surface coordinates.
+fn closest(candidates: Vec<(f32, Point, (f32, f32))>) -> Option<(f32, Point, (f32, f32))> {
+    candidates.into_iter().filter(|c| c.0 > 0.0).fold(
+        None,
+        |min: Option<(f32, Point, (f32, f32))>, c| match min {
+            Some(m) if m.0 <= c.0 => Some(m),
+            _ => Some(c),
+        },
+    )
+}
+
+/// An infinite plane
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Plane {
+    frame: Frame,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Plane {
+    #[new]
+    fn py_new(point: Point, normal: Point, material: Material) -> PyResult<Self> {
+        Ok(Plane::new(point, normal, material)?)
+    }
+}
+
+impl Plane {
+    pub fn new(point: Point, normal: Point, material: Material) -> Result<Self> {
+        Ok(Plane {
+            frame: Frame::along(point, normal, "normal")?,
+            material,
+        })
+    }
+}
+
+impl Thing for Plane {
+    /// Returns a hit with the position within the plane as surface coordinates
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let local = self.frame.ray(ray);
+        if local.direction.z.abs() < 1e-6 {
+            return None;
+        }
+        let t = -local.base.z / local.direction.z;
+        if t <= 0.0 {
+            return None;
+        }
+        let p = local.at(t);
+        Some(Hit::new(t, self.frame.z, self.material.clone()).with_uv(p.x, p.y))
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::infinite()
+    }
+}
+
+/// A flat, round disk
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Disk {
+    frame: Frame,
+    radius: f32,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Disk {
+    #[new]
+    fn py_new(center: Point, normal: Point, radius: f32, material: Material) -> PyResult<Self> {
+        Ok(Disk::new(center, normal, radius, material)?)
+    }
+}
+
+impl Disk {
+    pub fn new(center: Point, normal: Point, radius: f32, material: Material) -> Result<Self> {
+        positive(radius, "radius")?;
+        Ok(Disk {
+            frame: Frame::along(center, normal, "normal")?,
+            radius,
+            material,
+        })
+    }
+}
+
+impl Thing for Disk {
+    /// Returns a hit with the angle and the fractional radius as surface coordinates
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let local = self.frame.ray(ray);
+        if local.direction.z.abs() < 1e-6 {
+            return None;
+        }
+        let t = -local.base.z / local.direction.z;
+        let p = local.at(t);
+        let r = (p.x * p.x + p.y * p.y).sqrt();
+        if t <= 0.0 || r > self.radius {
+            return None;
+        }
+        let hit = Hit::new(t, self.frame.z, self.material.clone());
+        Some(hit.with_uv(azimuth(p), r / self.radius))
+    }
+
+    fn bounds(&self) -> Bounds {
+        let r = self.radius;
+        self.frame
+            .bounds(Point::new(-r, -r, 0.0), Point::new(r, r, 0.0))
+    }
+}
+
+/// Returns the hits of a ray, given in frame coordinates, with the caps of a cylinder or cone
+///
+/// # Arguments
+///
+/// * `ray` - the ray in frame coordinates
+/// * `caps` - the height and radius of each cap, and the direction of its normal along z
+fn cap_hits(ray: &Ray, caps: &[(f32, f32, f32)]) -> Vec<(f32, Point, (f32, f32))> {
+    if ray.direction.z == 0.0 {
+        return vec![];
+    }
+    caps.iter()
+        .filter_map(|&(z, radius, side)| {
+            let t = (z - ray.base.z) / ray.direction.z;
+            let p = ray.at(t);
+            let r = (p.x * p.x + p.y * p.y).sqrt();
+            if r <= radius {
+                Some((t, side * UNIT_Z, (azimuth(p), r / radius)))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// A cylinder closed at both ends
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Cylinder {
+    frame: Frame,
+    height: f32,
+    radius: f32,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Cylinder {
+    #[new]
+    fn py_new(base: Point, axis: Point, radius: f32, material: Material) -> PyResult<Self> {
+        Ok(Cylinder::new(base, axis, radius, material)?)
+    }
+}
+
+impl Cylinder {
+    /// Creates a cylinder from the center of its bottom along `axis` to the center of its top
+    pub fn new(base: Point, axis: Point, radius: f32, material: Material) -> Result<Self> {
+        positive(radius, "radius")?;
+        Ok(Cylinder {
+            frame: Frame::along(base, axis, "axis")?,
+            height: axis.norm(),
+            radius,
+            material,
+        })
+    }
+}
+
+impl Thing for Cylinder {
+    /// Returns a hit with the angle and the fractional height (or radius on the caps) as
+    /// surface coordinates
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let local = self.frame.ray(ray);
+        let (o, d) = (local.base, local.direction);
+        let mut candidates = cap_hits(
+            &local,
+            &[(0.0, self.radius, -1.0), (self.height, self.radius, 1.0)],
+        );
+        let a = d.x * d.x + d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.y * d.y);
+        let c = o.x * o.x + o.y * o.y - self.radius * self.radius;
+        for t in roots::quadratic(a as f64, b as f64, c as f64) {
+            let p = local.at(t as f32);
+            if (0.0..=self.height).contains(&p.z) {
+                let n = Point::new(p.x, p.y, 0.0) / self.radius;
+                candidates.push((t as f32, n, (azimuth(p), p.z / self.height)));
+            }
+        }
+        let (t, n, (u, v)) = closest(candidates)?;
+        Some(Hit::new(t, self.frame.world(n).normalized(), self.material.clone()).with_uv(u, v))
+    }
+
+    fn bounds(&self) -> Bounds {
+        let r = self.radius;
+        self.frame
+            .bounds(Point::new(-r, -r, 0.0), Point::new(r, r, self.height))
+    }
+}
+
+/// A cone closed at its base
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Cone {
+    frame: Frame,
+    height: f32,
+    radius: f32,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Cone {
+    #[new]
+    fn py_new(base: Point, axis: Point, radius: f32, material: Material) -> PyResult<Self> {
+        Ok(Cone::new(base, axis, radius, material)?)
+    }
+}
+
+impl Cone {
+    /// Creates a cone from the center of its base along `axis` to its apex
+    pub fn new(base: Point, axis: Point, radius: f32, material: Material) -> Result<Self> {
+        positive(radius, "radius")?;
+        Ok(Cone {
+            frame: Frame::along(base, axis, "axis")?,
+            height: axis.norm(),
+            radius,
+            material,
+        })
+    }
+}
+
+impl Thing for Cone {
+    /// Returns a hit with the angle and the fractional height (or radius on the base) as
+    /// surface coordinates
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let local = self.frame.ray(ray);
+        let (o, d) = (local.base, local.direction);
+        let mut candidates = cap_hits(&local, &[(0.0, self.radius, -1.0)]);
+        let k2 = (self.radius / self.height).powi(2);
+        let above = self.height - o.z;
+        let a = d.x * d.x + d.y * d.y - k2 * d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.y * d.y + k2 * above * d.z);
+        let c = o.x * o.x + o.y * o.y - k2 * above * above;
+        for t in roots::quadratic(a as f64, b as f64, c as f64) {
+            let p = local.at(t as f32);
+            if (0.0..=self.height).contains(&p.z) {
+                let n = Point::new(p.x, p.y, k2 * (self.height - p.z));
+                candidates.push((t as f32, n, (azimuth(p), p.z / self.height)));
+            }
+        }
+        let (t, n, (u, v)) = closest(candidates)?;
+        Some(Hit::new(t, self.frame.world(n).normalized(), self.material.clone()).with_uv(u, v))
+    }
+
+    fn bounds(&self) -> Bounds {
+        let r = self.radius;
+        self.frame
+            .bounds(Point::new(-r, -r, 0.0), Point::new(r, r, self.height))
+    }
+}
+
+/// A ring with a round cross section
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Torus {
+    frame: Frame,
+    /// The distance of the center of the tube from the center of the torus
+    major: f32,
+    /// The radius of the tube
+    minor: f32,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Torus {
+    #[new]
+    fn py_new(
+        center: Point,
+        axis: Point,
+        major: f32,
+        minor: f32,
+        material: Material,
+    ) -> PyResult<Self> {
+        Ok(Torus::new(center, axis, major, minor, material)?)
+    }
+}
+
+impl Torus {
+    /// Creates a ring torus, whose tube of radius `minor` is at most as thick as `major`
+    pub fn new(
+        center: Point,
+        axis: Point,
+        major: f32,
+        minor: f32,
+        material: Material,
+    ) -> Result<Self> {
+        positive(major, "major radius")?;
+        positive(minor, "minor radius")?;
+        if minor > major {
+            return Err(Error::Value(
+                "minor radius must not exceed the major radius".into(),
+            ));
+        }
+        Ok(Torus {
+            frame: Frame::along(center, axis, "axis")?,
+            major,
+            minor,
+            material,
+        })
+    }
+}
+
+impl Torus {
+    fn local_bounds(&self) -> Bounds {
+        let w = self.major + self.minor;
+        Bounds::new(
+            Point::new(-w, -w, -self.minor),
+            Point::new(w, w, self.minor),
+        )
+    }
+}
+
+impl Thing for Torus {
+    /// Determines where a ray hits the torus by solving a quartic equation
+    ///
+    /// The ray is advanced to the bounds of the torus first, to keep the coefficients of the
+    /// quartic small.  Returns a hit with the angles around the axis and around the tube as
+    /// surface coordinates.
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let local = self.frame.ray(ray);
+        let (start, _) = self.local_bounds().entry(&local, f32::INFINITY)?;
+        let o = local.at(start);
+        let d = local.direction;
+        let (ox, oy, oz) = (o.x as f64, o.y as f64, o.z as f64);
+        let (dx, dy, dz) = (d.x as f64, d.y as f64, d.z as f64);
+        let r2 = (self.major as f64).powi(2);
+        let q = ox * dx + oy * dy + oz * dz;
+        let w = ox * ox + oy * oy + oz * oz + r2 - (self.minor as f64).powi(2);
+        let t = roots::quartic(
+            4.0 * q,
+            4.0 * q * q + 2.0 * w - 4.0 * r2 * (dx * dx + dy * dy),
+            4.0 * q * w - 8.0 * r2 * (ox * dx + oy * dy),
+            w * w - 4.0 * r2 * (ox * ox + oy * oy),
+        )
+        .into_iter()
+        .map(|t| start + t as f32)
+        .find(|t| *t > 0.0)?;
+
+        let p = local.at(t);
+        let ring = (p.x * p.x + p.y * p.y).sqrt();
+        let center = Point::new(p.x, p.y, 0.0) * (self.major / ring);
+        let n = (p - center).normalized();
+        let v = 0.5 + p.z.atan2(ring - self.major) / (2.0 * PI);
+        Some(Hit::new(t, self.frame.world(n), self.material.clone()).with_uv(azimuth(p), v))
+    }
+
+    fn bounds(&self) -> Bounds {
+        let b = self.local_bounds();
+        self.frame.bounds(b.min, b.max)
+    }
+}
+
+/// A box with rectangular faces, possibly rotated
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Cuboid {
+    frame: Frame,
+    size: Point,
+    material: Material,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Cuboid {
+    #[new]
+    fn py_new(corner: Point, x: Point, y: Point, z: Point, material: Material) -> PyResult<Self> {
+        Ok(Cuboid::new(corner, x, y, z, material)?)
+    }
+
+    /// Creates a box aligned with the coordinate axes
+    #[staticmethod]
+    #[name = "aligned"]
+    fn py_aligned(min: Point, max: Point, material: Material) -> PyResult<Self> {
+        Ok(Cuboid::aligned(min, max, material)?)
+    }
+}
+
+impl Cuboid {
+    /// Creates a box from one corner and three perpendicular edges starting from it
+    pub fn new(corner: Point, x: Point, y: Point, z: Point, material: Material) -> Result<Self> {
+        let size = Point::new(x.norm(), y.norm(), z.norm());
+        if !(size.x > 0.0 && size.y > 0.0 && size.z > 0.0) {
+            return Err(Error::Value("edges of a cuboid must not be empty".into()));
+        }
+        let (x, y, z) = (x.normalized(), y.normalized(), z.normalized());
+        if [x * y, y * z, z * x]
+            .iter()
+            .any(|c| c.abs() > ORTHOGONALITY)
+        {
+            return Err(Error::Value(
+                "edges of a cuboid must be perpendicular".into(),
+            ));
+        }
+        Ok(Cuboid {
+            frame: Frame {
+                origin: corner,
+                x,
+                y,
+                z,
+            },
+            size,
+            material,
+        })
+    }
+
+    /// Creates a box aligned with the coordinate axes
+    pub fn aligned(min: Point, max: Point, material: Material) -> Result<Self> {
+        let size = max - min;
+        Cuboid::new(
+            min,
+            size.x * UNIT_X,
+            size.y * UNIT_Y,
+            size.z * UNIT_Z,
+            material,
+        )
+    }
+}
+
+impl Thing for Cuboid {
+    /// Returns a hit with the fractional position on the face hit as surface coordinates
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let local = self.frame.ray(ray);
+        let (near, far) = Bounds::new(ORIGIN, self.size).entry(&local, f32::INFINITY)?;
+        let t = if near > 0.0 { near } else { far };
+        if t <= 0.0 {
+            return None;
+        }
+        let p = local.at(t);
+        let distance = |axis: usize| {
+            let d = p[axis].min(self.size[axis] - p[axis]).abs();
+            d / self.size[axis]
+        };
+        let axis = (0..3)
+            .min_by(|a, b| distance(*a).partial_cmp(&distance(*b)).unwrap())
+            .unwrap_or(0);
+        let side = if p[axis] < 0.5 * self.size[axis] {
+            -1.0
+        } else {
+            1.0
+        };
+        let (n, u, v) = match axis {
+            0 => (side * UNIT_X, p.y / self.size.y, p.z / self.size.z),
+            1 => (side * UNIT_Y, p.x / self.size.x, p.z / self.size.z),
+            _ => (side * UNIT_Z, p.x / self.size.x, p.y / self.size.y),
+        };
+        Some(Hit::new(t, self.frame.world(n), self.material.clone()).with_uv(u, v))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.frame.bounds(ORIGIN, self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).norm() < 1e-4
+    }
+
+    fn distance(thing: &dyn Thing, ray: &Ray) -> Option<f32> {
+        thing.hit_by(ray).map(|h| h.distance)
+    }
+
+    #[test]
+    fn ray_hits_plane_and_disk() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let r = Ray::new(Point::new(3.0, 0.0, 0.0), -UNIT_X);
+
+        let p = Plane::new(ORIGIN, UNIT_X, m.clone()).unwrap();
+        let hit = p.hit_by(&r).unwrap();
+        assert_eq!(hit.distance, 3.0);
+        assert_eq!(hit.normal, UNIT_X);
+        assert_eq!(distance(&p, &Ray::new(ORIGIN, UNIT_Y)), None);
+        assert!(!p.bounds().is_finite());
+
+        let d = Disk::new(Point::new(0.0, 0.5, 0.0), UNIT_X, 1.0, m.clone()).unwrap();
+        let hit = d.hit_by(&r).unwrap();
+        assert_eq!(hit.distance, 3.0);
+        assert!((hit.uv.1 - 0.5).abs() < 1e-6);
+        let d = Disk::new(Point::new(0.0, 1.5, 0.0), UNIT_X, 1.0, m).unwrap();
+        assert_eq!(distance(&d, &r), None);
+    }
+
+    #[test]
+    fn ray_hits_cylinder_and_cone() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let c = Cylinder::new(ORIGIN, 2.0 * UNIT_Z, 1.0, m.clone()).unwrap();
+
+        let hit = c
+            .hit_by(&Ray::new(Point::new(-3.0, 0.0, 1.0), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-5);
+        assert!(close(hit.normal, -UNIT_X));
+        assert!((hit.uv.1 - 0.5).abs() < 1e-5);
+
+        let hit = c
+            .hit_by(&Ray::new(Point::new(0.5, 0.0, 5.0), -UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 3.0).abs() < 1e-5);
+        assert!(close(hit.normal, UNIT_Z));
+
+        let inside = c
+            .hit_by(&Ray::new(Point::new(0.0, 0.0, 1.0), UNIT_Y))
+            .unwrap();
+        assert!((inside.distance - 1.0).abs() < 1e-5);
+        assert!(close(inside.normal, UNIT_Y));
+        assert_eq!(
+            distance(&c, &Ray::new(Point::new(-3.0, 0.0, 3.0), UNIT_X)),
+            None
+        );
+
+        let k = Cone::new(ORIGIN, 2.0 * UNIT_Z, 1.0, m).unwrap();
+        let hit = k
+            .hit_by(&Ray::new(Point::new(-3.0, 0.0, 1.0), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+        assert!(close(hit.normal, Point::new(-2.0, 0.0, 1.0).normalized()));
+        let hit = k
+            .hit_by(&Ray::new(Point::new(0.0, 0.0, -1.0), UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-5);
+        assert!(close(hit.normal, -UNIT_Z));
+        let d = distance(&k, &Ray::new(Point::new(-3.0, 0.0, 1.9), UNIT_X)).unwrap();
+        assert!((d - 2.95).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ray_hits_torus() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let t = Torus::new(ORIGIN, UNIT_Z, 2.0, 0.5, m.clone()).unwrap();
+
+        assert_eq!(
+            distance(&t, &Ray::new(Point::new(0.0, 0.0, 5.0), -UNIT_Z)),
+            None
+        );
+
+        let hit = t
+            .hit_by(&Ray::new(Point::new(2.0, 0.0, 5.0), -UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-4);
+        assert!(close(hit.normal, UNIT_Z));
+
+        let hit = t
+            .hit_by(&Ray::new(Point::new(-10.0, 0.0, 0.0), UNIT_X))
+            .unwrap();
+        assert!((hit.distance - 7.5).abs() < 1e-4);
+        assert!(close(hit.normal, -UNIT_X));
+
+        let tilted = Torus::new(ORIGIN, UNIT_X, 2.0, 0.5, m).unwrap();
+        let hit = tilted
+            .hit_by(&Ray::new(Point::new(0.0, 0.0, -10.0), UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 7.5).abs() < 1e-4);
+        assert!(close(hit.normal, -UNIT_Z));
+    }
+
+    #[test]
+    fn degenerate_shapes_are_rejected() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        assert!(Plane::new(ORIGIN, ORIGIN, m.clone()).is_err());
+        assert!(Disk::new(ORIGIN, ORIGIN, 1.0, m.clone()).is_err());
+        assert!(Disk::new(ORIGIN, UNIT_Z, -1.0, m.clone()).is_err());
+        assert!(Cylinder::new(ORIGIN, ORIGIN, 1.0, m.clone()).is_err());
+        assert!(Cylinder::new(ORIGIN, UNIT_Z, 0.0, m.clone()).is_err());
+        assert!(Cone::new(ORIGIN, ORIGIN, 1.0, m.clone()).is_err());
+        assert!(Cone::new(ORIGIN, UNIT_Z, f32::NAN, m.clone()).is_err());
+        assert!(Torus::new(ORIGIN, ORIGIN, 2.0, 0.5, m.clone()).is_err());
+        assert!(Torus::new(ORIGIN, UNIT_Z, 0.5, 2.0, m.clone()).is_err());
+        assert!(Torus::new(ORIGIN, UNIT_Z, 2.0, -0.5, m).is_err());
+    }
+
+    #[test]
+    fn ray_hits_cuboid() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let b = Cuboid::aligned(
+            Point::new(1.0, -1.0, -1.0),
+            Point::new(2.0, 1.0, 3.0),
+            m.clone(),
+        )
+        .unwrap();
+
+        let hit = b.hit_by(&Ray::new(ORIGIN, UNIT_X)).unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-6);
+        assert!(close(hit.normal, -UNIT_X));
+        assert!((hit.uv.0 - 0.5).abs() < 1e-6 && (hit.uv.1 - 0.25).abs() < 1e-6);
+
+        let hit = b
+            .hit_by(&Ray::new(Point::new(1.5, 0.0, 0.0), UNIT_Z))
+            .unwrap();
+        assert!((hit.distance - 3.0).abs() < 1e-6);
+        assert!(close(hit.normal, UNIT_Z));
+        assert_eq!(distance(&b, &Ray::new(ORIGIN, -UNIT_X)), None);
+
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        let rotated = Cuboid::new(
+            Point::new(2.0, 0.0, -0.5),
+            Point::new(s, s, 0.0),
+            Point::new(-s, s, 0.0),
+            UNIT_Z,
+            m.clone(),
+        )
+        .unwrap();
+        let hit = rotated.hit_by(&Ray::new(ORIGIN, UNIT_X)).unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-6);
+        assert!(close(hit.normal, Point::new(-s, -s, 0.0)));
+        let bounds = rotated.bounds();
+        assert!(close(bounds.min, Point::new(2.0 - s, 0.0, -0.5)));
+        assert!(close(bounds.max, Point::new(2.0 + s, 2.0 * s, 0.5)));
+
+        assert!(Cuboid::aligned(ORIGIN, ORIGIN, m.clone()).is_err());
+        assert!(Cuboid::aligned(ORIGIN, Point::new(1.0, 0.0, 1.0), m.clone()).is_err());
+        assert!(Cuboid::new(ORIGIN, UNIT_X, Point::new(1.0, 1.0, 0.0), UNIT_Z, m).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Reading of STL files, in ASCII as well as in binary
+
+use std::error::Error;
+use std::fs;
+
+/// The triangles of an STL file, given by the coordinates of their corners
+///
+/// The normals stored in the file are ignored, as the order of the corners already tells the
+/// outside.
+#[derive(Debug)]
+pub struct Stl {
+    pub triangles: Vec<[[f32; 3]; 3]>,
+}
+
+impl Stl {
+    pub fn open(filename: &str) -> Result<Stl, Box<dyn Error>> {
+        Stl::parse(&fs::read(filename)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Stl, Box<dyn Error>> {
+        // Binary files may start with "solid" as well, but their size is known from the header
+        if bytes.len() >= 84 {
+            let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+            if bytes.len() == 84 + 50 * count {
+                return Ok(Stl::binary(&bytes[84..]));
+            }
+        }
+        if bytes.starts_with(b"solid") {
+            Stl::ascii(std::str::from_utf8(bytes)?)
+        } else {
+            Err("not an STL file".into())
+        }
+    }
+
+    /// Reads records of a normal, three corners and two bytes of attributes
+    fn binary(records: &[u8]) -> Stl {
+        let float = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let triangles = records
+            .chunks_exact(50)
+            .map(|record| {
+                let mut corners = [[0.0; 3]; 3];
+                for (i, corner) in corners.iter_mut().enumerate() {
+                    for (j, x) in corner.iter_mut().enumerate() {
+                        *x = float(&record[12 + 12 * i + 4 * j..]);
+                    }
+                }
+                corners
+            })
+            .collect();
+        Stl { triangles }
+    }
+
+    fn ascii(text: &str) -> Result<Stl, Box<dyn Error>> {
+        let mut words = text.split_whitespace();
+        let mut corners = Vec::new();
+        while let Some(word) = words.next() {
+            if word == "vertex" {
+                let mut corner = [0.0; 3];
+                for x in corner.iter_mut() {
+                    *x = words.next().ok_or("unexpected end of data")?.parse()?;
+                }
+                corners.push(corner);
+            }
+        }
+        if corners.len() % 3 != 0 {
+            return Err("facets need three vertices".into());
+        }
+        let triangles = corners
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+        Ok(Stl { triangles })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stl_parses() {
+        let ascii = "solid square\n\
+                     facet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 1 1 0\n\
+                     endloop\nendfacet\n\
+                     facet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 1 0\nvertex 0 1 0\n\
+                     endloop\nendfacet\nendsolid square\n";
+        let stl = Stl::parse(ascii.as_bytes()).unwrap();
+        assert_eq!(stl.triangles.len(), 2);
+        assert_eq!(stl.triangles[1][1], [1.0, 1.0, 0.0]);
+
+        // A binary header may start like an ASCII file
+        let mut binary = b"solid but binary".to_vec();
+        binary.resize(80, 0);
+        binary.extend_from_slice(&1u32.to_le_bytes());
+        for x in &[
+            0.0f32, 0.0, 1.0, 0.5, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 3.0,
+        ] {
+            binary.extend_from_slice(&x.to_le_bytes());
+        }
+        binary.extend_from_slice(&[0, 0]);
+        let stl = Stl::parse(&binary).unwrap();
+        assert_eq!(
+            stl.triangles,
+            vec![[[0.5, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 3.0]]]
+        );
+
+        assert!(Stl::parse(b"ply\n").is_err());
+        assert!(Stl::parse(b"solid\nvertex 0 0 0\nvertex 1 0 0\n").is_err());
+    }
+}
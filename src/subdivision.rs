@@ -0,0 +1,475 @@
+//! Catmull-Clark and Loop subdivision of polygon meshes
+//!
+//! After refining the control mesh a number of times, vertices are moved onto the limit surface
+//! and given its normals, so that even a few levels shade smoothly.  Boundaries are treated as
+//! cubic B-splines; vertices with more than two boundary edges or with a single face are kept
+//! as corners.
+
+use crate::things::*;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    /// Refines polygons into quadrilaterals, approximating bicubic B-splines
+    CatmullClark,
+    /// Refines triangles into four triangles each, approximating quartic box splines
+    Loop,
+}
+
+impl Scheme {
+    pub fn parse(name: &str) -> Option<Scheme> {
+        match name {
+            "catmull-clark" => Some(Scheme::CatmullClark),
+            "loop" => Some(Scheme::Loop),
+            _ => None,
+        }
+    }
+}
+
+/// A polygon mesh with optional surface coordinates at its vertices
+#[derive(Clone, Debug)]
+pub struct Polygons {
+    pub vertices: Vec<Point>,
+    /// Indices of vertices, counter-clockwise seen from outside
+    pub faces: Vec<Vec<usize>>,
+    pub uvs: Option<Vec<(f32, f32)>>,
+}
+
+fn key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+fn average(points: impl Iterator<Item = Point>) -> Point {
+    let (sum, count) = points.fold((ORIGIN, 0), |(sum, count), p| (sum + p, count + 1));
+    (1.0 / count as f32) * sum
+}
+
+fn midpoint(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    (0.5 * (a.0 + b.0), 0.5 * (a.1 + b.1))
+}
+
+/// How faces and vertices of a mesh are connected
+struct Topology {
+    /// The faces around each edge
+    edges: HashMap<(usize, usize), Vec<usize>>,
+    /// The faces around each vertex, with the position of the vertex in the face
+    corners: Vec<Vec<(usize, usize)>>,
+    /// The vertices joined to each vertex by an edge
+    neighbors: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    fn new(mesh: &Polygons) -> Topology {
+        let mut edges: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        let mut corners = vec![Vec::new(); mesh.vertices.len()];
+        let mut neighbors = vec![Vec::new(); mesh.vertices.len()];
+        for (f, face) in mesh.faces.iter().enumerate() {
+            for (k, &a) in face.iter().enumerate() {
+                let b = face[(k + 1) % face.len()];
+                corners[a].push((f, k));
+                let faces = edges.entry(key(a, b)).or_default();
+                if faces.is_empty() {
+                    neighbors[a].push(b);
+                    neighbors[b].push(a);
+                }
+                faces.push(f);
+            }
+        }
+        Topology {
+            edges,
+            corners,
+            neighbors,
+        }
+    }
+
+    fn is_boundary(&self, a: usize, b: usize) -> bool {
+        self.edges[&key(a, b)].len() == 1
+    }
+
+    /// Returns the neighbors of a vertex along the boundary, if any
+    fn boundary(&self, v: usize) -> Vec<usize> {
+        self.neighbors[v]
+            .iter()
+            .cloned()
+            .filter(|&n| self.is_boundary(v, n))
+            .collect()
+    }
+
+    /// Returns the faces around an interior vertex in counter-clockwise order, as pairs of face
+    /// and position of the vertex in it
+    fn ring(&self, mesh: &Polygons, v: usize) -> Option<Vec<(usize, usize)>> {
+        let corners = &self.corners[v];
+        let next = |&(f, k): &(usize, usize)| {
+            let face = &mesh.faces[f];
+            face[(k + 1) % face.len()]
+        };
+        let previous = |&(f, k): &(usize, usize)| {
+            let face = &mesh.faces[f];
+            face[(k + face.len() - 1) % face.len()]
+        };
+        let mut ring = vec![*corners.first()?];
+        while ring.len() < corners.len() {
+            let last = previous(ring.last().unwrap());
+            ring.push(*corners.iter().find(|c| next(c) == last)?);
+        }
+        if previous(ring.last().unwrap()) == next(&ring[0]) {
+            Some(ring)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the neighbors of a vertex along a smooth boundary, where it joins two boundary
+    /// edges and more than one face
+    fn smooth_boundary(&self, v: usize) -> Option<(usize, usize)> {
+        match self.boundary(v).as_slice() {
+            &[a, b] if self.corners[v].len() > 1 => Some((a, b)),
+            _ => None,
+        }
+    }
+
+    /// Returns the new position of a vertex on the boundary, or None for interior vertices
+    fn boundary_rule(&self, mesh: &Polygons, v: usize) -> Option<Point> {
+        if self.boundary(v).is_empty() {
+            return None;
+        }
+        match self.smooth_boundary(v) {
+            Some((a, b)) => {
+                Some(0.75 * mesh.vertices[v] + 0.125 * (mesh.vertices[a] + mesh.vertices[b]))
+            }
+            None => Some(mesh.vertices[v]),
+        }
+    }
+}
+
+/// Returns the mesh with every face split into triangles
+pub fn triangulate(mesh: &Polygons) -> Polygons {
+    let faces = mesh
+        .faces
+        .iter()
+        .flat_map(|f| (1..f.len() - 1).map(move |i| vec![f[0], f[i], f[i + 1]]))
+        .collect();
+    Polygons {
+        faces,
+        ..mesh.clone()
+    }
+}
+
+/// Splits every face with n corners into n quadrilaterals
+fn catmull_clark(mesh: &Polygons) -> Polygons {
+    let topology = Topology::new(mesh);
+    let p = &mesh.vertices;
+    let centers: Vec<Point> = mesh
+        .faces
+        .iter()
+        .map(|f| average(f.iter().map(|&i| p[i])))
+        .collect();
+
+    let mut vertices: Vec<Point> = (0..p.len())
+        .map(|v| {
+            topology.boundary_rule(mesh, v).unwrap_or_else(|| {
+                let n = topology.neighbors[v].len() as f32;
+                let q = average(topology.corners[v].iter().map(|&(f, _)| centers[f]));
+                let r = average(topology.neighbors[v].iter().map(|&w| 0.5 * (p[v] + p[w])));
+                (1.0 / n) * (q + 2.0 * r + (n - 3.0) * p[v])
+            })
+        })
+        .collect();
+    let first_center = vertices.len();
+    vertices.extend(centers.iter().cloned());
+
+    let mut uvs = mesh.uvs.clone();
+    if let Some(uvs) = uvs.as_mut() {
+        for f in &mesh.faces {
+            let n = f.len() as f32;
+            let (u, v) = f
+                .iter()
+                .fold((0.0, 0.0), |(u, v), &i| (u + uvs[i].0, v + uvs[i].1));
+            uvs.push((u / n, v / n));
+        }
+    }
+
+    let mut edges = HashMap::new();
+    let mut faces = Vec::new();
+    for (f, face) in mesh.faces.iter().enumerate() {
+        let mut split = |a: usize, b: usize| {
+            *edges.entry(key(a, b)).or_insert_with(|| {
+                let around = &topology.edges[&key(a, b)];
+                vertices.push(if around.len() == 2 {
+                    0.25 * (p[a] + p[b] + centers[around[0]] + centers[around[1]])
+                } else {
+                    0.5 * (p[a] + p[b])
+                });
+                if let Some(uvs) = uvs.as_mut() {
+                    uvs.push(midpoint(uvs[a], uvs[b]));
+                }
+                vertices.len() - 1
+            })
+        };
+        let n = face.len();
+        for k in 0..n {
+            let (previous, corner, next) = (face[(k + n - 1) % n], face[k], face[(k + 1) % n]);
+            let after = split(corner, next);
+            let before = split(previous, corner);
+            faces.push(vec![corner, after, first_center + f, before]);
+        }
+    }
+    Polygons {
+        vertices,
+        faces,
+        uvs,
+    }
+}
+
+/// Returns the weight of each neighbor of an interior vertex in Loop subdivision
+fn loop_weight(valence: usize) -> f32 {
+    if valence == 3 {
+        3.0 / 16.0
+    } else {
+        3.0 / (8.0 * valence as f32)
+    }
+}
+
+/// Splits every triangle into four
+fn loop_subdivision(mesh: &Polygons) -> Polygons {
+    let topology = Topology::new(mesh);
+    let p = &mesh.vertices;
+    let mut vertices: Vec<Point> = (0..p.len())
+        .map(|v| {
+            topology.boundary_rule(mesh, v).unwrap_or_else(|| {
+                let n = topology.neighbors[v].len();
+                let beta = loop_weight(n);
+                let sum = topology.neighbors[v].iter().fold(ORIGIN, |s, &w| s + p[w]);
+                (1.0 - n as f32 * beta) * p[v] + beta * sum
+            })
+        })
+        .collect();
+    let mut uvs = mesh.uvs.clone();
+
+    let opposite = |f: usize, a: usize, b: usize| {
+        let face = &mesh.faces[f];
+        p[face.iter().cloned().find(|&c| c != a && c != b).unwrap()]
+    };
+    let mut edges = HashMap::new();
+    let mut faces = Vec::new();
+    for face in &mesh.faces {
+        let mut split = |a: usize, b: usize| {
+            *edges.entry(key(a, b)).or_insert_with(|| {
+                let around = &topology.edges[&key(a, b)];
+                vertices.push(if around.len() == 2 {
+                    0.375 * (p[a] + p[b])
+                        + 0.125 * (opposite(around[0], a, b) + opposite(around[1], a, b))
+                } else {
+                    0.5 * (p[a] + p[b])
+                });
+                if let Some(uvs) = uvs.as_mut() {
+                    uvs.push(midpoint(uvs[a], uvs[b]));
+                }
+                vertices.len() - 1
+            })
+        };
+        let (a, b, c) = (face[0], face[1], face[2]);
+        let (ab, bc, ca) = (split(a, b), split(b, c), split(c, a));
+        faces.push(vec![a, ab, ca]);
+        faces.push(vec![ab, b, bc]);
+        faces.push(vec![ca, bc, c]);
+        faces.push(vec![ab, bc, ca]);
+    }
+    Polygons {
+        vertices,
+        faces,
+        uvs,
+    }
+}
+
+/// Returns normals at the vertices, averaging the normals of the faces around them by area
+pub fn vertex_normals(vertices: &[Point], faces: &[Vec<usize>]) -> Vec<Point> {
+    let mut normals = vec![ORIGIN; vertices.len()];
+    for f in faces {
+        for i in 1..f.len() - 1 {
+            let (a, b, c) = (f[0], f[i], f[i + 1]);
+            let n = (vertices[b] - vertices[a]).cross(vertices[c] - vertices[a]);
+            for &v in &[a, b, c] {
+                normals[v] += n;
+            }
+        }
+    }
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.norm() > 0.0 {
+                n.normalized()
+            } else {
+                UNIT_Z
+            }
+        })
+        .collect()
+}
+
+/// Moves the vertices of a refined mesh onto the limit surface, returning its normals there
+///
+/// Interior vertices use the exact limit positions and tangents of their scheme, which for
+/// Catmull-Clark assumes that all faces are quadrilaterals.  Normals at the boundary are
+/// averaged from the faces around.
+fn limit(mesh: &mut Polygons, scheme: Scheme) -> Vec<Point> {
+    let topology = Topology::new(mesh);
+    let p = &mesh.vertices;
+    let mut positions = p.clone();
+    let mut tangents = vec![None; p.len()];
+    for v in 0..p.len() {
+        if let Some((a, b)) = topology.smooth_boundary(v) {
+            positions[v] = (1.0 / 6.0) * (p[a] + 4.0 * p[v] + p[b]);
+        }
+        if !topology.boundary(v).is_empty() {
+            continue;
+        }
+        let ring = match topology.ring(mesh, v) {
+            Some(ring) => ring,
+            None => continue,
+        };
+        let n = ring.len();
+        let corner = |(f, k): (usize, usize), offset: usize| {
+            let face = &mesh.faces[f];
+            p[face[(k + offset) % face.len()]]
+        };
+        let angle = |i: usize| 2.0 * PI * i as f32 / n as f32;
+        let (mut s, mut t) = (ORIGIN, ORIGIN);
+        match scheme {
+            Scheme::CatmullClark => {
+                let (mut edges, mut diagonals) = (ORIGIN, ORIGIN);
+                let a = 1.0
+                    + angle(1).cos()
+                    + (PI / n as f32).cos() * (2.0 * (9.0 + angle(1).cos())).sqrt();
+                for (i, &c) in ring.iter().enumerate() {
+                    let (e, f) = (corner(c, 1), corner(c, 2));
+                    edges += e;
+                    diagonals += f;
+                    s += a * angle(i).cos() * e + (angle(i).cos() + angle(i + 1).cos()) * f;
+                    t += a * angle(i).sin() * e + (angle(i).sin() + angle(i + 1).sin()) * f;
+                }
+                let n = n as f32;
+                positions[v] = (1.0 / (n * (n + 5.0))) * (n * n * p[v] + 4.0 * edges + diagonals);
+            }
+            Scheme::Loop => {
+                let mut sum = ORIGIN;
+                for (i, &c) in ring.iter().enumerate() {
+                    let e = corner(c, 1);
+                    sum += e;
+                    s += angle(i).cos() * e;
+                    t += angle(i).sin() * e;
+                }
+                let gamma = 1.0 / (3.0 / (8.0 * loop_weight(n)) + n as f32);
+                positions[v] = (1.0 - n as f32 * gamma) * p[v] + gamma * sum;
+            }
+        }
+        tangents[v] = Some(s.cross(t));
+    }
+    mesh.vertices = positions;
+
+    let mut normals = vertex_normals(&mesh.vertices, &mesh.faces);
+    for (normal, tangent) in normals.iter_mut().zip(tangents) {
+        if let Some(n) = tangent.filter(|n| n.norm() > 0.0) {
+            // The ring may run either way around, so take the side of the faces
+            let n = n.normalized();
+            *normal = if n * *normal < 0.0 { -n } else { n };
+        }
+    }
+    normals
+}
+
+/// Subdivides a mesh a number of times and moves it onto the limit surface
+///
+/// Returns a triangle mesh with the normals of the limit surface at its vertices.
+pub fn subdivide(mesh: &Polygons, scheme: Scheme, levels: usize) -> (Polygons, Vec<Point>) {
+    let mut refined = match scheme {
+        Scheme::CatmullClark => mesh.clone(),
+        Scheme::Loop => triangulate(mesh),
+    };
+    for _ in 0..levels {
+        refined = match scheme {
+            Scheme::CatmullClark => catmull_clark(&refined),
+            Scheme::Loop => loop_subdivision(&refined),
+        };
+    }
+    let normals = if levels > 0 {
+        limit(&mut refined, scheme)
+    } else {
+        vertex_normals(&refined.vertices, &refined.faces)
+    };
+    (triangulate(&refined), normals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube() -> Polygons {
+        let vertices = (0..8)
+            .map(|i| Point::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32))
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        Polygons {
+            vertices,
+            faces,
+            uvs: None,
+        }
+    }
+
+    #[test]
+    fn closed_meshes_subdivide() {
+        let center = Point::new(0.5, 0.5, 0.5);
+        for &scheme in &[Scheme::CatmullClark, Scheme::Loop] {
+            let (mesh, normals) = subdivide(&cube(), scheme, 3);
+            // Both turn each of the twelve triangles of the cube into 64
+            assert_eq!(mesh.faces.len(), 12 * 64);
+            assert!(mesh.faces.iter().all(|f| f.len() == 3));
+            for (p, n) in mesh.vertices.iter().zip(normals) {
+                let radial = *p - center;
+                assert!(radial.norm() < 0.5 * 3f32.sqrt() && radial.norm() > 0.25);
+                assert!((n.norm() - 1.0).abs() < 1e-5);
+                assert!(n * radial.normalized() > 0.7);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_meshes_stay_flat() {
+        // A grid of 3 by 3 squares, with uvs following the positions
+        let vertices: Vec<Point> = (0..16)
+            .map(|i| Point::new((i % 4) as f32, (i / 4) as f32, 0.0))
+            .collect();
+        let uvs = vertices.iter().map(|p| (p.x, p.y)).collect();
+        let faces = (0..9)
+            .map(|i| {
+                let a = i % 3 + 4 * (i / 3);
+                vec![a, a + 1, a + 5, a + 4]
+            })
+            .collect();
+        let grid = Polygons {
+            vertices,
+            faces,
+            uvs: Some(uvs),
+        };
+        for &scheme in &[Scheme::CatmullClark, Scheme::Loop] {
+            let (mesh, normals) = subdivide(&grid, scheme, 2);
+            assert!(mesh.vertices.iter().all(|p| p.z == 0.0));
+            assert!(normals.iter().all(|n| (*n - UNIT_Z).norm() < 1e-5));
+            // The corners are kept and boundaries stay straight
+            assert!(mesh.vertices.contains(&Point::new(0.0, 3.0, 0.0)));
+            assert!(mesh.vertices.contains(&Point::new(3.0, 0.0, 0.0)));
+            assert!(mesh
+                .vertices
+                .iter()
+                .all(|p| (0.0..=3.0).contains(&p.x) && (0.0..=3.0).contains(&p.y)));
+            assert_eq!(mesh.uvs.as_ref().unwrap().len(), mesh.vertices.len());
+        }
+    }
+}
@@ -0,0 +1,657 @@
+use rand::prelude::*;
+use rand_xoshiro::rand_core::SeedableRng;
+use rand_xoshiro::Xoshiro256Plus;
+use rayon::prelude::*;
+
+use crate::error::{Error, Result};
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+/// The most steps a droplet takes while eroding the terrain
+static DROPLET_LIFETIME: usize = 64;
+
+/// The most iterations of midpoint displacement, for a grid of 4097 by 4097 samples
+static MAX_ITERATIONS: u32 = 12;
+
+/// Gradient noise on the plane, shuffled by a seed
+pub struct Noise {
+    permutation: Vec<usize>,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Noise {
+        let mut rng = Xoshiro256Plus::seed_from_u64(seed);
+        let mut permutation: Vec<usize> = (0..256).collect();
+        permutation.shuffle(&mut rng);
+        permutation.extend_from_within(..);
+        Noise { permutation }
+    }
+
+    fn hash(&self, i: i64, j: i64) -> usize {
+        self.permutation[self.permutation[(i & 255) as usize] + (j & 255) as usize]
+    }
+
+    /// Returns the dot product of one of eight gradients with an offset
+    fn gradient(hash: usize, x: f32, y: f32) -> f32 {
+        match hash & 7 {
+            0 => x + y,
+            1 => x - y,
+            2 => -x + y,
+            3 => -x - y,
+            4 => x,
+            5 => -x,
+            6 => y,
+            _ => -y,
+        }
+    }
+
+    /// Returns Perlin noise, zero at integer coordinates and roughly between -1 and 1
+    pub fn perlin(&self, x: f32, y: f32) -> f32 {
+        let (i, j) = (x.floor(), y.floor());
+        let (fx, fy) = (x - i, y - j);
+        let (i, j) = (i as i64, j as i64);
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let (u, v) = (fade(fx), fade(fy));
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+        let g = |di: i64, dj: i64| {
+            Noise::gradient(self.hash(i + di, j + dj), fx - di as f32, fy - dj as f32)
+        };
+        lerp(lerp(g(0, 0), g(1, 0), u), lerp(g(0, 1), g(1, 1), u), v)
+    }
+
+    /// Returns Perlin noise in space, zero at integer coordinates and roughly between -1 and 1
+    pub fn perlin3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (i, j, k) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - i, y - j, z - k);
+        let (i, j, k) = (i as i64, j as i64, k as i64);
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+        let g = |di: i64, dj: i64, dk: i64| {
+            let hash = self.permutation[self.hash(i + di, j + dj) + ((k + dk) & 255) as usize];
+            let (x, y, z) = (fx - di as f32, fy - dj as f32, fz - dk as f32);
+            // one of the twelve directions to the edges of a cube
+            match hash % 12 {
+                0 => x + y,
+                1 => -x + y,
+                2 => x - y,
+                3 => -x - y,
+                4 => x + z,
+                5 => -x + z,
+                6 => x - z,
+                7 => -x - z,
+                8 => y + z,
+                9 => -y + z,
+                10 => y - z,
+                _ => -y - z,
+            }
+        };
+        let face = |dk: i64| {
+            lerp(
+                lerp(g(0, 0, dk), g(1, 0, dk), u),
+                lerp(g(0, 1, dk), g(1, 1, dk), u),
+                v,
+            )
+        };
+        lerp(face(0), face(1), w)
+    }
+
+    /// Returns simplex noise, roughly between -1 and 1
+    pub fn simplex(&self, x: f32, y: f32) -> f32 {
+        let skew = 0.5 * (3.0f32.sqrt() - 1.0);
+        let unskew = (3.0 - 3.0f32.sqrt()) / 6.0;
+        let s = (x + y) * skew;
+        let (i, j) = ((x + s).floor(), (y + s).floor());
+        let t = (i + j) * unskew;
+        let (x0, y0) = (x - (i - t), y - (j - t));
+        let (di, dj) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let corners = [
+            (0, 0, x0, y0),
+            (di, dj, x0 - di as f32 + unskew, y0 - dj as f32 + unskew),
+            (1, 1, x0 - 1.0 + 2.0 * unskew, y0 - 1.0 + 2.0 * unskew),
+        ];
+        let (i, j) = (i as i64, j as i64);
+        let sum: f32 = corners
+            .iter()
+            .map(|&(ci, cj, cx, cy)| {
+                let falloff = 0.5 - cx * cx - cy * cy;
+                if falloff < 0.0 {
+                    0.0
+                } else {
+                    falloff.powi(4) * Noise::gradient(self.hash(i + ci, j + cj), cx, cy)
+                }
+            })
+            .sum();
+        70.0 * sum
+    }
+}
+
+/// Returns a normally distributed random number, using the Box-Muller transform
+fn gauss(rng: &mut dyn RngCore) -> f32 {
+    let u: f32 = 1.0 - rng.gen::<f32>();
+    let v: f32 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f32::consts::PI * v).cos()
+}
+
+/// How rain droplets erode a terrain, see `Elevation::erode`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Erosion {
+    /// How much droplets keep their direction, 0.05 by default
+    pub inertia: f32,
+    /// How much sediment droplets carry, 4 by default
+    pub capacity: f32,
+    /// How quickly terrain is eroded, 0.3 by default
+    pub erosion: f32,
+    /// How quickly excess sediment is deposited, 0.3 by default
+    pub deposition: f32,
+    /// How much water is lost at each step, 0.01 by default
+    pub evaporation: f32,
+}
+
+impl Default for Erosion {
+    fn default() -> Self {
+        Erosion {
+            inertia: 0.05,
+            capacity: 4.0,
+            erosion: 0.3,
+            deposition: 0.3,
+            evaporation: 0.01,
+        }
+    }
+}
+
+/// Elevations on a regular grid, as generated for terrains
+///
+/// Elevations can be passed to `Heightfield` directly, or converted to lists for numpy.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Elevation {
+    /// The number of samples along x and y
+    pub size: (usize, usize),
+    /// Elevations, consecutive along y
+    pub heights: Vec<f32>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Elevation {
+    /// Creates a square grid with `2**iterations + 1` samples along each side by midpoint
+    /// displacement
+    #[staticmethod]
+    #[name = "diamond_square"]
+    fn py_diamond_square(iterations: u32, roughness: f32, seed: Option<u64>) -> PyResult<Self> {
+        Ok(Elevation::diamond_square(iterations, roughness, seed)?)
+    }
+
+    /// Creates a square grid of fractal Brownian motion, summing octaves of noise
+    #[staticmethod]
+    #[name = "fbm"]
+    fn py_fbm(
+        size: usize,
+        scale: f32,
+        octaves: Option<usize>,
+        persistence: Option<f32>,
+        lacunarity: Option<f32>,
+        seed: Option<u64>,
+        simplex: Option<bool>,
+    ) -> PyResult<Self> {
+        Ok(Elevation::fbm(
+            size,
+            scale,
+            octaves,
+            persistence,
+            lacunarity,
+            seed,
+            simplex,
+        )?)
+    }
+
+    /// Creates a square grid of ridged multifractal noise, with sharp crests like mountain ranges
+    #[staticmethod]
+    #[name = "ridged"]
+    fn py_ridged(
+        size: usize,
+        scale: f32,
+        octaves: Option<usize>,
+        lacunarity: Option<f32>,
+        gain: Option<f32>,
+        offset: Option<f32>,
+        seed: Option<u64>,
+    ) -> PyResult<Self> {
+        Ok(Elevation::ridged(
+            size, scale, octaves, lacunarity, gain, offset, seed,
+        )?)
+    }
+
+    /// Simulates rain droplets running down the terrain, carrying away and depositing sediment
+    ///
+    /// # Arguments
+    ///
+    /// * `droplets` - how many droplets to simulate
+    /// * `seed` - places the droplets
+    /// * `inertia` - how much droplets keep their direction, 0.05 by default
+    /// * `capacity` - how much sediment droplets carry, 4 by default
+    /// * `erosion` - how quickly terrain is eroded, 0.3 by default
+    /// * `deposition` - how quickly excess sediment is deposited, 0.3 by default
+    /// * `evaporation` - how much water is lost at each step, 0.01 by default
+    #[name = "erode"]
+    fn py_erode(
+        &mut self,
+        droplets: usize,
+        seed: Option<u64>,
+        inertia: Option<f32>,
+        capacity: Option<f32>,
+        erosion: Option<f32>,
+        deposition: Option<f32>,
+        evaporation: Option<f32>,
+    ) -> PyResult<()> {
+        let defaults = Erosion::default();
+        let settings = Erosion {
+            inertia: inertia.unwrap_or(defaults.inertia),
+            capacity: capacity.unwrap_or(defaults.capacity),
+            erosion: erosion.unwrap_or(defaults.erosion),
+            deposition: deposition.unwrap_or(defaults.deposition),
+            evaporation: evaporation.unwrap_or(defaults.evaporation),
+        };
+        Ok(self.erode(droplets, seed.unwrap_or(0), &settings)?)
+    }
+
+    /// Returns elevations multiplied by a factor
+    #[name = "scaled"]
+    fn py_scaled(&self, factor: f32) -> Self {
+        self.scaled(factor)
+    }
+
+    #[getter]
+    fn get_size(&self) -> PyResult<(usize, usize)> {
+        Ok(self.size)
+    }
+
+    /// Returns the elevations as nested lists, indexed by x first
+    #[name = "to_list"]
+    fn py_to_list(&self) -> Vec<Vec<f32>> {
+        self.to_list()
+    }
+}
+
+impl Elevation {
+    /// Creates a square grid with `2**iterations + 1` samples along each side by midpoint
+    /// displacement
+    ///
+    /// Random displacements shrink by half every `roughness` iterations, which must be
+    /// positive, and there can be at most 12 iterations.
+    pub fn diamond_square(iterations: u32, roughness: f32, seed: Option<u64>) -> Result<Self> {
+        if iterations > MAX_ITERATIONS {
+            return Err(Error::Value(format!(
+                "at most {} iterations are supported",
+                MAX_ITERATIONS
+            )));
+        }
+        if !(roughness > 0.0 && roughness.is_finite()) {
+            return Err(Error::Value("roughness must be positive".into()));
+        }
+        let mut rng = Xoshiro256Plus::seed_from_u64(seed.unwrap_or(0));
+        let size = 2usize.pow(iterations) + 1;
+        let mut elevation = Elevation {
+            size: (size, size),
+            heights: vec![0.0; size * size],
+        };
+        for n in (1..=iterations).rev() {
+            let step = 2usize.pow(n);
+            let offset = step / 2;
+            let magnitude = 2.0f32.powf((n as f32 - iterations as f32) / roughness);
+            // Centers of the squares, from their corners
+            for x in (offset..size).step_by(step) {
+                for y in (offset..size).step_by(step) {
+                    let average = 0.25
+                        * (elevation.at(x - offset, y - offset)
+                            + elevation.at(x + offset, y - offset)
+                            + elevation.at(x - offset, y + offset)
+                            + elevation.at(x + offset, y + offset));
+                    elevation.set(x, y, average + gauss(&mut rng) * magnitude);
+                }
+            }
+            // Midpoints of the edges, averaging the neighbours within the grid
+            for (x0, y0) in [(0, offset), (offset, 0)].iter() {
+                for x in (*x0..size).step_by(step) {
+                    for y in (*y0..size).step_by(step) {
+                        let neighbours = [
+                            (x.checked_sub(offset), Some(y)),
+                            (Some(x + offset), Some(y)),
+                            (Some(x), y.checked_sub(offset)),
+                            (Some(x), Some(y + offset)),
+                        ];
+                        let (sum, count) = neighbours
+                            .iter()
+                            .filter_map(|n| match n {
+                                (Some(a), Some(b)) if *a < size && *b < size => {
+                                    Some(elevation.at(*a, *b))
+                                }
+                                _ => None,
+                            })
+                            .fold((0.0, 0), |(s, c), h| (s + h, c + 1));
+                        let average = sum / count as f32;
+                        elevation.set(x, y, average + gauss(&mut rng) * magnitude);
+                    }
+                }
+            }
+        }
+        Ok(elevation)
+    }
+
+    /// Creates a square grid of fractal Brownian motion, summing octaves of noise
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - the number of samples along each side
+    /// * `scale` - the number of samples spanned by the coarsest features
+    /// * `octaves` - how many layers of noise to add, 6 by default
+    /// * `persistence` - how much each octave is weakened, 0.5 by default
+    /// * `lacunarity` - how much finer each octave is, 2 by default
+    /// * `seed` - shuffles the noise
+    /// * `simplex` - if simplex noise is used instead of Perlin noise
+    pub fn fbm(
+        size: usize,
+        scale: f32,
+        octaves: Option<usize>,
+        persistence: Option<f32>,
+        lacunarity: Option<f32>,
+        seed: Option<u64>,
+        simplex: Option<bool>,
+    ) -> Result<Self> {
+        let noise = Noise::new(seed.unwrap_or(0));
+        let octaves = octaves.unwrap_or(6);
+        let persistence = persistence.unwrap_or(0.5);
+        let lacunarity = lacunarity.unwrap_or(2.0);
+        let simplex = simplex.unwrap_or(false);
+        Elevation::generate(size, |x, y| {
+            let (mut sum, mut total) = (0.0, 0.0);
+            let (mut amplitude, mut frequency) = (1.0, 1.0 / scale);
+            for _ in 0..octaves {
+                let n = if simplex {
+                    noise.simplex(x * frequency, y * frequency)
+                } else {
+                    noise.perlin(x * frequency, y * frequency)
+                };
+                sum += amplitude * n;
+                total += amplitude;
+                amplitude *= persistence;
+                frequency *= lacunarity;
+            }
+            sum / total
+        })
+    }
+
+    /// Creates a square grid of ridged multifractal noise, with sharp crests like mountain ranges
+    ///
+    /// Each octave is weighted by the one before, so that valleys stay smooth.  Arguments are as
+    /// for `fbm`, with `gain` (2 by default) controlling how quickly the weights saturate and
+    /// `offset` (1 by default) raising the ridges.
+    pub fn ridged(
+        size: usize,
+        scale: f32,
+        octaves: Option<usize>,
+        lacunarity: Option<f32>,
+        gain: Option<f32>,
+        offset: Option<f32>,
+        seed: Option<u64>,
+    ) -> Result<Self> {
+        let noise = Noise::new(seed.unwrap_or(0));
+        let octaves = octaves.unwrap_or(6);
+        let lacunarity = lacunarity.unwrap_or(2.0);
+        let gain = gain.unwrap_or(2.0);
+        let offset = offset.unwrap_or(1.0);
+        Elevation::generate(size, |x, y| {
+            let (mut sum, mut weight, mut frequency) = (0.0, 1.0, 1.0);
+            for _ in 0..octaves {
+                let f = frequency / scale;
+                let signal = (offset - noise.perlin(x * f, y * f).abs()).powi(2) * weight;
+                weight = (signal * gain).clamp(0.0, 1.0);
+                sum += signal / frequency;
+                frequency *= lacunarity;
+            }
+            sum
+        })
+    }
+
+    /// Simulates rain droplets running down the terrain, carrying away and depositing sediment
+    ///
+    /// # Arguments
+    ///
+    /// * `droplets` - how many droplets to simulate
+    /// * `seed` - places the droplets
+    /// * `settings` - how droplets move and carry sediment
+    pub fn erode(&mut self, droplets: usize, seed: u64, settings: &Erosion) -> Result<()> {
+        self.validate()?;
+        let mut rng = Xoshiro256Plus::seed_from_u64(seed);
+        let Erosion {
+            inertia,
+            capacity,
+            erosion,
+            deposition,
+            evaporation,
+        } = *settings;
+        let limit = ((self.size.0 - 1) as f32, (self.size.1 - 1) as f32);
+
+        for _ in 0..droplets {
+            let mut position = (rng.gen::<f32>() * limit.0, rng.gen::<f32>() * limit.1);
+            let mut direction = (0.0, 0.0);
+            let (mut speed, mut water, mut sediment) = (1.0f32, 1.0f32, 0.0f32);
+            for _ in 0..DROPLET_LIFETIME {
+                let (height, gradient) = self.sample(position);
+                direction = (
+                    direction.0 * inertia - gradient.0 * (1.0 - inertia),
+                    direction.1 * inertia - gradient.1 * (1.0 - inertia),
+                );
+                let length = (direction.0 * direction.0 + direction.1 * direction.1).sqrt();
+                if length == 0.0 {
+                    break;
+                }
+                direction = (direction.0 / length, direction.1 / length);
+                let next = (position.0 + direction.0, position.1 + direction.1);
+                if next.0 < 0.0 || next.1 < 0.0 || next.0 >= limit.0 || next.1 >= limit.1 {
+                    break;
+                }
+
+                let change = self.sample(next).0 - height;
+                let carried = (-change * speed * water * capacity).max(0.01);
+                if sediment > carried || change > 0.0 {
+                    let amount = if change > 0.0 {
+                        change.min(sediment)
+                    } else {
+                        (sediment - carried) * deposition
+                    };
+                    sediment -= amount;
+                    self.spread(position, amount);
+                } else {
+                    let amount = ((carried - sediment) * erosion).min(-change);
+                    sediment += amount;
+                    self.spread(position, -amount);
+                }
+
+                speed = (speed * speed + change * 4.0).max(0.0).sqrt();
+                water *= 1.0 - evaporation;
+                position = next;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns elevations multiplied by a factor
+    pub fn scaled(&self, factor: f32) -> Self {
+        Elevation {
+            size: self.size,
+            heights: self.heights.iter().map(|h| h * factor).collect(),
+        }
+    }
+
+    /// Returns the elevations as nested lists, indexed by x first
+    pub fn to_list(&self) -> Vec<Vec<f32>> {
+        self.heights
+            .chunks(self.size.1)
+            .map(|row| row.to_vec())
+            .collect()
+    }
+
+    /// Creates a square grid by evaluating a function at each sample in parallel
+    fn generate<F>(size: usize, f: F) -> Result<Self>
+    where
+        F: Fn(f32, f32) -> f32 + Sync,
+    {
+        if size < 2 {
+            return Err(Error::Value(
+                "elevation needs at least two samples along each side".into(),
+            ));
+        }
+        let mut heights = vec![0.0; size * size];
+        heights
+            .par_chunks_mut(size)
+            .enumerate()
+            .for_each(|(x, row)| {
+                for (y, h) in row.iter_mut().enumerate() {
+                    *h = f(x as f32, y as f32);
+                }
+            });
+        Ok(Elevation {
+            size: (size, size),
+            heights,
+        })
+    }
+
+    /// Checks that the grid can be used for a terrain
+    pub fn validate(&self) -> Result<()> {
+        if self.size.0 < 2 || self.size.1 < 2 {
+            return Err(Error::Value(
+                "elevation needs at least two samples along each side".into(),
+            ));
+        }
+        if self.heights.len() != self.size.0 * self.size.1 {
+            return Err(Error::Value("elevation does not match its size".into()));
+        }
+        Ok(())
+    }
+
+    fn at(&self, x: usize, y: usize) -> f32 {
+        self.heights[x * self.size.1 + y]
+    }
+
+    fn set(&mut self, x: usize, y: usize, height: f32) {
+        self.heights[x * self.size.1 + y] = height;
+    }
+
+    /// Returns the interpolated height and gradient at a position within the grid
+    fn sample(&self, (x, y): (f32, f32)) -> (f32, (f32, f32)) {
+        let (i, j) = (x as usize, y as usize);
+        let (fx, fy) = (x - i as f32, y - j as f32);
+        let h00 = self.at(i, j);
+        let h10 = self.at(i + 1, j);
+        let h01 = self.at(i, j + 1);
+        let h11 = self.at(i + 1, j + 1);
+        let height = h00 * (1.0 - fx) * (1.0 - fy)
+            + h10 * fx * (1.0 - fy)
+            + h01 * (1.0 - fx) * fy
+            + h11 * fx * fy;
+        let gradient = (
+            (h10 - h00) * (1.0 - fy) + (h11 - h01) * fy,
+            (h01 - h00) * (1.0 - fx) + (h11 - h10) * fx,
+        );
+        (height, gradient)
+    }
+
+    /// Adds an amount to the four samples around a position, weighted by their proximity
+    fn spread(&mut self, (x, y): (f32, f32), amount: f32) {
+        let (i, j) = (x as usize, y as usize);
+        let (fx, fy) = (x - i as f32, y - j as f32);
+        let ny = self.size.1;
+        self.heights[i * ny + j] += amount * (1.0 - fx) * (1.0 - fy);
+        self.heights[(i + 1) * ny + j] += amount * fx * (1.0 - fy);
+        self.heights[i * ny + j + 1] += amount * (1.0 - fx) * fy;
+        self.heights[(i + 1) * ny + j + 1] += amount * fx * fy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_is_smooth() {
+        let noise = Noise::new(3);
+        for k in 0..20 {
+            assert_eq!(noise.perlin(k as f32, (k * 3) as f32), 0.0);
+            let (x, y) = (k as f32 * 0.37, k as f32 * 0.71);
+            assert!((noise.perlin(x, y) - noise.perlin(x + 1e-3, y)).abs() < 1e-2);
+            assert!(noise.perlin(x, y).abs() <= 1.0);
+            assert!(noise.simplex(x, y).abs() <= 1.0);
+            assert!((noise.simplex(x, y) - noise.simplex(x, y + 1e-3)).abs() < 1e-2);
+        }
+        assert_ne!(Noise::new(4).perlin(0.5, 0.5), noise.perlin(0.5, 0.5));
+        for k in 0..20 {
+            assert_eq!(noise.perlin3(k as f32, -k as f32, (k * 7) as f32), 0.0);
+            let (x, y, z) = (k as f32 * 0.37, k as f32 * -0.71, k as f32 * 0.53);
+            assert!(noise.perlin3(x, y, z).abs() <= 1.0);
+            assert!((noise.perlin3(x, y, z) - noise.perlin3(x, y, z + 1e-3)).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn terrains_generate() {
+        let a = Elevation::diamond_square(5, 0.5, Some(1)).unwrap();
+        assert_eq!(a.size, (33, 33));
+        assert_eq!(a.heights.len(), 33 * 33);
+        assert_eq!(a.at(0, 0), 0.0);
+        assert!(a.heights.iter().any(|h| *h != 0.0));
+        let same = Elevation::diamond_square(5, 0.5, Some(1)).unwrap();
+        assert!(a.heights == same.heights);
+        let other = Elevation::diamond_square(5, 0.5, Some(2)).unwrap();
+        assert!(a.heights != other.heights);
+
+        // With displacements vanishing quickly, the last centers lie amid their corners
+        let smooth = Elevation::diamond_square(4, 0.25, Some(1)).unwrap();
+        for x in (1..17).step_by(2) {
+            for y in (1..17).step_by(2) {
+                let corners = smooth.at(x - 1, y - 1)
+                    + smooth.at(x + 1, y - 1)
+                    + smooth.at(x - 1, y + 1)
+                    + smooth.at(x + 1, y + 1);
+                assert!((smooth.at(x, y) - 0.25 * corners).abs() < 1e-2);
+            }
+        }
+        assert!(Elevation::diamond_square(4, 0.0, None).is_err());
+        assert!(Elevation::diamond_square(4, f32::NAN, None).is_err());
+        assert!(Elevation::diamond_square(64, 0.5, None).is_err());
+
+        let f = Elevation::fbm(64, 16.0, None, None, None, Some(1), None).unwrap();
+        assert!(f.heights.iter().all(|h| h.abs() <= 1.0));
+        let s = Elevation::fbm(64, 16.0, Some(3), None, None, Some(1), Some(true)).unwrap();
+        assert!(s.heights != f.heights);
+        let r = Elevation::ridged(64, 16.0, None, None, None, None, Some(1)).unwrap();
+        assert!(r.heights.iter().all(|h| *h >= 0.0));
+        assert_eq!(r.to_list().len(), 64);
+        assert!(r.validate().is_ok());
+    }
+
+    #[test]
+    fn erosion_moves_sediment() {
+        let mut e = Elevation::fbm(65, 32.0, None, None, None, Some(7), None)
+            .unwrap()
+            .scaled(10.0);
+        let before = e.heights.clone();
+        e.erode(2000, 1, &Erosion::default()).unwrap();
+        assert!(e.heights.iter().all(|h| h.is_finite()));
+        assert!(e.heights != before);
+        let total = |h: &[f32]| h.iter().sum::<f32>();
+        // Droplets only ever carry sediment off the grid, never add material
+        assert!(total(&e.heights) <= total(&before) + 1e-2);
+
+        for size in 0..2 {
+            assert!(Elevation::fbm(size, 4.0, None, None, None, None, None).is_err());
+            assert!(Elevation::ridged(size, 4.0, None, None, None, None, None).is_err());
+            let mut tiny = Elevation {
+                size: (size, size),
+                heights: vec![0.0; size * size],
+            };
+            assert!(tiny.erode(10, 0, &Erosion::default()).is_err());
+        }
+    }
+}
@@ -0,0 +1,636 @@
+use crate::error::{Error, Result};
+use crate::terrain::Noise;
+use crate::things::{Color, Point};
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+/// The octaves of noise disturbing marble and wood
+static OCTAVES: usize = 4;
+
+/// How images continue beyond surface coordinates of zero and one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    /// Repeated, every other copy mirrored so that edges meet seamlessly
+    Mirror,
+    /// Continued by the pixels at the edges
+    Clamp,
+}
+
+impl Wrap {
+    /// Returns a wrap mode by name, one of repeat, mirror or clamp
+    pub fn named(name: &str) -> Result<Wrap> {
+        match name {
+            "repeat" => Ok(Wrap::Repeat),
+            "mirror" => Ok(Wrap::Mirror),
+            "clamp" => Ok(Wrap::Clamp),
+            _ => Err(Error::Value(format!("unknown wrap mode {}", name))),
+        }
+    }
+
+    /// Returns the index of a pixel in a row or column of `count`
+    fn index(&self, i: i64, count: usize) -> usize {
+        let n = count as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(n),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+            Wrap::Clamp => i.clamp(0, n - 1),
+        };
+        i as usize
+    }
+}
+
+/// Where procedural textures are evaluated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Space {
+    /// On the plane of surface coordinates, u along x and v along y
+    Uv,
+    /// At the points hit in the scene, so that patterns run on across surfaces
+    World,
+}
+
+impl Space {
+    /// Returns a space by name, uv or world
+    pub fn named(name: &str) -> Result<Space> {
+        match name {
+            "uv" => Ok(Space::Uv),
+            "world" => Ok(Space::World),
+            _ => Err(Error::Value(format!("unknown texture space {}", name))),
+        }
+    }
+}
+
+/// Procedural patterns, between zero and one
+enum Shape {
+    /// Alternating unit cubes, or squares on the plane of surface coordinates
+    Checker,
+    /// Fractal Brownian motion
+    Noise { noise: Noise, octaves: usize },
+    /// Unit stripes along x, shifted by turbulence
+    Marble { noise: Noise, turbulence: f32 },
+    /// Unit rings around the z axis, shifted by noise
+    Wood { noise: Noise, turbulence: f32 },
+    /// Rising along the line between two points
+    Gradient { start: Point, end: Point },
+}
+
+impl Shape {
+    fn name(&self) -> &str {
+        match self {
+            Shape::Checker => "checker",
+            Shape::Noise { .. } => "noise",
+            Shape::Marble { .. } => "marble",
+            Shape::Wood { .. } => "wood",
+            Shape::Gradient { .. } => "gradient",
+        }
+    }
+
+    fn value(&self, p: Point, space: Space) -> f32 {
+        match self {
+            Shape::Checker => (p.x.floor() + p.y.floor() + p.z.floor()).rem_euclid(2.0),
+            Shape::Noise { noise, octaves } => 0.5 + 0.5 * fbm(noise, space, p, *octaves, false),
+            Shape::Marble { noise, turbulence } => {
+                let shift = turbulence * fbm(noise, space, p, OCTAVES, true);
+                0.5 + 0.5 * (2.0 * PI * (p.x + shift)).sin()
+            }
+            Shape::Wood { noise, turbulence } => {
+                let shift = turbulence * fbm(noise, space, p, OCTAVES, false);
+                ((p.x * p.x + p.y * p.y).sqrt() + shift).rem_euclid(1.0)
+            }
+            Shape::Gradient { start, end } => {
+                let along = *end - *start;
+                ((p - *start) * along / (along * along)).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// Returns noise summed over octaves of doubling frequency and halving amplitude, between -1
+/// and 1, or for turbulence its absolute values, between 0 and 1
+fn fbm(noise: &Noise, space: Space, p: Point, octaves: usize, turbulent: bool) -> f32 {
+    let (mut sum, mut total, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+    for _ in 0..octaves {
+        let q = frequency * p;
+        let value = match space {
+            Space::Uv => noise.perlin(q.x, q.y),
+            Space::World => noise.perlin3(q.x, q.y, q.z),
+        };
+        sum += amplitude * if turbulent { value.abs() } else { value };
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+enum Pattern {
+    Constant(f32),
+    /// Colors of pixels, consecutive along x, wrapped along u and v
+    Image {
+        width: usize,
+        height: usize,
+        pixels: Vec<Color>,
+        wrap: (Wrap, Wrap),
+    },
+    /// A shape at points in a space, scaled down by `scale`
+    Procedural {
+        shape: Shape,
+        space: Space,
+        scale: f32,
+    },
+}
+
+/// A color or scalar value varying over surfaces
+///
+/// The scalar value of an image is its brightness.  Other textures are gray unless colored by
+/// a ramp of colors at their values.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Texture {
+    pattern: Arc<Pattern>,
+    /// Colors at values, sorted by value
+    ramp: Option<Arc<Vec<(f32, Color)>>>,
+}
+
+/// Textures are equal only if they share their pattern
+impl PartialEq for Texture {
+    fn eq(&self, other: &Texture) -> bool {
+        Arc::ptr_eq(&self.pattern, &other.pattern) && self.ramp == other.ramp
+    }
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &*self.pattern {
+            Pattern::Constant(value) => write!(f, "Texture::constant({})", value)?,
+            Pattern::Image {
+                width,
+                height,
+                wrap,
+                ..
+            } => write!(f, "Texture::image({}x{}, {:?})", width, height, wrap)?,
+            Pattern::Procedural {
+                shape,
+                space,
+                scale,
+            } => write!(f, "Texture::{}({}, {:?})", shape.name(), scale, space)?,
+        }
+        if let Some(ramp) = &self.ramp {
+            write!(f, ".colored({:?})", ramp)?;
+        }
+        Ok(())
+    }
+}
+
+/// Textures varying the properties of a material over its surface
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Textures {
+    /// Multiplying the color of the material
+    pub color: Option<Texture>,
+    /// Replacing the roughness of the material, or the hardness of plain ones
+    pub roughness: Option<Texture>,
+    /// Multiplying the light emitted
+    pub emission: Option<Texture>,
+    /// The share of light stopped by the surface rather than passing straight through it
+    pub opacity: Option<Texture>,
+}
+
+#[cfg(feature = "python")]
+fn space(name: Option<&str>) -> Result<Space> {
+    name.map_or(Ok(Space::Uv), Space::named)
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Texture {
+    #[staticmethod]
+    #[name = "constant"]
+    fn py_constant(value: f32) -> Self {
+        Texture::constant(value)
+    }
+
+    /// Loads an image, repeated beyond surface coordinates of zero and one unless `wrap` is
+    /// mirror or clamp
+    #[staticmethod]
+    #[name = "image"]
+    fn py_image(filename: &str, wrap: Option<&str>) -> PyResult<Self> {
+        let wrap = wrap.map_or(Ok(Wrap::Repeat), Wrap::named)?;
+        Ok(Texture::image(filename, wrap)?)
+    }
+
+    /// Creates a checkerboard with squares of the size `size`, cubes in world space
+    ///
+    /// Procedural textures are evaluated in surface coordinates unless `space` is world.
+    #[staticmethod]
+    #[name = "checker"]
+    fn py_checker(size: f32, space: Option<&str>) -> PyResult<Self> {
+        Ok(Texture::checker(size, self::space(space)?)?)
+    }
+
+    /// Creates smooth noise with features of the size `scale`
+    #[staticmethod]
+    #[name = "noise"]
+    fn py_noise(
+        scale: f32,
+        octaves: Option<usize>,
+        seed: Option<u64>,
+        space: Option<&str>,
+    ) -> PyResult<Self> {
+        Ok(Texture::noise(scale, octaves, seed, self::space(space)?)?)
+    }
+
+    /// Creates marble veins `scale` apart, shifted by up to `turbulence` of that, 2 by default
+    #[staticmethod]
+    #[name = "marble"]
+    fn py_marble(
+        scale: f32,
+        turbulence: Option<f32>,
+        seed: Option<u64>,
+        space: Option<&str>,
+    ) -> PyResult<Self> {
+        Ok(Texture::marble(
+            scale,
+            turbulence,
+            seed,
+            self::space(space)?,
+        )?)
+    }
+
+    /// Creates rings of wood `scale` apart, shifted by up to `turbulence` of that, 0.5 by
+    /// default
+    #[staticmethod]
+    #[name = "wood"]
+    fn py_wood(
+        scale: f32,
+        turbulence: Option<f32>,
+        seed: Option<u64>,
+        space: Option<&str>,
+    ) -> PyResult<Self> {
+        Ok(Texture::wood(scale, turbulence, seed, self::space(space)?)?)
+    }
+
+    /// Creates a gradient from zero at `start` to one at `end`
+    #[staticmethod]
+    #[name = "gradient"]
+    fn py_gradient(start: Point, end: Point, space: Option<&str>) -> PyResult<Self> {
+        Ok(Texture::gradient(start, end, self::space(space)?)?)
+    }
+
+    /// Returns the texture colored by a ramp of pairs of values and colors
+    #[name = "colored"]
+    fn py_colored(&self, stops: Vec<(f32, Color)>) -> PyResult<Self> {
+        Ok(self.clone().colored(stops)?)
+    }
+
+    /// Returns the value at surface coordinates, and at a point for textures in world space
+    #[name = "value"]
+    fn py_value(&self, u: f32, v: f32, point: Option<Point>) -> f32 {
+        self.value_at(point.unwrap_or_else(|| Point::new(u, v, 0.0)), u, v)
+    }
+
+    /// Returns the color at surface coordinates, and at a point for textures in world space
+    #[name = "color"]
+    fn py_color(&self, u: f32, v: f32, point: Option<Point>) -> Color {
+        self.color_at(point.unwrap_or_else(|| Point::new(u, v, 0.0)), u, v)
+    }
+}
+
+impl Texture {
+    pub fn constant(value: f32) -> Self {
+        Texture::from(Pattern::Constant(value))
+    }
+
+    /// Loads an image in any format `image` reads, such as PNG, JPEG or OpenEXR
+    ///
+    /// Colors are taken as they are stored, as are those of the rendered image.
+    pub fn image(filename: &str, wrap: Wrap) -> Result<Self> {
+        let image = image::open(filename)
+            .map_err(|e| Error::Io(format!("{}: {}", filename, e)))?
+            .to_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image
+            .pixels()
+            .map(|p| Color::new(p.0[0], p.0[1], p.0[2]))
+            .collect();
+        Texture::pixels(width, height, pixels, (wrap, wrap))
+    }
+
+    /// Creates an image from the colors of its pixels, row by row from the top, wrapped
+    /// differently along u and v
+    pub fn pixels(
+        width: usize,
+        height: usize,
+        pixels: Vec<Color>,
+        wrap: (Wrap, Wrap),
+    ) -> Result<Self> {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return Err(Error::Value(format!(
+                "need {}x{} pixels, got {}",
+                width,
+                height,
+                pixels.len()
+            )));
+        }
+        Ok(Texture::from(Pattern::Image {
+            width,
+            height,
+            pixels,
+            wrap,
+        }))
+    }
+
+    /// Creates a checkerboard with squares of the size `size`, cubes in world space
+    pub fn checker(size: f32, space: Space) -> Result<Self> {
+        Texture::procedural(Shape::Checker, space, size)
+    }
+
+    /// Creates smooth noise with features of the size `scale`
+    pub fn noise(
+        scale: f32,
+        octaves: Option<usize>,
+        seed: Option<u64>,
+        space: Space,
+    ) -> Result<Self> {
+        let shape = Shape::Noise {
+            noise: Noise::new(seed.unwrap_or(0)),
+            octaves: octaves.unwrap_or(4),
+        };
+        Texture::procedural(shape, space, scale)
+    }
+
+    /// Creates marble veins `scale` apart across x, shifted by up to `turbulence` of that, 2 by
+    /// default
+    pub fn marble(
+        scale: f32,
+        turbulence: Option<f32>,
+        seed: Option<u64>,
+        space: Space,
+    ) -> Result<Self> {
+        let shape = Shape::Marble {
+            noise: Noise::new(seed.unwrap_or(0)),
+            turbulence: turbulence.unwrap_or(2.0),
+        };
+        Texture::procedural(shape, space, scale)
+    }
+
+    /// Creates rings of wood `scale` apart around the z axis, or around the origin of surface
+    /// coordinates, shifted by up to `turbulence` of that, 0.5 by default
+    pub fn wood(
+        scale: f32,
+        turbulence: Option<f32>,
+        seed: Option<u64>,
+        space: Space,
+    ) -> Result<Self> {
+        let shape = Shape::Wood {
+            noise: Noise::new(seed.unwrap_or(0)),
+            turbulence: turbulence.unwrap_or(0.5),
+        };
+        Texture::procedural(shape, space, scale)
+    }
+
+    /// Creates a gradient from zero at `start` to one at `end`, constant beyond them
+    pub fn gradient(start: Point, end: Point, space: Space) -> Result<Self> {
+        if start == end {
+            return Err(Error::Value("gradients need distinct ends".into()));
+        }
+        Texture::procedural(Shape::Gradient { start, end }, space, 1.0)
+    }
+
+    /// Returns the texture colored by a ramp, interpolating between the colors at the values
+    /// given and keeping those at its ends beyond them
+    pub fn colored(self, mut stops: Vec<(f32, Color)>) -> Result<Self> {
+        if stops.is_empty() || stops.iter().any(|(value, _)| value.is_nan()) {
+            return Err(Error::Value("color ramps need colors at values".into()));
+        }
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Ok(Texture {
+            ramp: Some(Arc::new(stops)),
+            ..self
+        })
+    }
+
+    /// Returns the value at surface coordinates
+    pub fn value(&self, u: f32, v: f32) -> f32 {
+        self.value_at(Point::new(u, v, 0.0), u, v)
+    }
+
+    /// Returns the color at surface coordinates
+    pub fn color(&self, u: f32, v: f32) -> Color {
+        self.color_at(Point::new(u, v, 0.0), u, v)
+    }
+
+    /// Returns the value where a surface is hit, the point used only by textures in world space
+    pub fn value_at(&self, point: Point, u: f32, v: f32) -> f32 {
+        match &*self.pattern {
+            Pattern::Constant(value) => *value,
+            Pattern::Image { .. } => {
+                // Rec. 709 luma, as of grayscale images
+                let c = self.pixel(u, v);
+                0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b
+            }
+            Pattern::Procedural {
+                shape,
+                space,
+                scale,
+            } => {
+                let p = match space {
+                    Space::Uv => Point::new(u, v, 0.0),
+                    Space::World => point,
+                };
+                shape.value(p / *scale, *space)
+            }
+        }
+    }
+
+    /// Returns the color where a surface is hit, see `value_at`
+    pub fn color_at(&self, point: Point, u: f32, v: f32) -> Color {
+        if let Some(ramp) = &self.ramp {
+            return color(ramp, self.value_at(point, u, v));
+        }
+        match &*self.pattern {
+            Pattern::Image { .. } => self.pixel(u, v),
+            _ => {
+                let value = self.value_at(point, u, v);
+                Color::new(value, value, value)
+            }
+        }
+    }
+
+    /// Returns the color of an image at surface coordinates, black for other textures
+    fn pixel(&self, u: f32, v: f32) -> Color {
+        match &*self.pattern {
+            Pattern::Image {
+                width,
+                height,
+                pixels,
+                wrap,
+            } => {
+                // Bilinear interpolation between the centers of pixels, with v pointing up
+                let x = u * *width as f32 - 0.5;
+                let y = (1.0 - v) * *height as f32 - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let at = |i: f32, j: f32| {
+                    let i = wrap.0.index(i as i64, *width);
+                    let j = wrap.1.index(j as i64, *height);
+                    pixels[j * width + i]
+                };
+                (1.0 - fy) * ((1.0 - fx) * at(x0, y0) + fx * at(x0 + 1.0, y0))
+                    + fy * ((1.0 - fx) * at(x0, y0 + 1.0) + fx * at(x0 + 1.0, y0 + 1.0))
+            }
+            _ => Color::black(),
+        }
+    }
+
+    fn procedural(shape: Shape, space: Space, scale: f32) -> Result<Self> {
+        if !(scale > 0.0 && scale.is_finite()) {
+            return Err(Error::Value(format!(
+                "procedural textures need a positive scale, not {}",
+                scale
+            )));
+        }
+        Ok(Texture::from(Pattern::Procedural {
+            shape,
+            space,
+            scale,
+        }))
+    }
+
+    fn from(pattern: Pattern) -> Self {
+        Texture {
+            pattern: Arc::new(pattern),
+            ramp: None,
+        }
+    }
+}
+
+/// Returns the color of a ramp at a value
+fn color(ramp: &[(f32, Color)], value: f32) -> Color {
+    match ramp.iter().position(|&(at, _)| at > value) {
+        Some(0) => ramp[0].1,
+        Some(i) => {
+            let ((a, from), (b, to)) = (ramp[i - 1], ramp[i]);
+            let t = (value - a) / (b - a);
+            (1.0 - t) * from + t * to
+        }
+        None => ramp[ramp.len() - 1].1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::things::ORIGIN;
+
+    #[test]
+    fn textures_vary() {
+        assert_eq!(Texture::constant(0.3).value(0.7, 0.1), 0.3);
+
+        let (black, white) = (Color::black(), Color::white());
+        let checker = |wrap| Texture::pixels(2, 2, vec![black, white, white, black], (wrap, wrap));
+        let checker = |wrap| checker(wrap).unwrap();
+        let repeated = checker(Wrap::Repeat);
+        assert_eq!(repeated.value(0.25, 0.75), 0.0);
+        assert!((repeated.value(0.75, 0.75) - 1.0).abs() < 1e-6);
+        assert_eq!(repeated.value(1.25, 0.75), 0.0);
+        assert!((repeated.value(0.5, 0.75) - 0.5).abs() < 1e-6);
+        assert_eq!(checker(Wrap::Mirror).color(1.25, 0.75), white);
+        assert_eq!(checker(Wrap::Clamp).color(1.25, 0.75), white);
+        assert_eq!(checker(Wrap::Clamp).color(-3.0, 0.75), black);
+        assert_eq!(checker(Wrap::Mirror).color(-0.25, 0.75), black);
+        assert!(Wrap::named("tile").is_err());
+        let striped = Texture::pixels(
+            2,
+            2,
+            vec![black, white, white, black],
+            (Wrap::Clamp, Wrap::Repeat),
+        );
+        assert_eq!(striped.unwrap().color(1.25, 1.75), white);
+        assert!(Texture::pixels(2, 2, vec![black], (Wrap::Clamp, Wrap::Clamp)).is_err());
+
+        let noise = Texture::noise(0.1, None, Some(2), Space::Uv).unwrap();
+        for k in 0..20 {
+            let value = noise.value(0.013 * k as f32, 0.029 * k as f32);
+            assert!((0.0..=1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn images_load() {
+        let filename = std::env::temp_dir().join("pathetic-texture.png");
+        let filename = filename.to_str().unwrap();
+        let mut image = image::RgbImage::new(2, 1);
+        image.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+        image.put_pixel(1, 0, image::Rgb([0, 0, 255]));
+        image.save(filename).unwrap();
+
+        let texture = Texture::image(filename, Wrap::Clamp).unwrap();
+        assert_eq!(texture.color(0.1, 0.5), Color::red());
+        assert_eq!(texture.color(0.9, 0.5), Color::blue());
+        assert!((texture.value(0.1, 0.5) - 0.2126).abs() < 1e-6);
+        assert!(Texture::image("missing.png", Wrap::Repeat).is_err());
+    }
+
+    #[test]
+    fn procedural_textures() {
+        let squares = Texture::checker(0.5, Space::Uv).unwrap();
+        assert_eq!(squares.value(0.25, 0.25), 0.0);
+        assert_eq!(squares.value(0.75, 0.25), 1.0);
+        assert_eq!(squares.value(-0.25, 0.25), 1.0);
+        let cubes = Texture::checker(0.5, Space::World).unwrap();
+        assert_eq!(cubes.value_at(Point::new(0.2, 0.2, 0.7), 0.0, 0.0), 1.0);
+        assert_eq!(format!("{:?}", cubes), "Texture::checker(0.5, World)");
+
+        let ramp = Texture::gradient(ORIGIN, Point::new(2.0, 0.0, 0.0), Space::World).unwrap();
+        assert_eq!(ramp.value_at(Point::new(1.0, 5.0, 0.0), 0.0, 0.0), 0.5);
+        assert_eq!(ramp.value_at(Point::new(3.0, 0.0, 0.0), 0.0, 0.0), 1.0);
+        let stops = vec![
+            (1.0, Color::blue()),
+            (0.0, Color::red()),
+            (0.5, Color::white()),
+        ];
+        let colored = ramp.colored(stops).unwrap();
+        assert_eq!(colored.color_at(ORIGIN, 0.0, 0.0), Color::red());
+        let bluish = colored.color_at(Point::new(1.5, 0.0, 0.0), 0.0, 0.0);
+        assert_eq!(bluish, Color::new(0.5, 0.5, 1.0));
+        assert!(Texture::gradient(ORIGIN, ORIGIN, Space::Uv).is_err());
+        assert!(Texture::constant(0.5).colored(vec![]).is_err());
+        assert!(Space::named("object").is_err());
+
+        let marble = Texture::marble(0.3, None, Some(1), Space::World).unwrap();
+        let wood = Texture::wood(0.1, None, Some(1), Space::Uv).unwrap();
+        let noise = Texture::noise(0.2, Some(3), None, Space::World).unwrap();
+        assert!(Texture::checker(0.0, Space::Uv).is_err());
+        assert!(Texture::noise(-0.2, None, None, Space::Uv).is_err());
+        assert!(Texture::marble(f32::NAN, None, None, Space::World).is_err());
+        assert!(Texture::wood(f32::INFINITY, None, None, Space::World).is_err());
+        for texture in &[marble, wood, noise] {
+            let values: Vec<f32> = (0..20)
+                .map(|k| {
+                    let p = Point::new(0.11 * k as f32, 0.07 * k as f32, -0.05 * k as f32);
+                    texture.value_at(p, p.x, p.y)
+                })
+                .collect();
+            assert!(
+                values.iter().all(|v| (0.0..=1.0).contains(v)),
+                "{:?}",
+                texture
+            );
+            assert!(
+                values.iter().any(|&v| (v - values[0]).abs() > 0.1),
+                "{:?}",
+                texture
+            );
+        }
+    }
+}
@@ -1,15 +1,29 @@
 extern crate rand;
 
+use crate::bsdf::SharedBsdf;
+use crate::bvh::*;
+use crate::error::{Error, Result};
+use crate::microfacet::Microfacet;
+use crate::principled::Principled;
+use crate::scene::Shutter;
+#[cfg(feature = "python")]
+use crate::texture::Texture;
+use crate::texture::Textures;
 use pathetic_derive::*;
+#[cfg(feature = "python")]
+use pyo3::exceptions;
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
 use rand::prelude::*;
 use std::ops;
+use std::sync::Arc;
 
 static EPSILON: f32 = 1e-6;
+/// Offset for rays leaving a surface
+pub static BIAS: f32 = 1e-4;
 
 /// A point in space
-#[pyclass]
-#[text_signature = "(x, y, z)"]
+#[cfg_attr(feature = "python", pyclass, text_signature = "(x, y, z)")]
 #[derive(Clone, Copy, Debug, PartialEq, PartialOps)]
 pub struct Point {
     pub x: f32,
@@ -17,11 +31,12 @@ pub struct Point {
     pub z: f32,
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Point {
     #[new]
-    pub fn new(x: f32, y: f32, z: f32) -> Point {
-        Point { x, y, z }
+    fn py_new(x: f32, y: f32, z: f32) -> Point {
+        Point::new(x, y, z)
     }
 
     #[getter]
@@ -59,6 +74,10 @@ impl Point {
 }
 
 impl Point {
+    pub fn new(x: f32, y: f32, z: f32) -> Point {
+        Point { x, y, z }
+    }
+
     pub fn cross(self, other: Point) -> Point {
         Point::new(
             self.y * other.z - self.z * other.y,
@@ -116,6 +135,20 @@ impl Point {
     }
 }
 
+impl ops::Index<usize> for Point {
+    type Output = f32;
+
+    /// Returns the component along the `n`th axis
+    fn index(&self, n: usize) -> &f32 {
+        match n {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("a point only has three axes"),
+        }
+    }
+}
+
 impl ops::Mul<Point> for Point {
     type Output = f32;
 
@@ -124,9 +157,14 @@ impl ops::Mul<Point> for Point {
     }
 }
 
+impl From<[f64; 3]> for Point {
+    fn from(p: [f64; 3]) -> Point {
+        Point::new(p[0] as f32, p[1] as f32, p[2] as f32)
+    }
+}
+
 /// A color with components red, green, and blue
-#[pyclass]
-#[text_signature = "(r, g, b)"]
+#[cfg_attr(feature = "python", pyclass, text_signature = "(r, g, b)")]
 #[derive(Clone, Copy, Debug, PartialEq, PartialOps)]
 pub struct Color {
     pub r: f32,
@@ -134,11 +172,12 @@ pub struct Color {
     pub b: f32,
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Color {
     #[new]
-    pub fn new(r: f32, g: f32, b: f32) -> Color {
-        Color { r, g, b }
+    fn py_new(r: f32, g: f32, b: f32) -> Color {
+        Color::new(r, g, b)
     }
 
     #[getter]
@@ -157,26 +196,57 @@ impl Color {
     }
 
     #[staticmethod]
+    #[name = "black"]
+    fn py_black() -> Self {
+        Color::black()
+    }
+
+    #[staticmethod]
+    #[name = "white"]
+    fn py_white() -> Self {
+        Color::white()
+    }
+
+    #[staticmethod]
+    #[name = "red"]
+    fn py_red() -> Self {
+        Color::red()
+    }
+
+    #[staticmethod]
+    #[name = "green"]
+    fn py_green() -> Self {
+        Color::green()
+    }
+
+    #[staticmethod]
+    #[name = "blue"]
+    fn py_blue() -> Self {
+        Color::blue()
+    }
+}
+
+impl Color {
+    pub fn new(r: f32, g: f32, b: f32) -> Color {
+        Color { r, g, b }
+    }
+
     pub fn black() -> Self {
         Color::new(0.0, 0.0, 0.0)
     }
 
-    #[staticmethod]
     pub fn white() -> Self {
         Color::new(1.0, 1.0, 1.0)
     }
 
-    #[staticmethod]
     pub fn red() -> Self {
         Color::new(1.0, 0.0, 0.0)
     }
 
-    #[staticmethod]
     pub fn green() -> Self {
         Color::new(0.0, 1.0, 0.0)
     }
 
-    #[staticmethod]
     pub fn blue() -> Self {
         Color::new(0.0, 0.0, 1.0)
     }
@@ -190,6 +260,12 @@ impl ops::Mul<Color> for Color {
     }
 }
 
+impl From<[f64; 3]> for Color {
+    fn from(c: [f64; 3]) -> Color {
+        Color::new(c[0] as f32, c[1] as f32, c[2] as f32)
+    }
+}
+
 pub static ORIGIN: Point = Point {
     x: 0.0,
     y: 0.0,
@@ -212,8 +288,12 @@ pub static UNIT_Z: Point = Point {
 };
 
 /// Properties of objects in a scene
-#[pyclass]
-#[derive(Clone, Copy)]
+///
+/// The specular part reflects off a rough surface with the hardness as its roughness.  With a
+/// BSDF of its own, the material scatters light only by that, see `bsdf`.  Textures vary
+/// the color and other properties over surfaces, see `Shading::at`.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone, Debug)]
 pub struct Material {
     pub specularity: f32,
     pub hardness: f32,
@@ -221,11 +301,103 @@ pub struct Material {
     pub refraction: f32,
     pub emittance: f32,
     pub color: Color,
+    pub bsdf: Option<SharedBsdf>,
+    pub textures: Textures,
+}
+
+/// Materials with a BSDF or textures are equal only if they share them
+impl PartialEq for Material {
+    fn eq(&self, other: &Material) -> bool {
+        let same_bsdf = match (&self.bsdf, &other.bsdf) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        same_bsdf
+            && self.textures == other.textures
+            && (self.specularity, self.hardness, self.diffusion)
+                == (other.specularity, other.hardness, other.diffusion)
+            && (self.refraction, self.emittance, self.color)
+                == (other.refraction, other.emittance, other.color)
+    }
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Material {
     #[new]
+    fn py_new(
+        specularity: f32,
+        hardness: f32,
+        diffusion: f32,
+        refraction: f32,
+        emittance: f32,
+        color: Color,
+    ) -> Material {
+        Material::new(
+            specularity,
+            hardness,
+            diffusion,
+            refraction,
+            emittance,
+            color,
+        )
+    }
+
+    #[staticmethod]
+    #[name = "light"]
+    fn py_light(color: Color) -> Self {
+        Material::light(color)
+    }
+
+    /// Returns a metal by name, one of gold, copper, aluminium or silver
+    #[staticmethod]
+    #[name = "metal"]
+    fn py_metal(name: &str, roughness: f32) -> PyResult<Self> {
+        Ok(Material::metal(name, roughness)?)
+    }
+
+    /// Returns a metal of a complex index of refraction, given for red, green and blue light
+    #[staticmethod]
+    #[name = "conductor"]
+    fn py_conductor(eta: Color, k: Color, roughness: f32) -> Self {
+        Material::conductor(eta, k, roughness)
+    }
+
+    /// Returns a transparent material such as glass, frosted by its roughness
+    #[staticmethod]
+    #[name = "dielectric"]
+    fn py_dielectric(ior: f32, roughness: f32) -> Self {
+        Material::dielectric(ior, roughness)
+    }
+
+    /// Returns a material following the principled model
+    #[staticmethod]
+    #[name = "principled"]
+    fn py_principled(principled: Principled) -> Self {
+        Material::principled(principled)
+    }
+
+    /// Returns the material with textures for color, roughness, emission or opacity, keeping
+    /// those of slots not given
+    #[name = "textured"]
+    fn py_textured(
+        &self,
+        color: Option<Texture>,
+        roughness: Option<Texture>,
+        emission: Option<Texture>,
+        opacity: Option<Texture>,
+    ) -> Self {
+        let textures = &self.textures;
+        self.clone().textured(Textures {
+            color: color.or_else(|| textures.color.clone()),
+            roughness: roughness.or_else(|| textures.roughness.clone()),
+            emission: emission.or_else(|| textures.emission.clone()),
+            opacity: opacity.or_else(|| textures.opacity.clone()),
+        })
+    }
+}
+
+impl Material {
     pub fn new(
         specularity: f32,
         hardness: f32,
@@ -241,10 +413,12 @@ impl Material {
             refraction,
             emittance,
             color,
+            bsdf: None,
+            textures: Textures::default(),
         }
     }
 
-    #[staticmethod]
+    /// Returns a material only emitting light of the given color
     pub fn light(color: Color) -> Self {
         Material {
             specularity: 0.0,
@@ -253,53 +427,120 @@ impl Material {
             refraction: 0.0,
             emittance: 1.0,
             color,
+            bsdf: None,
+            textures: Textures::default(),
+        }
+    }
+
+    /// Returns a material scattering light only by a BSDF, see `bsdf::Bsdf`
+    pub fn from_bsdf(bsdf: SharedBsdf) -> Self {
+        Material {
+            bsdf: Some(bsdf),
+            ..Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::white())
+        }
+    }
+
+    /// Returns a material scattering light only by a microfacet surface
+    pub fn microfacet(microfacet: Microfacet) -> Self {
+        Material::from_bsdf(Arc::new(microfacet))
+    }
+
+    /// Returns a metal by name, one of gold, copper, aluminium or silver
+    pub fn metal(name: &str, roughness: f32) -> Result<Self> {
+        Ok(Material::microfacet(Microfacet::metal(name, roughness)?))
+    }
+
+    /// Returns a metal of a complex index of refraction, given for red, green and blue light
+    pub fn conductor(eta: Color, k: Color, roughness: f32) -> Self {
+        Material::microfacet(Microfacet::Conductor { eta, k, roughness })
+    }
+
+    /// Returns a transparent material such as glass, frosted by its roughness
+    pub fn dielectric(ior: f32, roughness: f32) -> Self {
+        Material::microfacet(Microfacet::Dielectric { ior, roughness })
+    }
+
+    /// Returns a material following the principled model
+    ///
+    /// The color of the material is the base color, so that vertex colors of meshes and point
+    /// clouds apply as with other materials.
+    pub fn principled(principled: Principled) -> Self {
+        Material {
+            color: principled.base_color,
+            ..Material::from_bsdf(Arc::new(principled))
         }
     }
+
+    /// Returns the material with its properties varied by textures
+    pub fn textured(self, textures: Textures) -> Self {
+        Material { textures, ..self }
+    }
 }
 
-#[pyclass]
+#[cfg_attr(feature = "python", pyclass)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Ray {
     pub base: Point,
     pub direction: Point,
+    /// The moment the ray was cast at, used to place moving objects
+    pub time: f32,
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Ray {
     #[new]
+    fn py_new(base: Point, direction: Point) -> Ray {
+        Ray::new(base, direction)
+    }
+}
+
+impl Ray {
     pub fn new(base: Point, direction: Point) -> Ray {
         Ray {
             base,
             direction: direction / direction.norm(),
+            time: 0.0,
+        }
+    }
+
+    /// Returns the same ray cast at a different time
+    pub fn with_time(self, time: f32) -> Ray {
+        Ray { time, ..self }
+    }
+
+    /// Returns a new ray starting at `base` cast at the same time as this one
+    ///
+    /// The base is nudged along the direction, so that the new ray does not hit the surface it
+    /// starts from again due to rounding errors.
+    pub fn spawn(&self, base: Point, direction: Point) -> Ray {
+        let ray = Ray::new(base, direction).with_time(self.time);
+        Ray {
+            base: ray.at(BIAS),
+            ..ray
         }
     }
-}
 
-impl Ray {
     /// Returns a new point along the ray at distance `d` from the base
     pub fn at(&self, d: f32) -> Point {
         self.base + d * self.direction
     }
 
-    pub fn intersect(
-        &self,
-        things: &[Box<dyn Thing + Sync>],
-        skip: Option<usize>,
-    ) -> Option<(f32, usize)> {
+    pub fn intersect(&self, things: &[SharedThing], skip: Option<usize>) -> Option<(Hit, usize)> {
         things.iter().enumerate().fold(None, |min, (n, e)| {
             if skip.is_some() && skip.unwrap() == n {
                 return min;
             }
-            let hit = e.hit_by(&self);
+            let hit = e.hit_by(self);
             match hit {
                 None => min,
-                Some(d) => match min {
-                    None => Some((d, n)),
-                    Some(m) => {
-                        if m.0 < d {
+                Some(h) => match min {
+                    None => Some((h, n)),
+                    Some(ref m) => {
+                        if m.0.distance < h.distance {
                             min
                         } else {
-                            Some((d, n))
+                            Some((h, n))
                         }
                     }
                 },
@@ -308,23 +549,169 @@ impl Ray {
     }
 }
 
+/// Where and on what kind of surface a ray hits a thing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// The distance from the base of the ray
+    pub distance: f32,
+    /// The surface normal at the point of impact
+    pub normal: Point,
+    /// The material of the surface
+    pub material: Material,
+    /// Coordinates on the surface, usually between zero and one
+    pub uv: (f32, f32),
+    /// The direction of a fiber at the point of impact, scattering light like hair
+    pub tangent: Option<Point>,
+}
+
+impl Hit {
+    pub fn new(distance: f32, normal: Point, material: Material) -> Hit {
+        Hit {
+            distance,
+            normal,
+            material,
+            uv: (0.0, 0.0),
+            tangent: None,
+        }
+    }
+
+    /// Returns the same hit with different surface coordinates
+    pub fn with_uv(self, u: f32, v: f32) -> Hit {
+        Hit { uv: (u, v), ..self }
+    }
+
+    /// Returns the same hit on a fiber running along `tangent`
+    pub fn with_tangent(self, tangent: Point) -> Hit {
+        Hit {
+            tangent: Some(tangent),
+            ..self
+        }
+    }
+}
+
 pub trait Thing {
-    fn hit_by(&self, ray: &Ray) -> Option<f32>;
-    fn material(&self) -> Material;
-    fn normal(&self, position: &Point, direction: &Point) -> Point;
+    /// Returns the closest intersection with a ray, if any
+    fn hit_by(&self, ray: &Ray) -> Option<Hit>;
+    /// Returns a box enclosing the thing
+    fn bounds(&self) -> Bounds;
 }
 
-#[pyclass]
+/// A thing that can be shared between several owners, e.g. Python objects and a scene
+pub type SharedThing = Arc<dyn Thing + Send + Sync>;
+
+/// Returns the values of a buffer of 32 or 64 bit floats, e.g. of a numpy array
+#[cfg(feature = "python")]
+pub fn to_floats(py: Python, buffer: &pyo3::buffer::PyBuffer) -> PyResult<Vec<f64>> {
+    if buffer.item_size() == 4 {
+        Ok(buffer
+            .to_vec::<f32>(py)?
+            .into_iter()
+            .map(f64::from)
+            .collect())
+    } else {
+        buffer.to_vec::<f64>(py)
+    }
+}
+
+/// Converts a Python object into a thing that can be rendered
+#[cfg(feature = "python")]
+pub fn to_thing(obj: &PyAny) -> PyResult<SharedThing> {
+    use crate::csg::*;
+    use crate::curves::*;
+    use crate::graph::*;
+    use crate::heightfield::*;
+    use crate::instance::*;
+    use crate::mesh::*;
+    use crate::metaballs::*;
+    use crate::pointcloud::*;
+    use crate::quadric::*;
+    use crate::sdf::*;
+    use crate::shapes::*;
+
+    if let Ok(r) = obj.extract::<Rhomboid>() {
+        return Ok(Arc::new(r));
+    }
+    if let Ok(s) = obj.extract::<Sphere>() {
+        return Ok(Arc::new(s));
+    }
+    if let Ok(t) = obj.extract::<Triangle>() {
+        return Ok(Arc::new(t));
+    }
+    if let Ok(m) = obj.extract::<Mesh>() {
+        return Ok(Arc::new(m));
+    }
+    if let Ok(p) = obj.extract::<Plane>() {
+        return Ok(Arc::new(p));
+    }
+    if let Ok(d) = obj.extract::<Disk>() {
+        return Ok(Arc::new(d));
+    }
+    if let Ok(c) = obj.extract::<Cylinder>() {
+        return Ok(Arc::new(c));
+    }
+    if let Ok(c) = obj.extract::<Cone>() {
+        return Ok(Arc::new(c));
+    }
+    if let Ok(t) = obj.extract::<Torus>() {
+        return Ok(Arc::new(t));
+    }
+    if let Ok(c) = obj.extract::<Cuboid>() {
+        return Ok(Arc::new(c));
+    }
+    if let Ok(q) = obj.extract::<Quadric>() {
+        return Ok(Arc::new(q));
+    }
+    if let Ok(c) = obj.extract::<Csg>() {
+        return Ok(Arc::new(c));
+    }
+    if let Ok(m) = obj.extract::<Marched>() {
+        return Ok(Arc::new(m));
+    }
+    if let Ok(m) = obj.extract::<Metaballs>() {
+        return Ok(Arc::new(m));
+    }
+    if let Ok(c) = obj.extract::<Curves>() {
+        return Ok(Arc::new(c));
+    }
+    if let Ok(p) = obj.extract::<PointCloud>() {
+        return Ok(Arc::new(p));
+    }
+    if let Ok(h) = obj.extract::<Heightfield>() {
+        return Ok(Arc::new(h));
+    }
+    if let Ok(m) = obj.extract::<Motion>() {
+        return Ok(Arc::new(m));
+    }
+    if let Ok(g) = obj.extract::<Geometry>() {
+        return Ok(g.shared());
+    }
+    if let Ok(i) = obj.extract::<Instance>() {
+        return Ok(Arc::new(i));
+    }
+    if let Ok(g) = obj.extract::<Group>() {
+        return Ok(Geometry::from_things(g.flatten()).shared());
+    }
+    Err(exceptions::TypeError::py_err("object cannot be rendered"))
+}
+
+#[cfg_attr(feature = "python", pyclass)]
 #[derive(Clone)]
 pub struct Sphere {
-    center: Point,
-    radius: f32,
-    material: Material,
+    pub(crate) center: Point,
+    pub(crate) radius: f32,
+    pub(crate) material: Material,
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Sphere {
     #[new]
+    fn py_new(center: Point, radius: f32, material: Material) -> Self {
+        Sphere::new(center, radius, material)
+    }
+}
+
+impl Sphere {
     pub fn new(center: Point, radius: f32, material: Material) -> Sphere {
         Sphere {
             center,
@@ -332,10 +719,8 @@ impl Sphere {
             material,
         }
     }
-}
 
-impl Thing for Sphere {
-    fn hit_by(&self, ray: &Ray) -> Option<f32> {
+    fn distance(&self, ray: &Ray) -> Option<f32> {
         let hypo = self.center - ray.base;
         let dot = ray.direction * hypo;
         let root = dot * dot - hypo.norm_sqr() + self.radius * self.radius;
@@ -353,68 +738,52 @@ impl Thing for Sphere {
         None
     }
 
-    fn material(&self) -> Material {
-        self.material
-    }
-
-    fn normal(&self, point: &Point, _direction: &Point) -> Point {
+    fn normal(&self, point: &Point) -> Point {
         let dist = *point - self.center;
         dist / dist.norm()
     }
 }
 
-#[pyclass]
+impl Thing for Sphere {
+    /// Returns a hit with the longitude and latitude around the z axis as surface coordinates
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let d = self.distance(ray)?;
+        let n = self.normal(&ray.at(d));
+        let u = 0.5 + n.y.atan2(n.x) / (2.0 * std::f32::consts::PI);
+        let v = n.z.clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+        Some(Hit::new(d, n, self.material.clone()).with_uv(u, v))
+    }
+
+    fn bounds(&self) -> Bounds {
+        let r = Point::new(self.radius, self.radius, self.radius);
+        Bounds::new(self.center - r, self.center + r)
+    }
+}
+
+#[cfg_attr(feature = "python", pyclass)]
 #[derive(Clone)]
 pub struct Triangle {
-    a: Point,
-    b: Point,
-    c: Point,
-    material: Material,
+    pub(crate) a: Point,
+    pub(crate) b: Point,
+    pub(crate) c: Point,
+    pub(crate) material: Material,
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Triangle {
     #[new]
-    pub fn new(a: Point, b: Point, c: Point, material: Material) -> Triangle {
-        Triangle { a, b, c, material }
+    fn py_new(a: Point, b: Point, c: Point, material: Material) -> Self {
+        Triangle::new(a, b, c, material)
     }
 }
 
-impl Thing for Triangle {
-    /// Determines at which point a ray hits the triangle
-    ///
-    /// Basic implementation of the Möller-Trumbore algorithm.
-    fn hit_by(&self, ray: &Ray) -> Option<f32> {
-        let x = self.b - self.a;
-        let y = self.c - self.a;
-        let h = ray.direction.cross(y);
-        let a = x * h;
-        if a.abs() < EPSILON {
-            return None; // parallel
-        }
-        let s = ray.base - self.a;
-        let u = s * h / a;
-        if u < 0.0 || u > 1.0 {
-            return None;
-        }
-        let q = s.cross(x);
-        let v = ray.direction * q / a;
-        if v < 0.0 || u + v > 1.0 {
-            return None;
-        }
-        let t = y * q / a;
-        if t > EPSILON {
-            Some(t)
-        } else {
-            None
-        }
-    }
-
-    fn material(&self) -> Material {
-        self.material
+impl Triangle {
+    pub fn new(a: Point, b: Point, c: Point, material: Material) -> Triangle {
+        Triangle { a, b, c, material }
     }
 
-    fn normal(&self, _point: &Point, direction: &Point) -> Point {
+    fn normal(&self, direction: &Point) -> Point {
         let x = self.b - self.a;
         let y = self.c - self.a;
         let n = y.cross(x).normalized();
@@ -426,21 +795,70 @@ impl Thing for Triangle {
     }
 }
 
-#[pyclass]
+impl Thing for Triangle {
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        let (d, u, v) = triangle_distance(self.a, self.b, self.c, ray)?;
+        Some(Hit::new(d, self.normal(&ray.direction), self.material.clone()).with_uv(u, v))
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::around(&[self.a, self.b, self.c])
+    }
+}
+
+/// Determines at which point a ray hits the triangle between three corners
+///
+/// Basic implementation of the Möller-Trumbore algorithm, returning the distance and the
+/// barycentric coordinates of the hit, those of `b` and `c`.  Surfaces made of many triangles
+/// use it to find the closest one before building a `Hit` for it alone.
+pub fn triangle_distance(a: Point, b: Point, c: Point, ray: &Ray) -> Option<(f32, f32, f32)> {
+    let x = b - a;
+    let y = c - a;
+    let h = ray.direction.cross(y);
+    let det = x * h;
+    if det.abs() < EPSILON {
+        return None; // parallel
+    }
+    let s = ray.base - a;
+    let u = s * h / det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+    let q = s.cross(x);
+    let v = ray.direction * q / det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = y * q / det;
+    if t > EPSILON {
+        Some((t, u, v))
+    } else {
+        None
+    }
+}
+
+#[cfg_attr(feature = "python", pyclass)]
 #[derive(Clone)]
 pub struct Rhomboid {
-    base: Point,
-    x: Point,
-    y: Point,
+    pub(crate) base: Point,
+    pub(crate) x: Point,
+    pub(crate) y: Point,
     n: Point,
-    width: f32,
-    height: f32,
-    material: Material,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+    pub(crate) material: Material,
 }
 
+#[cfg(feature = "python")]
 #[pymethods]
 impl Rhomboid {
     #[new]
+    fn py_new(base: Point, x: Point, y: Point, material: Material) -> Self {
+        Rhomboid::new(base, x, y, material)
+    }
+}
+
+impl Rhomboid {
     pub fn new(base: Point, x: Point, y: Point, material: Material) -> Rhomboid {
         let normal = x.cross(y).normalized();
         let width = x.norm();
@@ -458,9 +876,9 @@ impl Rhomboid {
 }
 
 impl Thing for Rhomboid {
-    fn hit_by(&self, ray: &Ray) -> Option<f32> {
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
         let conn = self.base - ray.base;
-        let norm = self.normal(&ORIGIN, &ray.direction);
+        let norm = self.n;
         let t = conn * norm / (ray.direction * norm);
         if t < 0.0 {
             return None;
@@ -469,18 +887,138 @@ impl Thing for Rhomboid {
         let along_x = self.x * in_plane;
         let along_y = self.y * in_plane;
         if (0.0..=self.width).contains(&along_x) && (0.0..=self.height).contains(&along_y) {
-            Some(t)
+            let uv = (along_x / self.width, along_y / self.height);
+            Some(Hit::new(t, self.n, self.material.clone()).with_uv(uv.0, uv.1))
         } else {
             None
         }
     }
 
-    fn material(&self) -> Material {
-        self.material
+    fn bounds(&self) -> Bounds {
+        let x = self.width * self.x;
+        let y = self.height * self.y;
+        Bounds::around(&[self.base, self.base + x, self.base + y, self.base + x + y])
+    }
+}
+
+/// The displacement of a moving object over time
+#[derive(Clone)]
+enum Path {
+    /// Moves with a constant velocity, starting at the origin at time zero, while the shutter
+    /// is open and holding still before and after
+    Linear { velocity: Point, span: (f32, f32) },
+    /// Interpolates linearly between offsets at the given times, sorted by time
+    Keyframes(Vec<(f32, Point)>),
+}
+
+impl Path {
+    /// Returns the offset from the resting position at a given time
+    fn offset(&self, time: f32) -> Point {
+        match self {
+            Path::Linear { velocity, span } => time.max(span.0).min(span.1) * *velocity,
+            Path::Keyframes(frames) => {
+                let next = frames.iter().position(|(t, _)| *t > time);
+                match next {
+                    Some(0) => frames[0].1,
+                    Some(n) => {
+                        let (t0, p0) = frames[n - 1];
+                        let (t1, p1) = frames[n];
+                        let f = (time - t0) / (t1 - t0);
+                        (1.0 - f) * p0 + f * p1
+                    }
+                    None => frames.last().map_or(ORIGIN, |(_, p)| *p),
+                }
+            }
+        }
+    }
+}
+
+/// Wraps a thing to move it over time
+///
+/// Rays are shifted by the offset of the object at the time they were cast, so that objects
+/// blur when the camera shutter stays open while they move.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Clone)]
+pub struct Motion {
+    thing: SharedThing,
+    path: Path,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Motion {
+    /// Moves a thing linearly while the shutter is open, with the offset `velocity * time`
+    #[new]
+    fn py_new(thing: &PyAny, velocity: Point, shutter: Shutter) -> PyResult<Self> {
+        Ok(Motion::linear(to_thing(thing)?, velocity, &shutter))
+    }
+
+    /// Moves a thing along keyframed offsets, holding still before the first and after the last
+    #[staticmethod]
+    fn keyframed(thing: &PyAny, times: Vec<f32>, offsets: Vec<Point>) -> PyResult<Self> {
+        Ok(Motion::along(to_thing(thing)?, times, offsets)?)
+    }
+}
+
+impl Motion {
+    /// Returns a thing moving linearly while the shutter is open, with the offset
+    /// `velocity * time`
+    pub fn linear(thing: SharedThing, velocity: Point, shutter: &Shutter) -> Self {
+        Motion {
+            thing,
+            path: Path::Linear {
+                velocity,
+                span: (shutter.open, shutter.close),
+            },
+        }
+    }
+
+    /// Returns a thing moving along keyframed offsets
+    ///
+    /// # Arguments
+    ///
+    /// * `thing` - the object to move
+    /// * `times` - the times of the keyframes
+    /// * `offsets` - the displacement of the object at each keyframe
+    pub fn along(thing: SharedThing, times: Vec<f32>, offsets: Vec<Point>) -> Result<Self> {
+        if times.is_empty() || times.len() != offsets.len() {
+            return Err(Error::Value(
+                "need the same, non-zero number of times and offsets".into(),
+            ));
+        }
+        let mut frames: Vec<(f32, Point)> = times.into_iter().zip(offsets).collect();
+        frames.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(Motion {
+            thing,
+            path: Path::Keyframes(frames),
+        })
+    }
+
+    /// Returns the ray relative to the resting position of the moving object
+    fn shifted(&self, ray: &Ray) -> Ray {
+        Ray {
+            base: ray.base - self.path.offset(ray.time),
+            ..*ray
+        }
+    }
+}
+
+impl Thing for Motion {
+    fn hit_by(&self, ray: &Ray) -> Option<Hit> {
+        self.thing.hit_by(&self.shifted(ray))
     }
 
-    fn normal(&self, _point: &Point, _direction: &Point) -> Point {
-        self.n
+    /// Returns bounds covering the thing at the ends of its linear motion, or at all keyframes
+    fn bounds(&self) -> Bounds {
+        let b = self.thing.bounds();
+        let times = match &self.path {
+            Path::Linear { span, .. } => vec![span.0, span.1],
+            Path::Keyframes(frames) => frames.iter().map(|(t, _)| *t).collect(),
+        };
+        times.into_iter().fold(Bounds::empty(), |all, time| {
+            let p = self.path.offset(time);
+            all.union(Bounds::new(b.min + p, b.max + p))
+        })
     }
 }
 
@@ -493,9 +1031,9 @@ mod tests {
         let p = Point::new(1.0, 2.0, 3.0);
         let q = Point::new(0.0, 1.0, 2.0);
 
-        assert_eq!(p.get_x().unwrap(), 1.0);
-        assert_eq!(p.get_y().unwrap(), 2.0);
-        assert_eq!(p.get_z().unwrap(), 3.0);
+        assert_eq!(p.x, 1.0);
+        assert_eq!(p.y, 2.0);
+        assert_eq!(p.z, 3.0);
 
         assert_eq!(p.norm_sqr(), 14.0);
 
@@ -566,32 +1104,32 @@ mod tests {
         let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
         let r = Ray::new(Point::new(0.0, 0.0, 0.0), Point::new(1.0, 0.0, 0.0));
 
-        let s = Sphere::new(Point::new(1.0, 0.0, 0.0), 0.5, m);
-        assert_eq!(s.hit_by(&r), Some(0.5));
-        let n = s.normal(&r.at(0.5), &r.direction);
+        let s = Sphere::new(Point::new(1.0, 0.0, 0.0), 0.5, m.clone());
+        assert_eq!(s.hit_by(&r).map(|h| h.distance), Some(0.5));
+        let n = s.hit_by(&r).unwrap().normal;
         assert_eq!(n, Point::new(-1.0, 0.0, 0.0));
 
         // Inside sphere
-        let s = Sphere::new(Point::new(0.0, 0.0, 0.0), 0.5, m);
-        assert_eq!(s.hit_by(&r), Some(0.5));
-        let n = s.normal(&r.at(0.5), &r.direction);
+        let s = Sphere::new(Point::new(0.0, 0.0, 0.0), 0.5, m.clone());
+        assert_eq!(s.hit_by(&r).map(|h| h.distance), Some(0.5));
+        let n = s.hit_by(&r).unwrap().normal;
         assert_eq!(n, Point::new(1.0, 0.0, 0.0));
 
         let r = Ray::new(Point::new(0.0, 0.5, 0.0), Point::new(1.0, 0.0, 0.0));
         let s = Sphere::new(Point::new(1.0, 0.0, 0.0), 0.5, m);
-        assert_eq!(s.hit_by(&r), Some(1.0));
+        assert_eq!(s.hit_by(&r).map(|h| h.distance), Some(1.0));
     }
 
     #[test]
     fn ray_misses_sphere() {
         let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
         let r = Ray::new(Point::new(0.0, 0.0, 0.0), Point::new(1.0, 0.0, 0.0));
-        let s = Sphere::new(Point::new(-1.0, 0.0, 0.0), 0.5, m);
-        assert_eq!(s.hit_by(&r), None);
+        let s = Sphere::new(Point::new(-1.0, 0.0, 0.0), 0.5, m.clone());
+        assert_eq!(s.hit_by(&r).map(|h| h.distance), None);
 
         let r = Ray::new(Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 0.0));
         let s = Sphere::new(Point::new(1.0, 0.0, 0.0), 0.5, m);
-        assert_eq!(s.hit_by(&r), None);
+        assert_eq!(s.hit_by(&r).map(|h| h.distance), None);
     }
 
     #[test]
@@ -599,12 +1137,12 @@ mod tests {
         let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
         let r = Ray::new(-UNIT_X, UNIT_X);
         let a = Point::new(5.0, -1.0, -1.0);
-        let t = Triangle::new(a, a + 2.0 * UNIT_Y, a + 2.0 * UNIT_Z, m);
-        assert_eq!(t.hit_by(&r), Some(6.0));
+        let t = Triangle::new(a, a + 2.0 * UNIT_Y, a + 2.0 * UNIT_Z, m.clone());
+        assert_eq!(t.hit_by(&r).map(|h| h.distance), Some(6.0));
 
         let a = Point::new(4.0, -0.1, -0.1);
         let t = Triangle::new(a, a + 2.0 * UNIT_Y, a + 2.0 * UNIT_Z, m);
-        assert_eq!(t.hit_by(&r), Some(5.0));
+        assert_eq!(t.hit_by(&r).map(|h| h.distance), Some(5.0));
     }
 
     #[test]
@@ -612,31 +1150,46 @@ mod tests {
         let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
         let r = Ray::new(-UNIT_X, UNIT_X);
         let a = Point::new(5.0, -1.9, -1.9);
-        let t = Triangle::new(a, a + 2.0 * UNIT_Y, a + 2.0 * UNIT_Z, m);
-        assert_eq!(t.hit_by(&r), None);
+        let t = Triangle::new(a, a + 2.0 * UNIT_Y, a + 2.0 * UNIT_Z, m.clone());
+        assert_eq!(t.hit_by(&r).map(|h| h.distance), None);
 
         let r = Ray::new(-UNIT_X, Point::new(1.0, 0.1, 0.1));
         let a = Point::new(5.0, -1.0, -1.0);
         let t = Triangle::new(a, a + 2.0 * UNIT_Y, a + 2.0 * UNIT_Z, m);
-        assert_eq!(t.hit_by(&r), None);
+        assert_eq!(t.hit_by(&r).map(|h| h.distance), None);
     }
 
     #[test]
     fn ray_hits_rectangle() {
         let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
         let r = Ray::new(-UNIT_X, UNIT_X);
-        let r2 = Rhomboid::new(Point::new(5.0, -1.0, -1.0), 2.0 * UNIT_Y, 2.0 * UNIT_Z, m);
-        assert_eq!(r2.hit_by(&r), Some(6.0));
-
-        let r2 = Rhomboid::new(Point::new(4.0, -0.1, -0.1), 2.0 * UNIT_Y, 2.0 * UNIT_Z, m);
-        assert_eq!(r2.hit_by(&r), Some(5.0));
-
-        let r2 = Rhomboid::new(Point::new(5.0, -1.9, -1.9), 2.0 * UNIT_Y, 2.0 * UNIT_Z, m);
-        assert_eq!(r2.hit_by(&r), Some(6.0));
+        let r2 = Rhomboid::new(
+            Point::new(5.0, -1.0, -1.0),
+            2.0 * UNIT_Y,
+            2.0 * UNIT_Z,
+            m.clone(),
+        );
+        assert_eq!(r2.hit_by(&r).map(|h| h.distance), Some(6.0));
+
+        let r2 = Rhomboid::new(
+            Point::new(4.0, -0.1, -0.1),
+            2.0 * UNIT_Y,
+            2.0 * UNIT_Z,
+            m.clone(),
+        );
+        assert_eq!(r2.hit_by(&r).map(|h| h.distance), Some(5.0));
+
+        let r2 = Rhomboid::new(
+            Point::new(5.0, -1.9, -1.9),
+            2.0 * UNIT_Y,
+            2.0 * UNIT_Z,
+            m.clone(),
+        );
+        assert_eq!(r2.hit_by(&r).map(|h| h.distance), Some(6.0));
 
         let r = Ray::new(-UNIT_X, Point::new(1.0, 0.1, 0.1));
         let r2 = Rhomboid::new(Point::new(5.0, -1.0, -1.0), 2.0 * UNIT_Y, 2.0 * UNIT_Z, m);
-        let t = r2.hit_by(&r).unwrap();
+        let t = r2.hit_by(&r).unwrap().distance;
         assert!(t > 6.0);
         assert_eq!(r.at(t).x, 5.0);
     }
@@ -645,16 +1198,26 @@ mod tests {
     fn ray_misses_rectangle() {
         let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
         let r = Ray::new(ORIGIN, -UNIT_X);
-        let r2 = Rhomboid::new(Point::new(5.0, -1.0, -1.0), 2.0 * UNIT_Y, 2.0 * UNIT_Z, m);
-        assert_eq!(r2.hit_by(&r), None);
+        let r2 = Rhomboid::new(
+            Point::new(5.0, -1.0, -1.0),
+            2.0 * UNIT_Y,
+            2.0 * UNIT_Z,
+            m.clone(),
+        );
+        assert_eq!(r2.hit_by(&r).map(|h| h.distance), None);
 
         let r = Ray::new(ORIGIN, UNIT_X);
-        let r2 = Rhomboid::new(Point::new(5.0, 1.0, 1.0), 2.0 * UNIT_Y, 2.0 * UNIT_Z, m);
-        assert_eq!(r2.hit_by(&r), None);
+        let r2 = Rhomboid::new(
+            Point::new(5.0, 1.0, 1.0),
+            2.0 * UNIT_Y,
+            2.0 * UNIT_Z,
+            m.clone(),
+        );
+        assert_eq!(r2.hit_by(&r).map(|h| h.distance), None);
 
         let r = Ray::new(ORIGIN, UNIT_Z);
         let r2 = Rhomboid::new(Point::new(5.0, 1.0, 1.0), 2.0 * UNIT_Y, 2.0 * UNIT_Z, m);
-        assert_eq!(r2.hit_by(&r), None);
+        assert_eq!(r2.hit_by(&r).map(|h| h.distance), None);
     }
 
     #[test]
@@ -662,24 +1225,60 @@ mod tests {
         let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
         let r = Ray::new(ORIGIN, UNIT_Z);
 
-        let things: Vec<Box<dyn Thing + Sync + 'static>> = vec![
-            Box::new(Sphere::new(Point::new(0.0, 0.0, 1.0), 0.5, m)),
-            Box::new(Sphere::new(Point::new(0.0, 0.0, 3.0), 0.5, m)),
-            Box::new(Sphere::new(Point::new(0.0, 0.0, 2.0), 0.5, m)),
+        let things: Vec<SharedThing> = vec![
+            Arc::new(Sphere::new(Point::new(0.0, 0.0, 1.0), 0.5, m.clone())),
+            Arc::new(Sphere::new(Point::new(0.0, 0.0, 3.0), 0.5, m.clone())),
+            Arc::new(Sphere::new(Point::new(0.0, 0.0, 2.0), 0.5, m)),
         ];
 
         let res = r.intersect(&things[..], Some(0));
         assert!(res.is_some());
-        let (dist, item) = res.unwrap();
-        assert_eq!(dist, 1.5);
+        let (hit, item) = res.unwrap();
+        assert_eq!(hit.distance, 1.5);
         assert_eq!(item, 2);
     }
 
+    #[test]
+    fn ray_hits_moving_sphere() {
+        let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
+        let r = Ray::new(ORIGIN, UNIT_X);
+        let s = Arc::new(Sphere::new(Point::new(2.0, 0.0, 0.0), 0.5, m));
+
+        let shutter = Shutter::new(-0.25, 1.0).unwrap();
+        let moving = Motion::linear(s.clone(), UNIT_Y, &shutter);
+        assert_eq!(moving.hit_by(&r).map(|h| h.distance), Some(1.5));
+        assert_eq!(moving.hit_by(&r.with_time(1.0)).map(|h| h.distance), None);
+        assert!(moving.hit_by(&r.with_time(-0.25)).is_some());
+        // The sphere holds still after the shutter closes, within its bounds
+        let b = moving.bounds();
+        assert_eq!((b.min.y, b.max.y), (-0.75, 1.5));
+        let above = Ray::new(Point::new(2.0, 1.0, 5.0), -UNIT_Z);
+        assert!(moving.hit_by(&above.with_time(3.0)).is_some());
+
+        let times = vec![1.0, 0.0, 2.0];
+        let offsets = vec![UNIT_Y, ORIGIN, ORIGIN];
+        let moving = Motion::along(s, times, offsets).unwrap();
+        assert_eq!(
+            moving.hit_by(&r.with_time(-1.0)).map(|h| h.distance),
+            Some(1.5)
+        );
+        assert_eq!(moving.hit_by(&r.with_time(1.0)).map(|h| h.distance), None);
+        assert_eq!(
+            moving.hit_by(&r.with_time(3.0)).map(|h| h.distance),
+            Some(1.5)
+        );
+
+        let r = r.with_time(0.25);
+        let n = moving.hit_by(&r).unwrap().normal;
+        assert!((n.norm() - 1.0).abs() < 1.0e-6);
+        assert!((n.y + 0.5).abs() < 1.0e-6);
+    }
+
     #[test]
     fn normal_for_rectangle() {
         let m = Material::new(0.0, 0.0, 0.0, 0.0, 0.0, Color::black());
         let r = Rhomboid::new(ORIGIN, 1.0 * UNIT_Y, 1.0 * UNIT_Z, m);
-        let n = r.normal(&ORIGIN, &UNIT_X);
+        let n = r.hit_by(&Ray::new(-UNIT_X, UNIT_X)).unwrap().normal;
         assert_eq!(n, UNIT_X);
     }
 
@@ -688,9 +1287,9 @@ mod tests {
         let c = Color::new(1.0, 2.0, 3.0);
         let d = Color::new(1.0, 0.0, 3.0);
 
-        assert_eq!(c.get_r().unwrap(), 1.0);
-        assert_eq!(c.get_g().unwrap(), 2.0);
-        assert_eq!(c.get_b().unwrap(), 3.0);
+        assert_eq!(c.r, 1.0);
+        assert_eq!(c.g, 2.0);
+        assert_eq!(c.b, 3.0);
 
         assert_eq!(c * d, Color::new(1.0, 0.0, 9.0));
     }